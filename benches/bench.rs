@@ -1,25 +1,49 @@
+use std::fmt::Debug;
 use std::iter;
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use quadtree::{Point2D, QuadTree, QuadTreeOption, Rectangle};
-use rand::Rng;
+use quadtree::testutil::{clustered_points, query_regions, uniform_points};
+use quadtree::{
+    AggregateQuadTree, BroadPhase, Count, IndexQuadTree, KdTree, LinearQuadTree, Point2D, QuadTree,
+    Rectangle, SparseQuadTree, SpatialIndex,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-fn create_rootleaf_tree(elements: &[Point2D<u8>]) -> QuadTree<u8> {
-    let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+fn create_tree<T: Copy + Debug, I: SpatialIndex<T>>(elements: &[Point2D<T>]) -> I {
+    let mut quadtree = I::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
     for point in elements {
         quadtree.insert(*point).unwrap();
     }
     quadtree
 }
 
-fn create_struct_tree(elements: &[Point2D<u8>]) -> QuadTreeOption<u8> {
-    let mut quadtree = QuadTreeOption::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+fn create_linear_tree<T: Copy + Debug>(elements: &[Point2D<T>]) -> LinearQuadTree<T> {
+    LinearQuadTree::<T>::from_points(Rectangle::new(0.0, 0.0, 100.0, 100.0), elements.to_vec())
+        .unwrap()
+}
+
+// with_capacity_policy takes plain fn pointers, so one per capacity
+type CapacityPolicy = fn(usize) -> usize;
+
+const CAPACITIES: [(usize, CapacityPolicy); 4] =
+    [(2, |_| 2), (4, |_| 4), (16, |_| 16), (64, |_| 64)];
+
+fn create_sparse_tree<T: Copy + Debug>(
+    elements: &[Point2D<T>],
+    capacity: CapacityPolicy,
+) -> SparseQuadTree<T> {
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut quadtree = SparseQuadTree::with_capacity_policy(boundary, capacity);
     for point in elements {
         quadtree.insert(*point).unwrap();
     }
     quadtree
 }
 
+fn create_kd_tree<T: Copy + Debug>(elements: &[Point2D<T>]) -> KdTree<T> {
+    KdTree::<T>::from_points(Rectangle::new(0.0, 0.0, 100.0, 100.0), elements.to_vec()).unwrap()
+}
+
 fn insert_nodes(c: &mut Criterion) {
     static KB: usize = 1024;
 
@@ -36,16 +60,25 @@ fn insert_nodes(c: &mut Criterion) {
 
         group.throughput(Throughput::Bytes(*size as u64));
         group.bench_with_input(BenchmarkId::new("Leaf+Root", size), size, |b, _i| {
-            b.iter(|| create_rootleaf_tree(&points))
+            b.iter(|| create_tree::<u8, QuadTree<u8>>(&points))
         });
         group.bench_with_input(BenchmarkId::new("Common Structs", size), size, |b, _i| {
-            b.iter(|| create_struct_tree(&points))
+            b.iter(|| create_tree::<u8, SparseQuadTree<u8>>(&points))
+        });
+        group.bench_with_input(BenchmarkId::new("Linear", size), size, |b, _i| {
+            b.iter(|| create_linear_tree(&points))
+        });
+        group.bench_with_input(BenchmarkId::new("KdTree", size), size, |b, _i| {
+            b.iter(|| create_tree::<u8, KdTree<u8>>(&points))
+        });
+        group.bench_with_input(BenchmarkId::new("KdTree bulk", size), size, |b, _i| {
+            b.iter(|| create_kd_tree(&points))
         });
     }
     group.finish();
 }
 
-fn query_tree_leaf_root(quadtree: &QuadTree<u8>, regions: &[Rectangle]) -> usize {
+fn query_tree<T: Debug, I: SpatialIndex<T>>(quadtree: &I, regions: &[Rectangle]) -> usize {
     let mut sum = 0;
     for region in regions {
         sum += quadtree.query(*region).len();
@@ -53,12 +86,8 @@ fn query_tree_leaf_root(quadtree: &QuadTree<u8>, regions: &[Rectangle]) -> usize
     sum
 }
 
-fn query_tree_struct(quadtree: &QuadTreeOption<u8>, regions: &[Rectangle]) -> usize {
-    let mut sum = 0;
-    for region in regions {
-        sum += quadtree.query(*region).len();
-    }
-    sum
+fn query_many_tree<T: Debug, I: SpatialIndex<T>>(quadtree: &I, regions: &[Rectangle]) -> usize {
+    quadtree.query_many(regions).iter().map(|result| result.len()).sum()
 }
 
 fn query_nodes(c: &mut Criterion) {
@@ -81,16 +110,322 @@ fn query_nodes(c: &mut Criterion) {
 
         group.throughput(Throughput::Bytes(*size as u64));
         group.bench_with_input(BenchmarkId::new("Leaf+Root", size), size, |b, _i| {
-            let quadtree = create_rootleaf_tree(&points);
-            b.iter(|| query_tree_leaf_root(&quadtree, &regions))
+            let quadtree = create_tree::<u8, QuadTree<u8>>(&points);
+            b.iter(|| query_tree(&quadtree, &regions))
         });
         group.bench_with_input(BenchmarkId::new("Common Structs", size), size, |b, _i| {
-            let quadtree = create_struct_tree(&points);
-            b.iter(|| query_tree_struct(&quadtree, &regions))
+            let quadtree = create_tree::<u8, SparseQuadTree<u8>>(&points);
+            b.iter(|| query_tree(&quadtree, &regions))
+        });
+        group.bench_with_input(BenchmarkId::new("Linear", size), size, |b, _i| {
+            let quadtree = create_linear_tree(&points);
+            b.iter(|| query_tree(&quadtree, &regions))
         });
+        group.bench_with_input(BenchmarkId::new("KdTree", size), size, |b, _i| {
+            let tree = create_kd_tree(&points);
+            b.iter(|| query_tree(&tree, &regions))
+        });
+        group.bench_with_input(BenchmarkId::new("Leaf+Root batched", size), size, |b, _i| {
+            let quadtree = create_tree::<u8, QuadTree<u8>>(&points);
+            b.iter(|| query_many_tree(&quadtree, &regions))
+        });
+        group.bench_with_input(BenchmarkId::new("Common Structs batched", size), size, |b, _i| {
+            let quadtree = create_tree::<u8, SparseQuadTree<u8>>(&points);
+            b.iter(|| query_many_tree(&quadtree, &regions))
+        });
+    }
+    group.finish();
+}
+
+fn query_selectivity(c: &mut Criterion) {
+    static SIZE: usize = 16 * 1024;
+    static QUERIES: usize = 1024;
+
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut rng = StdRng::seed_from_u64(42);
+    let distributions = [
+        ("uniform", uniform_points(&mut rng, &boundary, SIZE)),
+        ("clustered", clustered_points(&mut rng, &boundary, SIZE, 8, 0.02)),
+    ];
+
+    let mut group = c.benchmark_group("query_selectivity");
+    for (distribution, points) in distributions.iter() {
+        let leaf_root = create_tree::<usize, QuadTree<usize>>(points);
+        let common_structs = create_tree::<usize, SparseQuadTree<usize>>(points);
+        let linear = create_linear_tree(points);
+        let index = IndexQuadTree::index_over(boundary, points).unwrap();
+        let kd_tree = create_kd_tree(points);
+
+        for selectivity in [0.001, 0.01, 0.1] {
+            let regions = query_regions(&mut rng, &boundary, selectivity, QUERIES);
+            let parameter = format!("{} {}%", distribution, selectivity * 100.0);
+
+            group.throughput(Throughput::Elements(QUERIES as u64));
+            group.bench_with_input(BenchmarkId::new("Leaf+Root", &parameter), &regions, |b, r| {
+                b.iter(|| query_tree(&leaf_root, r))
+            });
+            group.bench_with_input(BenchmarkId::new("Common Structs", &parameter), &regions, |b, r| {
+                b.iter(|| query_tree(&common_structs, r))
+            });
+            group.bench_with_input(BenchmarkId::new("Linear", &parameter), &regions, |b, r| {
+                b.iter(|| query_tree(&linear, r))
+            });
+            group.bench_with_input(BenchmarkId::new("Index", &parameter), &regions, |b, r| {
+                b.iter(|| r.iter().map(|region| index.query(points, *region).len()).sum::<usize>())
+            });
+            group.bench_with_input(BenchmarkId::new("KdTree", &parameter), &regions, |b, r| {
+                b.iter(|| query_tree(&kd_tree, r))
+            });
+        }
     }
     group.finish();
 }
 
-criterion_group!(benches, insert_nodes, query_nodes);
+fn update_nodes(c: &mut Criterion) {
+    static SIZE: usize = 16 * 1024;
+    static UPDATES: usize = 4 * 1024;
+
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut rng = StdRng::seed_from_u64(42);
+    let points = uniform_points(&mut rng, &boundary, SIZE);
+    // half of the updates hit stored points, the other half insert new ones
+    let updates: Vec<(f64, f64)> = (0..UPDATES)
+        .map(|i| match i % 2 {
+            0 => (points[i].x, points[i].y),
+            _ => (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)),
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("update_nodes");
+    group.throughput(Throughput::Elements(UPDATES as u64));
+    group.bench_function("Leaf+Root", |b| {
+        b.iter_batched(
+            || create_tree::<usize, QuadTree<usize>>(&points),
+            |mut quadtree| {
+                for (x, y) in updates.iter() {
+                    quadtree.upsert(*x, *y, 1, |data, new| *data += new).unwrap();
+                }
+                quadtree
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("Common Structs", |b| {
+        b.iter_batched(
+            || create_tree::<usize, SparseQuadTree<usize>>(&points),
+            |mut quadtree| {
+                for (x, y) in updates.iter() {
+                    quadtree.upsert(*x, *y, 1, |data, new| *data += new).unwrap();
+                }
+                quadtree
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn capacity_nodes(c: &mut Criterion) {
+    static SIZE: usize = 16 * 1024;
+    static QUERIES: usize = 1024;
+    static UPDATES: usize = 4 * 1024;
+
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut rng = StdRng::seed_from_u64(42);
+    let distributions = [
+        ("uniform", uniform_points(&mut rng, &boundary, SIZE)),
+        ("clustered", clustered_points(&mut rng, &boundary, SIZE, 8, 0.02)),
+    ];
+    let regions = query_regions(&mut rng, &boundary, 0.01, QUERIES);
+    let updates: Vec<(f64, f64)> =
+        distributions[0].1.iter().take(UPDATES).map(|point| (point.x, point.y)).collect();
+
+    let mut group = c.benchmark_group("capacity");
+    for (capacity, policy) in CAPACITIES {
+        group.throughput(Throughput::Elements(SIZE as u64));
+        group.bench_with_input(BenchmarkId::new("insert", capacity), &policy, |b, policy| {
+            b.iter(|| create_sparse_tree(&distributions[0].1, *policy))
+        });
+
+        group.throughput(Throughput::Elements(QUERIES as u64));
+        for (distribution, points) in distributions.iter() {
+            let quadtree = create_sparse_tree(points, policy);
+            let name = format!("query {} 1%", distribution);
+            group.bench_with_input(BenchmarkId::new(name, capacity), &regions, |b, r| {
+                b.iter(|| query_tree(&quadtree, r))
+            });
+        }
+
+        group.throughput(Throughput::Elements(UPDATES as u64));
+        group.bench_with_input(BenchmarkId::new("update", capacity), &policy, |b, policy| {
+            b.iter_batched(
+                || create_sparse_tree(&distributions[0].1, *policy),
+                |mut quadtree| {
+                    for (x, y) in updates.iter() {
+                        quadtree.upsert(*x, *y, 1, |data, new| *data += new).unwrap();
+                    }
+                    quadtree
+                },
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// only AggregateQuadTree and BroadPhase remove single items, the point trees
+// lose points through clear alone
+fn remove_nodes(c: &mut Criterion) {
+    static SIZE: usize = 16 * 1024;
+    static REMOVALS: usize = 4 * 1024;
+
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut rng = StdRng::seed_from_u64(42);
+    let points = uniform_points(&mut rng, &boundary, SIZE);
+    let boxes: Vec<Rectangle> = points
+        .iter()
+        .map(|point| Rectangle::new(point.x.min(99.0), point.y.min(99.0), 1.0, 1.0))
+        .collect();
+
+    let mut group = c.benchmark_group("remove_nodes");
+    group.throughput(Throughput::Elements(REMOVALS as u64));
+    group.bench_function("Aggregate", |b| {
+        b.iter_batched(
+            || {
+                let mut quadtree = AggregateQuadTree::<usize, Count>::new(boundary);
+                for point in points.iter() {
+                    quadtree.insert(*point).unwrap();
+                }
+                quadtree
+            },
+            |mut quadtree| {
+                for point in points.iter().take(REMOVALS) {
+                    quadtree.remove(point.x, point.y).unwrap();
+                }
+                quadtree
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("BroadPhase", |b| {
+        b.iter_batched(
+            || {
+                let mut broadphase = BroadPhase::new(boundary);
+                for (i, aabb) in boxes.iter().enumerate() {
+                    broadphase.insert(*aabb, i).unwrap();
+                }
+                broadphase
+            },
+            |mut broadphase| {
+                for (i, aabb) in boxes.iter().enumerate().take(REMOVALS) {
+                    broadphase.remove(aabb, |&data| data == i).unwrap();
+                }
+                broadphase
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// the same workloads against other crates, run with
+// `cargo bench --features bench-competitors -- competitors`
+#[cfg(feature = "bench-competitors")]
+fn competitors(c: &mut Criterion) {
+    use quadtree::competitors::{KdtreeIndex, KiddoIndex, RStarIndex};
+
+    static SIZE: usize = 16 * 1024;
+    static QUERIES: usize = 1024;
+
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut rng = StdRng::seed_from_u64(42);
+    let points = uniform_points(&mut rng, &boundary, SIZE);
+
+    let mut group = c.benchmark_group("competitors");
+    group.throughput(Throughput::Elements(SIZE as u64));
+    group.bench_function("insert/Leaf+Root", |b| {
+        b.iter(|| create_tree::<usize, QuadTree<usize>>(&points))
+    });
+    group.bench_function("insert/Common Structs", |b| {
+        b.iter(|| create_tree::<usize, SparseQuadTree<usize>>(&points))
+    });
+    group.bench_function("insert/rstar", |b| {
+        b.iter(|| create_tree::<usize, RStarIndex<usize>>(&points))
+    });
+    group.bench_function("insert/kdtree", |b| {
+        b.iter(|| create_tree::<usize, KdtreeIndex<usize>>(&points))
+    });
+    group.bench_function("insert/kiddo", |b| {
+        b.iter(|| create_tree::<usize, KiddoIndex<usize>>(&points))
+    });
+    group.bench_function("bulk_load/Linear", |b| b.iter(|| create_linear_tree(&points)));
+    group.bench_function("bulk_load/rstar", |b| {
+        b.iter(|| RStarIndex::from_points(boundary, points.clone()).unwrap())
+    });
+    group.bench_function("bulk_load/kiddo", |b| {
+        b.iter(|| KiddoIndex::from_points(boundary, points.clone()).unwrap())
+    });
+
+    let leaf_root = create_tree::<usize, QuadTree<usize>>(&points);
+    let common_structs = create_tree::<usize, SparseQuadTree<usize>>(&points);
+    let linear = create_linear_tree(&points);
+    let rstar = RStarIndex::from_points(boundary, points.clone()).unwrap();
+    let kdtree = create_tree::<usize, KdtreeIndex<usize>>(&points);
+    let kiddo = KiddoIndex::from_points(boundary, points.clone()).unwrap();
+
+    let regions = query_regions(&mut rng, &boundary, 0.01, QUERIES);
+    group.throughput(Throughput::Elements(QUERIES as u64));
+    group.bench_function("query/Leaf+Root", |b| b.iter(|| query_tree(&leaf_root, &regions)));
+    group.bench_function("query/Common Structs", |b| {
+        b.iter(|| query_tree(&common_structs, &regions))
+    });
+    group.bench_function("query/Linear", |b| b.iter(|| query_tree(&linear, &regions)));
+    group.bench_function("query/rstar", |b| b.iter(|| query_tree(&rstar, &regions)));
+    group.bench_function("query/kdtree", |b| b.iter(|| query_tree(&kdtree, &regions)));
+    group.bench_function("query/kiddo", |b| b.iter(|| query_tree(&kiddo, &regions)));
+
+    let targets: Vec<(f64, f64)> = (0..QUERIES)
+        .map(|_| (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)))
+        .collect();
+    group.bench_function("k_nearest/Leaf+Root", |b| {
+        b.iter(|| targets.iter().map(|(x, y)| leaf_root.k_nearest(*x, *y, 10).len()).sum::<usize>())
+    });
+    group.bench_function("k_nearest/Common Structs", |b| {
+        b.iter(|| {
+            targets.iter().map(|(x, y)| common_structs.k_nearest(*x, *y, 10).len()).sum::<usize>()
+        })
+    });
+    group.bench_function("k_nearest/rstar", |b| {
+        b.iter(|| targets.iter().map(|(x, y)| rstar.k_nearest(*x, *y, 10).len()).sum::<usize>())
+    });
+    group.bench_function("k_nearest/kdtree", |b| {
+        b.iter(|| targets.iter().map(|(x, y)| kdtree.k_nearest(*x, *y, 10).len()).sum::<usize>())
+    });
+    group.bench_function("k_nearest/kiddo", |b| {
+        b.iter(|| targets.iter().map(|(x, y)| kiddo.k_nearest(*x, *y, 10).len()).sum::<usize>())
+    });
+    group.finish();
+}
+
+#[cfg(not(feature = "bench-competitors"))]
+criterion_group!(
+    benches,
+    insert_nodes,
+    query_nodes,
+    query_selectivity,
+    update_nodes,
+    capacity_nodes,
+    remove_nodes
+);
+#[cfg(feature = "bench-competitors")]
+criterion_group!(
+    benches,
+    insert_nodes,
+    query_nodes,
+    query_selectivity,
+    update_nodes,
+    capacity_nodes,
+    remove_nodes,
+    competitors
+);
 criterion_main!(benches);
@@ -0,0 +1,502 @@
+use std::ops::AddAssign;
+
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+
+/// A summary of the points below a node, kept up to date by
+/// `AggregateQuadTree`. `Default` is the summary of no points.
+pub trait Aggregate<T>: Clone + Default {
+    fn add(&mut self, point: &Point2D<T>);
+    fn merge(&mut self, other: &Self);
+}
+
+/// Number of points.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Count(pub usize);
+
+impl<T> Aggregate<T> for Count {
+    fn add(&mut self, _point: &Point2D<T>) {
+        self.0 += 1;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 += other.0;
+    }
+}
+
+/// Sum of the payloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sum<T>(pub T);
+
+impl<T: Copy + Default + AddAssign> Aggregate<T> for Sum<T> {
+    fn add(&mut self, point: &Point2D<T>) {
+        self.0 += point.data;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 += other.0;
+    }
+}
+
+/// Smallest and largest payload, `None` without points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinMax<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+impl<T> Default for MinMax<T> {
+    fn default() -> Self {
+        MinMax {
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl<T: Copy + PartialOrd> Aggregate<T> for MinMax<T> {
+    fn add(&mut self, point: &Point2D<T>) {
+        self.merge(&MinMax {
+            min: Some(point.data),
+            max: Some(point.data),
+        });
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if let Some(min) = other.min {
+            if self.min.is_none_or(|current| min < current) {
+                self.min = Some(min);
+            }
+        }
+        if let Some(max) = other.max {
+            if self.max.is_none_or(|current| max > current) {
+                self.max = Some(max);
+            }
+        }
+    }
+}
+
+/// Sum of the coordinates and number of points, for their centroid.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Centroid {
+    pub sum_x: f64,
+    pub sum_y: f64,
+    pub count: usize,
+}
+
+impl Centroid {
+    /// Mean position of the points, `None` without points.
+    pub fn position(&self) -> Option<(f64, f64)> {
+        let count = self.count as f64;
+        (self.count > 0).then(|| (self.sum_x / count, self.sum_y / count))
+    }
+}
+
+impl<T> Aggregate<T> for Centroid {
+    fn add(&mut self, point: &Point2D<T>) {
+        self.sum_x += point.x;
+        self.sum_y += point.y;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.sum_x += other.sum_x;
+        self.sum_y += other.sum_y;
+        self.count += other.count;
+    }
+}
+
+/// A group of nearby points returned by `cluster`, e.g. one marker on a
+/// map. `point` is set for clusters of a single point.
+#[derive(Debug)]
+pub struct Cluster<'a, T> {
+    pub x: f64,
+    pub y: f64,
+    pub count: usize,
+    pub point: Option<&'a Point2D<T>>,
+}
+
+/// A quadtree annotating every node with an aggregate of the points below
+/// it, so aggregates over a region only visit the nodes crossing its border
+/// (Barnes-Hut style).
+#[derive(Debug)]
+pub struct AggregateQuadTree<T, A: Aggregate<T>> {
+    boundary: Rectangle,
+    aggregate: A,
+    // points live in leaves like in `SparseQuadTree`
+    points: Vec<Point2D<T>>,
+    children: Option<Box<[AggregateQuadTree<T, A>; 4]>>,
+}
+
+impl<T, A: Aggregate<T>> AggregateQuadTree<T, A> {
+    const MAX_CAPACITY: usize = 4;
+    const MAX_DEPTH: usize = 32;
+
+    pub fn new(boundary: Rectangle) -> Self {
+        debug_assert!(boundary.is_valid(), "invalid boundary {:?}", boundary);
+        AggregateQuadTree {
+            boundary,
+            aggregate: A::default(),
+            points: Vec::new(),
+            children: None,
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            count += node.points.len();
+            stack.extend(node.children.iter().flat_map(|children| children.iter()));
+        }
+        count
+    }
+
+    /// The aggregate over all points.
+    pub fn total(&self) -> &A {
+        &self.aggregate
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+
+        let mut node = self;
+        let mut depth = 0;
+        node.aggregate.add(&point);
+        while node.children.is_some() {
+            let index = node.boundary.which_quadrant(point.x, point.y).index();
+            node = &mut node.children.as_mut().unwrap()[index];
+            node.aggregate.add(&point);
+            depth += 1;
+        }
+        node.points.push(point);
+        if node.points.len() > Self::MAX_CAPACITY {
+            node.split(depth);
+        }
+        Ok(())
+    }
+
+    // same rules as SparseQuadTree: leaves of identical points or at
+    // MAX_DEPTH grow instead
+    fn split(&mut self, depth: usize) {
+        let first = &self.points[0];
+        if depth >= Self::MAX_DEPTH
+            || self.points.iter().all(|p| p.x == first.x && p.y == first.y)
+        {
+            return;
+        }
+
+        let boundary = self.boundary;
+        let mut children = Box::new([
+            Self::new(boundary.new_ne()),
+            Self::new(boundary.new_se()),
+            Self::new(boundary.new_sw()),
+            Self::new(boundary.new_nw()),
+        ]);
+        for point in std::mem::take(&mut self.points) {
+            let child = &mut children[boundary.which_quadrant(point.x, point.y).index()];
+            child.aggregate.add(&point);
+            child.points.push(point);
+        }
+        for child in children.iter_mut() {
+            if child.points.len() > Self::MAX_CAPACITY {
+                child.split(depth + 1);
+            }
+        }
+        self.children = Some(children);
+    }
+
+    /// Removes one point stored at exactly (x, y) and recomputes the
+    /// aggregates on its path.
+    pub fn remove(&mut self, x: f64, y: f64) -> Option<Point2D<T>> {
+        if !self.boundary.contains(x, y) {
+            return None;
+        }
+        let removed = match self.children.as_mut() {
+            Some(children) => children[self.boundary.which_quadrant(x, y).index()].remove(x, y)?,
+            None => {
+                let index = self.points.iter().position(|p| p.x == x && p.y == y)?;
+                self.points.swap_remove(index)
+            }
+        };
+
+        self.recompute();
+        Some(removed)
+    }
+
+    // min/max style aggregates can't be undone, rebuild them from below
+    fn recompute(&mut self) {
+        let mut aggregate = A::default();
+        match self.children.as_ref() {
+            Some(children) => children.iter().for_each(|child| aggregate.merge(&child.aggregate)),
+            None => self.points.iter().for_each(|point| aggregate.add(point)),
+        }
+        self.aggregate = aggregate;
+    }
+
+    // removes the points failing `keep` and returns how many. Sub-trees whose
+    // aggregate passes `drop_all` are emptied and those passing `keep_all`
+    // are left alone without visiting their points.
+    pub(crate) fn retain_where(
+        &mut self,
+        drop_all: &impl Fn(&A) -> bool,
+        keep_all: &impl Fn(&A) -> bool,
+        keep: &impl Fn(&Point2D<T>) -> bool,
+    ) -> usize {
+        if keep_all(&self.aggregate) {
+            return 0;
+        }
+        if drop_all(&self.aggregate) {
+            let removed = self.count();
+            *self = Self::new(self.boundary);
+            return removed;
+        }
+
+        let removed = match self.children.as_mut() {
+            Some(children) => children
+                .iter_mut()
+                .map(|child| child.retain_where(drop_all, keep_all, keep))
+                .sum(),
+            None => {
+                let before = self.points.len();
+                self.points.retain(|point| keep(point));
+                before - self.points.len()
+            }
+        };
+        if removed > 0 {
+            self.recompute();
+        }
+        removed
+    }
+
+    // points inside `region` passing `keep`, skipping sub-trees whose
+    // aggregate fails `visit`
+    pub(crate) fn query_where(
+        &self,
+        region: &Rectangle,
+        visit: impl Fn(&A) -> bool,
+        keep: impl Fn(&Point2D<T>) -> bool,
+    ) -> Vec<&Point2D<T>> {
+        let mut result = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if !region.intersects(&node.boundary) || !visit(&node.aggregate) {
+                continue;
+            }
+            for point in node.points.iter() {
+                if region.contains(point.x, point.y) && keep(point) {
+                    result.push(point);
+                }
+            }
+            if let Some(children) = node.children.as_ref() {
+                // reversed, so children are visited in ne, se, sw, nw order
+                stack.extend(children.iter().rev());
+            }
+        }
+        result
+    }
+
+    /// The aggregate over the points inside `region`. Nodes completely inside
+    /// contribute their stored aggregate without visiting their points.
+    pub fn aggregate(&self, region: impl Into<Rectangle>) -> A {
+        let region = region.into();
+        let mut result = A::default();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if !region.intersects(&node.boundary) {
+                continue;
+            }
+            if region.contains_rectangle(&node.boundary) {
+                result.merge(&node.aggregate);
+                continue;
+            }
+            for point in node.points.iter() {
+                if region.contains(point.x, point.y) {
+                    result.add(point);
+                }
+            }
+            stack.extend(node.children.iter().flat_map(|children| children.iter()));
+        }
+        result
+    }
+}
+
+impl<T> AggregateQuadTree<T, Centroid> {
+    /// Groups the points into clusters at most about `zoom_cell_size` wide,
+    /// like marker clustering on a web map. Nodes that fit into a cell become
+    /// one cluster at the centroid of their points without visiting them.
+    /// The points of larger leaves are grouped on a grid of that cell size.
+    pub fn cluster(&self, zoom_cell_size: f64) -> Vec<Cluster<'_, T>> {
+        let mut result = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.aggregate.count == 0 {
+                continue;
+            }
+            let boundary = &node.boundary;
+            if boundary.width <= zoom_cell_size && boundary.height <= zoom_cell_size {
+                result.push(node.to_cluster());
+                continue;
+            }
+            match node.children.as_ref() {
+                // reversed, so children are visited in ne, se, sw, nw order
+                Some(children) => stack.extend(children.iter().rev()),
+                None => result.extend(Self::cluster_points(&node.points, zoom_cell_size)),
+            }
+        }
+        result
+    }
+
+    // the whole sub-tree as a single cluster
+    fn to_cluster(&self) -> Cluster<'_, T> {
+        let (x, y) = self.aggregate.position().unwrap_or_default();
+        let mut point = None;
+        if self.aggregate.count == 1 {
+            let mut node = self;
+            while let Some(children) = node.children.as_ref() {
+                node = children.iter().find(|child| child.aggregate.count > 0).unwrap();
+            }
+            point = node.points.first();
+        }
+        Cluster { x, y, count: self.aggregate.count, point }
+    }
+
+    // groups the points of a leaf by grid cell
+    fn cluster_points(points: &[Point2D<T>], zoom_cell_size: f64) -> Vec<Cluster<'_, T>> {
+        let mut groups: Vec<((i64, i64), Centroid, &Point2D<T>)> = Vec::new();
+        for point in points {
+            let cell = (
+                (point.x / zoom_cell_size).floor() as i64,
+                (point.y / zoom_cell_size).floor() as i64,
+            );
+            match groups.iter_mut().find(|(other, _, _)| *other == cell) {
+                Some((_, centroid, _)) => centroid.add(point),
+                None => {
+                    let mut centroid = Centroid::default();
+                    centroid.add(point);
+                    groups.push((cell, centroid, point));
+                }
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(_, centroid, first)| {
+                let (x, y) = centroid.position().unwrap_or_default();
+                let point = (centroid.count == 1).then_some(first);
+                Cluster { x, y, count: centroid.count, point }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_aggregates_regions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(820);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut heights = AggregateQuadTree::<i64, MinMax<i64>>::new(boundary);
+        let mut sums = AggregateQuadTree::<i64, Sum<i64>>::new(boundary);
+        let mut points = Vec::new();
+        for _ in 0..1000 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: rng.gen_range(-500..500),
+            };
+            points.push(point);
+            heights.insert(point)?;
+            sums.insert(point)?;
+        }
+        assert_eq!(heights.count(), 1000);
+        assert!(heights.insert(Point2D { x: -1.0, y: 0.0, data: 0 }).is_err());
+
+        let expected = |points: &[Point2D<i64>], region: &Rectangle| {
+            let inside: Vec<i64> = points
+                .iter()
+                .filter(|p| region.contains(p.x, p.y))
+                .map(|p| p.data)
+                .collect();
+            let sum: i64 = inside.iter().sum();
+            (inside.iter().min().copied(), inside.iter().max().copied(), sum)
+        };
+        for _ in 0..50 {
+            let region = Rectangle::new(
+                rng.gen_range(-10.0..100.0),
+                rng.gen_range(-10.0..100.0),
+                rng.gen_range(0.0..60.0),
+                rng.gen_range(0.0..60.0),
+            );
+            let (min, max, sum) = expected(&points, &region);
+            assert_eq!(heights.aggregate(region), MinMax { min, max });
+            assert_eq!(sums.aggregate(region), Sum(sum));
+        }
+
+        // removing the maximum updates the aggregates on its path
+        let (_, max, _) = expected(&points, &boundary);
+        let index = points.iter().position(|p| Some(p.data) == max).unwrap();
+        let removed = points.swap_remove(index);
+        assert!(heights.remove(removed.x, removed.y).is_some());
+        assert!(heights.remove(removed.x, removed.y).is_none());
+        let (min, max, _) = expected(&points, &boundary);
+        assert_eq!(*heights.total(), MinMax { min, max });
+        assert_eq!(heights.count(), 999);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_counts_identical_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = AggregateQuadTree::<(), Count>::new(Rectangle::new(0.0, 0.0, 1.0, 1.0));
+        for _ in 0..10 {
+            quadtree.insert(Point2D { x: 0.5, y: 0.5, data: () })?;
+        }
+        quadtree.insert(Point2D { x: 0.1, y: 0.1, data: () })?;
+        assert_eq!(*quadtree.total(), Count(11));
+        assert_eq!(quadtree.aggregate(Rectangle::new(0.4, 0.4, 0.2, 0.2)), Count(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_clusters_nearby_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(861);
+        let boundary = Rectangle::new(0.0, 0.0, 1024.0, 1024.0);
+        let mut quadtree = AggregateQuadTree::<usize, Centroid>::new(boundary);
+        // two dense groups of markers and a lone one
+        for i in 0..1000 {
+            let (cx, cy) = if i % 2 == 0 { (100.0, 100.0) } else { (700.0, 300.0) };
+            quadtree.insert(Point2D {
+                x: cx + rng.gen_range(0.0..20.0),
+                y: cy + rng.gen_range(0.0..20.0),
+                data: i,
+            })?;
+        }
+        quadtree.insert(Point2D { x: 1000.0, y: 1000.0, data: 1000 })?;
+
+        let clusters = quadtree.cluster(256.0);
+        assert_eq!(clusters.iter().map(|cluster| cluster.count).sum::<usize>(), 1001);
+        assert!(clusters.len() <= 6);
+        for cluster in clusters.iter() {
+            let near = |cx: f64, cy: f64| (cluster.x - cx).hypot(cluster.y - cy) < 30.0;
+            assert!(near(110.0, 110.0) || near(710.0, 310.0) || near(1000.0, 1000.0));
+        }
+        let lone = clusters.iter().find(|cluster| cluster.count == 1).ok_or("no lone marker")?;
+        assert_eq!(lone.point.map(|point| point.data), Some(1000));
+
+        // zoomed in far enough every marker stands alone
+        let clusters = quadtree.cluster(1e-6);
+        assert_eq!(clusters.len(), 1001);
+        assert!(clusters.iter().all(|cluster| cluster.point.is_some()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+use crate::error::RejectReason;
+use crate::geometry::Rectangle;
+
+/// Collision broad-phase over axis-aligned bounding boxes. Every box is
+/// inserted into the deepest node that fully contains it, so boxes crossing
+/// a seam stay higher up and each box lives in exactly one node.
+///
+/// In a loose tree, created with `with_looseness`, nodes hold boxes within
+/// their boundary scaled around its center, so small boxes near a seam still
+/// go down to the child holding their center and moving boxes rarely change
+/// nodes when `relocate`d.
+#[derive(Debug)]
+pub struct BroadPhase<T> {
+    boundary: Rectangle,
+    // the boundary scaled by `looseness`, where the node's boxes lie
+    loose: Rectangle,
+    looseness: f64,
+    items: Vec<(Rectangle, T)>,
+    children: Option<Box<[BroadPhase<T>; 4]>>,
+}
+
+impl<T> BroadPhase<T> {
+    const MAX_CAPACITY: usize = 4;
+    const MAX_DEPTH: usize = 32;
+
+    pub fn new(boundary: Rectangle) -> Self {
+        BroadPhase::with_looseness(boundary, 1.0)
+    }
+
+    /// A loose tree whose nodes take boxes within their boundary scaled by
+    /// `looseness` around its center, typically 2. A looseness of 1 is a
+    /// tree like `new` creates. Panics unless `looseness` is finite and at
+    /// least 1.
+    pub fn with_looseness(boundary: Rectangle, looseness: f64) -> Self {
+        debug_assert!(boundary.is_valid(), "invalid boundary {:?}", boundary);
+        let valid = looseness >= 1.0 && looseness.is_finite();
+        assert!(valid, "invalid looseness {}", looseness);
+        BroadPhase {
+            boundary,
+            loose: if looseness == 1.0 { boundary } else { boundary.scaled(looseness) },
+            looseness,
+            items: Vec::new(),
+            children: None,
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn looseness(&self) -> f64 {
+        self.looseness
+    }
+
+    pub fn count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            count += node.items.len();
+            stack.extend(node.children.iter().flat_map(|children| children.iter()));
+        }
+        count
+    }
+
+    /// Adds a box, which has to lie completely inside the boundary.
+    pub fn insert(&mut self, aabb: Rectangle, data: T) -> Result<(), RejectReason> {
+        self.check(&aabb)?;
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            if node.children.is_none() {
+                if node.items.len() < Self::MAX_CAPACITY || depth >= Self::MAX_DEPTH {
+                    node.items.push((aabb, data));
+                    return Ok(());
+                }
+                node.split();
+            }
+            match node.child_index(&aabb) {
+                Some(index) => {
+                    node = &mut node.children.as_mut().unwrap()[index];
+                    depth += 1;
+                }
+                None => {
+                    node.items.push((aabb, data));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn check(&self, aabb: &Rectangle) -> Result<(), RejectReason> {
+        if !aabb.is_valid() {
+            return Err(RejectReason::InvalidCoordinates);
+        }
+        if !self.boundary.contains_rectangle(aabb) {
+            return Err(RejectReason::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Removes a box equal to `aabb` whose payload `matches`, returning its
+    /// payload.
+    pub fn remove(&mut self, aabb: &Rectangle, mut matches: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut path = Vec::new();
+        let index = self.find(aabb, &mut matches, &mut path)?;
+        Some(self.node_mut(&path).items.swap_remove(index).1)
+    }
+
+    /// Moves a box equal to `aabb` whose payload `matches` to `to`. Returns
+    /// whether there was such a box. The box stays in its node as long as
+    /// `to` still fits into it, even where it would fit into a child, so in
+    /// loose trees boxes rarely change nodes while moving around.
+    pub fn relocate(
+        &mut self,
+        aabb: &Rectangle,
+        to: Rectangle,
+        mut matches: impl FnMut(&T) -> bool,
+    ) -> Result<bool, RejectReason> {
+        self.check(&to)?;
+        let mut path = Vec::new();
+        let Some(index) = self.find(aabb, &mut matches, &mut path) else {
+            return Ok(false);
+        };
+        let node = self.node_mut(&path);
+        if node.loose.contains_rectangle(&to) {
+            node.items[index].0 = to;
+        } else {
+            let (_, data) = node.items.swap_remove(index);
+            self.insert(to, data)?;
+        }
+        Ok(true)
+    }
+
+    // index of a box equal to `aabb` whose payload `matches` in the node at
+    // the end of `path`, which leads there as child indices
+    fn find(
+        &self,
+        aabb: &Rectangle,
+        matches: &mut impl FnMut(&T) -> bool,
+        path: &mut Vec<usize>,
+    ) -> Option<usize> {
+        let found = self.items.iter().position(|(other, data)| other == aabb && matches(data));
+        if found.is_some() {
+            return found;
+        }
+        for (i, child) in self.children.iter().flat_map(|children| children.iter()).enumerate() {
+            if child.loose.contains_rectangle(aabb) {
+                path.push(i);
+                if let Some(index) = child.find(aabb, matches, path) {
+                    return Some(index);
+                }
+                path.pop();
+            }
+        }
+        None
+    }
+
+    fn node_mut(&mut self, path: &[usize]) -> &mut Self {
+        let mut node = self;
+        for &index in path {
+            node = &mut node.children.as_mut().expect("path leads to a child")[index];
+        }
+        node
+    }
+
+    // moves every box that fits into a quadrant down one level
+    fn split(&mut self) {
+        let (boundary, looseness) = (self.boundary, self.looseness);
+        let mut children = Box::new([
+            Self::with_looseness(boundary.new_ne(), looseness),
+            Self::with_looseness(boundary.new_se(), looseness),
+            Self::with_looseness(boundary.new_sw(), looseness),
+            Self::with_looseness(boundary.new_nw(), looseness),
+        ]);
+        for (aabb, data) in std::mem::take(&mut self.items) {
+            match self.child_index(&aabb) {
+                Some(index) => children[index].items.push((aabb, data)),
+                None => self.items.push((aabb, data)),
+            }
+        }
+        self.children = Some(children);
+    }
+
+    // index into [ne, se, sw, nw] of the quadrant fully containing `aabb`.
+    // Loose trees only try the quadrant holding the center of `aabb`.
+    fn child_index(&self, aabb: &Rectangle) -> Option<usize> {
+        let boundary = &self.boundary;
+        if self.looseness != 1.0 {
+            let (x, y) = (aabb.x + aabb.width / 2.0, aabb.y + aabb.height / 2.0);
+            let quadrant = boundary.which_quadrant(x, y);
+            let loose = boundary.quadrant(quadrant).scaled(self.looseness);
+            return loose.contains_rectangle(aabb).then_some(quadrant.index());
+        }
+        [boundary.new_ne(), boundary.new_se(), boundary.new_sw(), boundary.new_nw()]
+            .iter()
+            .position(|quadrant| quadrant.contains_rectangle(aabb))
+    }
+
+    /// Payloads of the boxes intersecting `region`.
+    pub fn query(&self, region: &Rectangle) -> Vec<&T> {
+        let mut result = Vec::new();
+        self.for_each_intersecting(region, |data| result.push(data));
+        result
+    }
+
+    fn for_each_intersecting<'a>(&'a self, region: &Rectangle, mut f: impl FnMut(&'a T)) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if !region.intersects(&node.loose) {
+                continue;
+            }
+            for (aabb, data) in node.items.iter() {
+                if region.intersects(aabb) {
+                    f(data);
+                }
+            }
+            stack.extend(node.children.iter().flat_map(|children| children.iter().rev()));
+        }
+    }
+
+    /// Every pair of boxes that intersect, each pair reported once. Boxes
+    /// are compared with boxes in the same node or in nodes above it, and
+    /// with those in later sibling sub-trees whose boxes reach them, which
+    /// loose nodes and boxes touching a seam do.
+    pub fn candidate_pairs(&self) -> Vec<(&T, &T)> {
+        let mut pairs = Vec::new();
+        self.pairs_into(&mut Vec::new(), &mut Vec::new(), &mut pairs);
+        pairs
+    }
+
+    // `later` holds the sub-trees after the path to this node, at any level
+    fn pairs_into<'a>(
+        &'a self,
+        ancestors: &mut Vec<&'a (Rectangle, T)>,
+        later: &mut Vec<&'a BroadPhase<T>>,
+        pairs: &mut Vec<(&'a T, &'a T)>,
+    ) {
+        for (i, (aabb, data)) in self.items.iter().enumerate() {
+            for (other, other_data) in ancestors.iter().map(|item| (&item.0, &item.1)) {
+                if aabb.intersects(other) {
+                    pairs.push((other_data, data));
+                }
+            }
+            for (other, other_data) in &self.items[i + 1..] {
+                if aabb.intersects(other) {
+                    pairs.push((data, other_data));
+                }
+            }
+            for subtree in later.iter() {
+                subtree.for_each_intersecting(aabb, |other_data| pairs.push((data, other_data)));
+            }
+        }
+
+        if let Some(children) = self.children.as_ref() {
+            let len = ancestors.len();
+            ancestors.extend(self.items.iter());
+            for (i, child) in children.iter().enumerate() {
+                let siblings = later.len();
+                later.extend(children[i + 1..].iter());
+                child.pairs_into(ancestors, later, pairs);
+                later.truncate(siblings);
+            }
+            ancestors.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_finds_all_overlapping_boxes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut broadphase = BroadPhase::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(837);
+        let mut boxes = Vec::new();
+        for i in 0..300 {
+            let width = rng.gen_range(0.0..8.0);
+            let height = rng.gen_range(0.0..8.0);
+            let aabb = Rectangle::new(
+                rng.gen_range(0.0..100.0 - width),
+                rng.gen_range(0.0..100.0 - height),
+                width,
+                height,
+            );
+            boxes.push(aabb);
+            broadphase.insert(aabb, i)?;
+        }
+        assert_eq!(broadphase.count(), 300);
+        assert_eq!(
+            broadphase.insert(Rectangle::new(95.0, 0.0, 10.0, 1.0), 300),
+            Err(RejectReason::OutOfBounds)
+        );
+
+        let mut expected = Vec::new();
+        for (i, a) in boxes.iter().enumerate() {
+            for (j, b) in boxes.iter().enumerate().skip(i + 1) {
+                if a.intersects(b) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        let mut found: Vec<(usize, usize)> = broadphase
+            .candidate_pairs()
+            .into_iter()
+            .map(|(&a, &b)| (a.min(b), a.max(b)))
+            .collect();
+        found.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+
+        let region = Rectangle::new(20.0, 20.0, 30.0, 30.0);
+        let mut found: Vec<usize> = broadphase.query(&region).into_iter().copied().collect();
+        found.sort();
+        let expected: Vec<usize> = (0..300).filter(|&i| boxes[i].intersects(&region)).collect();
+        assert_eq!(found, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_pairs_boxes_touching_at_the_split_line() -> Result<(), Box<dyn std::error::Error>> {
+        let mut broadphase = BroadPhase::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..4 {
+            broadphase.insert(Rectangle::new(20.0 * i as f64, 80.0, 1.0, 1.0), i)?;
+        }
+        // both sit below the split root, one in each quadrant, and share x = 50
+        broadphase.insert(Rectangle::new(40.0, 10.0, 10.0, 10.0), 4)?;
+        broadphase.insert(Rectangle::new(50.0, 15.0, 10.0, 10.0), 5)?;
+        assert!(broadphase.items.is_empty());
+
+        let pairs: Vec<(usize, usize)> =
+            broadphase.candidate_pairs().into_iter().map(|(&a, &b)| (a.min(b), a.max(b))).collect();
+        assert_eq!(pairs, vec![(4, 5)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_moving_boxes_in_loose_nodes() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut tight = BroadPhase::new(boundary);
+        let mut loose = BroadPhase::with_looseness(boundary, 2.0);
+        let mut rng = StdRng::seed_from_u64(890);
+        let mut boxes: Vec<Rectangle> = (0..300)
+            .map(|_| Rectangle::new(rng.gen_range(5.0..90.0), rng.gen_range(5.0..90.0), 2.0, 2.0))
+            .collect();
+        for (i, aabb) in boxes.iter().enumerate() {
+            tight.insert(*aabb, i)?;
+            loose.insert(*aabb, i)?;
+        }
+
+        // every box drifts a little per step, counting the moves to another node
+        let mut changes = [0, 0];
+        for _ in 0..20 {
+            for (i, aabb) in boxes.iter_mut().enumerate() {
+                let (dx, dy) = (rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+                let to = aabb.translated(dx, dy).clamped_to(&boundary);
+                for (tree, changes) in [&mut tight, &mut loose].into_iter().zip(&mut changes) {
+                    let (mut before, mut after) = (Vec::new(), Vec::new());
+                    tree.find(aabb, &mut |&data| data == i, &mut before);
+                    assert!(tree.relocate(aabb, to, |&data| data == i)?);
+                    tree.find(&to, &mut |&data| data == i, &mut after);
+                    if before != after {
+                        *changes += 1;
+                    }
+                }
+                *aabb = to;
+            }
+        }
+        assert!(changes[1] * 2 < changes[0], "{:?}", changes);
+
+        let mut expected = Vec::new();
+        for (i, a) in boxes.iter().enumerate() {
+            for (j, b) in boxes.iter().enumerate().skip(i + 1) {
+                if a.intersects(b) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        let region = Rectangle::new(20.0, 20.0, 30.0, 30.0);
+        for tree in [&tight, &loose] {
+            assert_eq!(tree.count(), 300);
+            let mut found: Vec<(usize, usize)> = tree
+                .candidate_pairs()
+                .into_iter()
+                .map(|(&a, &b)| (a.min(b), a.max(b)))
+                .collect();
+            found.sort();
+            assert_eq!(found, expected);
+
+            let mut found: Vec<usize> = tree.query(&region).into_iter().copied().collect();
+            found.sort();
+            let expected: Vec<usize> = (0..300).filter(|&i| boxes[i].intersects(&region)).collect();
+            assert_eq!(found, expected);
+        }
+
+        assert_eq!(loose.remove(&boxes[7], |&data| data == 7), Some(7));
+        assert_eq!(loose.remove(&boxes[7], |&data| data == 7), None);
+        assert!(!loose.relocate(&boxes[7], boxes[8], |&data| data == 7)?);
+        let outside = Rectangle::new(99.0, 0.0, 2.0, 2.0);
+        assert_eq!(loose.relocate(&boxes[8], outside, |_| true), Err(RejectReason::OutOfBounds));
+        assert_eq!(loose.count(), 299);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+
+use crate::geometry::{Point2D, Rectangle};
+
+// Compact little-endian layout shared by both tree implementations:
+//
+//   boundary: x, y, width, height as f64
+//   nodes in pre-order, each as
+//     u8  bitmap of present children (ne = 1, se = 2, sw = 4, nw = 8),
+//         plus 16 if the children don't meet at the node's center
+//     u32 number of points stored in the node
+//     x, y as f64 where the children meet, only with the 16 flag
+//     points: x, y as f64 followed by the packed payload
+//     children in ne, se, sw, nw order
+//
+// Child boundaries are not stored, they are derived from the parent and
+// the position its children meet at.
+//
+// Patches written by `serialize_dirty` use the same layout, but every node
+// starts with a u8 marker: UNCHANGED nodes end there and keep their sub-tree,
+// CHANGED nodes continue with the header, points and children as above.
+
+pub(crate) const NE: u8 = 1;
+pub(crate) const SE: u8 = 2;
+pub(crate) const SW: u8 = 4;
+pub(crate) const NW: u8 = 8;
+const SPLIT: u8 = 16;
+
+pub(crate) const UNCHANGED: u8 = 0;
+pub(crate) const CHANGED: u8 = 1;
+
+/// Payloads with a fixed-size little-endian byte representation.
+pub trait Packable: Sized {
+    const SIZE: usize;
+
+    fn pack(&self, out: &mut Vec<u8>);
+    fn unpack(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_packable {
+    ($($t:ty),*) => {
+        $(
+            impl Packable for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn pack(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn unpack(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_packable!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Packable for () {
+    const SIZE: usize = 0;
+
+    fn pack(&self, _out: &mut Vec<u8>) {}
+
+    fn unpack(_bytes: &[u8]) -> Self {}
+}
+
+pub(crate) fn write_rectangle(out: &mut Vec<u8>, rectangle: &Rectangle) {
+    for value in [rectangle.x, rectangle.y, rectangle.width, rectangle.height] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+// `split` is where the children meet, `None` for the center
+pub(crate) fn write_node_header(
+    out: &mut Vec<u8>,
+    children: u8,
+    points: usize,
+    split: Option<(f64, f64)>,
+) {
+    out.push(if split.is_some() { children | SPLIT } else { children });
+    out.extend_from_slice(&(points as u32).to_le_bytes());
+    if let Some((x, y)) = split {
+        out.extend_from_slice(&x.to_le_bytes());
+        out.extend_from_slice(&y.to_le_bytes());
+    }
+}
+
+pub(crate) fn write_point<T: Packable>(out: &mut Vec<u8>, point: &Point2D<T>) {
+    out.extend_from_slice(&point.x.to_le_bytes());
+    out.extend_from_slice(&point.y.to_le_bytes());
+    point.data.pack(out);
+}
+
+// where nodes are read from: a byte slice for `from_bytes` or any
+// `io::Read` for `from_reader`
+pub(crate) trait Source {
+    fn take(&mut self, len: usize) -> Result<&[u8], &'static str>;
+    // how many bytes are known to be left, caps allocations sized by counts
+    // read from the untrusted input
+    fn available(&self) -> usize;
+    fn at_end(&mut self) -> bool;
+
+    fn read_f64(&mut self) -> Result<f64, &'static str> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_rectangle(&mut self) -> Result<Rectangle, &'static str> {
+        Rectangle::try_new(
+            self.read_f64()?,
+            self.read_f64()?,
+            self.read_f64()?,
+            self.read_f64()?,
+        )
+    }
+
+    // the child bitmap, the number of points and where the children of a
+    // node covering `boundary` meet
+    fn read_node_header(
+        &mut self,
+        boundary: &Rectangle,
+    ) -> Result<(u8, usize, (f64, f64)), &'static str> {
+        let children = self.take(1)?[0];
+        if children & !(NE | SE | SW | NW | SPLIT) != 0 {
+            return Err("Invalid child bitmap");
+        }
+        let points = u32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        let mut split = boundary.center();
+        if children & SPLIT != 0 {
+            split = (self.read_f64()?, self.read_f64()?);
+            if !boundary.contains(split.0, split.1) {
+                return Err("Boundary doesn't contain split point");
+            }
+        }
+        Ok((children & !SPLIT, points as usize, split))
+    }
+
+    fn read_points<T: Packable>(
+        &mut self,
+        boundary: &Rectangle,
+        count: usize,
+    ) -> Result<Vec<Point2D<T>>, &'static str> {
+        // don't trust the count for the allocation before the bytes are there
+        let mut points = Vec::with_capacity(count.min(self.available() / (16 + T::SIZE).max(1)));
+        for _ in 0..count {
+            let x = self.read_f64()?;
+            let y = self.read_f64()?;
+            let data = T::unpack(self.take(T::SIZE)?);
+            if !boundary.contains(x, y) {
+                return Err("Boundary doesn't contain point");
+            }
+            points.push(Point2D { x, y, data });
+        }
+        Ok(points)
+    }
+}
+
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes }
+    }
+}
+
+impl Source for ByteReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], &'static str> {
+        if self.bytes.len() < len {
+            return Err("Unexpected end of input");
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn available(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+// reads through a small buffer holding one value at a time. I/O errors are
+// kept aside so callers can report them instead of the generic message.
+pub(crate) struct StreamReader<R: io::BufRead> {
+    reader: R,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl<R: io::BufRead> StreamReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        StreamReader {
+            reader,
+            buffer: Vec::new(),
+            error: None,
+        }
+    }
+
+    // the I/O error that stopped reading, or `message` as invalid data
+    pub(crate) fn error(&mut self, message: &'static str) -> io::Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+}
+
+impl<R: io::BufRead> Source for StreamReader<R> {
+    fn take(&mut self, len: usize) -> Result<&[u8], &'static str> {
+        self.buffer.resize(len, 0);
+        if let Err(error) = self.reader.read_exact(&mut self.buffer) {
+            self.error = Some(error);
+            return Err("Failed to read input");
+        }
+        Ok(&self.buffer)
+    }
+
+    fn available(&self) -> usize {
+        // unknown, vectors start small and grow while points arrive
+        64 * 1024
+    }
+
+    fn at_end(&mut self) -> bool {
+        match self.reader.fill_buf() {
+            Ok(buffer) => buffer.is_empty(),
+            Err(error) => {
+                self.error = Some(error);
+                false
+            }
+        }
+    }
+}
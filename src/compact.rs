@@ -0,0 +1,209 @@
+use crate::error::RejectReason;
+use crate::geometry::{Point2D, Rectangle};
+use crate::morton;
+
+// 12 bytes per node. Children are four consecutive entries of `nodes`
+// starting at `children` in Z-order (nw, ne, sw, se), 0 marks a leaf since
+// the root is never a child. Leaves own `positions[start..start + len]`.
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    children: u32,
+    start: u32,
+    len: u32,
+}
+
+/// A static, memory-frugal quadtree built in one go from a set of points.
+/// Nodes link their children with `u32` indices and coordinates are
+/// quantized to a `u32` grid over the boundary, so a point costs 8 bytes plus
+/// its payload. Positions are only kept to within `boundary.width / 2^32`
+/// (and the same vertically), queries see the quantized positions.
+#[derive(Debug)]
+pub struct CompactQuadTree<T> {
+    boundary: Rectangle,
+    nodes: Vec<Node>,
+    // quantized (x, y) and payloads, sorted by Z-order
+    positions: Vec<(u32, u32)>,
+    data: Vec<T>,
+}
+
+impl<T> CompactQuadTree<T> {
+    const MAX_CAPACITY: usize = 8;
+    const DEPTH: u32 = 32;
+
+    /// Builds the tree from all points. Panics with 2^32 or more points.
+    pub fn from_points(boundary: Rectangle, points: Vec<Point2D<T>>) -> Result<Self, RejectReason> {
+        assert!(u32::try_from(points.len()).is_ok(), "too many points for u32 indices");
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+
+        let mut coded: Vec<(u64, T)> = points
+            .into_iter()
+            .map(|point| {
+                (
+                    morton::encode(point.x, point.y, &boundary, Self::DEPTH),
+                    point.data,
+                )
+            })
+            .collect();
+        coded.sort_by_key(|(code, _)| *code);
+        let codes: Vec<u64> = coded.iter().map(|(code, _)| *code).collect();
+
+        let mut tree = CompactQuadTree {
+            boundary,
+            nodes: vec![Node::default()],
+            positions: codes
+                .iter()
+                .map(|code| morton::decode_cell(*code))
+                .collect(),
+            data: coded.into_iter().map(|(_, data)| data).collect(),
+        };
+        tree.build(0, &codes, 0, 0);
+        Ok(tree)
+    }
+
+    // fills node `index` with `codes`, which start at `start` in the sorted
+    // order and all share the cell prefix of `level`
+    fn build(&mut self, index: usize, codes: &[u64], start: usize, level: u32) {
+        if codes.len() <= Self::MAX_CAPACITY || level == Self::DEPTH {
+            // the lengths fit into u32, checked in from_points
+            self.nodes[index] = Node {
+                children: 0,
+                start: start as u32,
+                len: codes.len() as u32,
+            };
+            return;
+        }
+
+        let children = self.nodes.len();
+        self.nodes.resize(children + 4, Node::default());
+        self.nodes[index].children = children as u32;
+        let shift = 2 * (Self::DEPTH - level - 1);
+        let mut offset = 0;
+        for quadrant in 0..4 {
+            let end =
+                offset + codes[offset..].partition_point(|code| (code >> shift) & 3 <= quadrant);
+            self.build(
+                children + quadrant as usize,
+                &codes[offset..end],
+                start + offset,
+                level + 1,
+            );
+            offset = end;
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // the center of the quantization cell, halfway between its edges so
+    // rounding can't push it into a neighboring cell
+    fn position(&self, (qx, qy): (u32, u32)) -> (f64, f64) {
+        let cells = (1u64 << Self::DEPTH) as f64;
+        let x = self.boundary.x + (qx as f64 + 0.5) / cells * self.boundary.width;
+        let y = self.boundary.y + (qy as f64 + 0.5) / cells * self.boundary.height;
+        (x, y)
+    }
+
+    /// Points inside `region` at their quantized positions, with borrowed
+    /// payloads.
+    pub fn query(&self, region: impl Into<Rectangle>) -> Vec<Point2D<&T>> {
+        let region = region.into();
+        let mut result = Vec::new();
+        if self.data.is_empty() {
+            return result;
+        }
+        let mut stack = vec![(0, 0u64, 0)];
+        while let Some((index, prefix, level)) = stack.pop() {
+            let node = self.nodes[index];
+            if !region.intersects(&morton::cell_rectangle(prefix, level, &self.boundary)) {
+                continue;
+            }
+            if node.children == 0 {
+                let range = node.start as usize..(node.start + node.len) as usize;
+                for (position, data) in self.positions[range.clone()].iter().zip(&self.data[range])
+                {
+                    let (x, y) = self.position(*position);
+                    if region.contains(x, y) {
+                        result.push(Point2D { x, y, data });
+                    }
+                }
+                continue;
+            }
+            // reversed, so children are visited in Z-order
+            for quadrant in (0..4).rev() {
+                stack.push((
+                    node.children as usize + quadrant as usize,
+                    (prefix << 2) | quadrant,
+                    level + 1,
+                ));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::VecIndex;
+
+    use super::*;
+
+    #[test]
+    fn it_answers_queries_like_a_plain_list() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(std::mem::size_of::<Node>(), 12);
+
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(847);
+        let mut points = Vec::new();
+        let mut index = VecIndex::new(boundary);
+        for i in 0..2000 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            points.push(point);
+            index.insert(point)?;
+        }
+        // a pile of identical points stops splitting at the deepest level
+        for i in 2000..2100 {
+            points.push(Point2D::new(12.5, 12.5, i));
+            index.insert(Point2D::new(12.5, 12.5, i))?;
+        }
+        let tree = CompactQuadTree::from_points(boundary, points)?;
+        assert_eq!(tree.count(), 2100);
+
+        for region in [
+            Rectangle::new(10.0, 10.0, 30.0, 5.0),
+            Rectangle::new(0.0, 0.0, 100.0, 100.0),
+            Rectangle::new(12.0, 12.0, 1.0, 1.0),
+            Rectangle::new(90.0, 90.0, 50.0, 50.0),
+        ] {
+            let mut expected: Vec<usize> = index.query(region).iter().map(|p| p.data).collect();
+            let mut found: Vec<usize> = tree.query(region).iter().map(|p| *p.data).collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+
+        let all = tree.query(boundary);
+        assert!(all.iter().all(|p| boundary.contains(p.x, p.y)));
+        assert!(CompactQuadTree::from_points(boundary, vec![Point2D::new(101.0, 0.0, 0)]).is_err());
+        assert_eq!(
+            CompactQuadTree::<u8>::from_points(boundary, vec![])?
+                .query(boundary)
+                .len(),
+            0
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+//! Adapters exposing other spatial index crates through `SpatialIndex`, so
+//! the benchmarks run identical workloads against them and this crate.
+
+use std::num::NonZeroUsize;
+
+use kdtree::distance::squared_euclidean;
+use kiddo::leaf_strategies::VecOfArrays;
+use kiddo::{Eytzinger, SquaredEuclidean};
+use rstar::{PointDistance, RTree, RTreeObject, AABB};
+
+use crate::error::{InsertError, RejectReason};
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+// a stored point as rstar sees it
+#[derive(Debug)]
+struct Entry<T>(Point2D<T>);
+
+impl<T> RTreeObject for Entry<T> {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point([self.0.x, self.0.y])
+    }
+}
+
+impl<T> PointDistance for Entry<T> {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        self.0.distance_squared_to_position(point[0], point[1])
+    }
+}
+
+/// An `rstar` R*-tree behind the `SpatialIndex` interface. Points outside
+/// the boundary are rejected like in the quadtrees.
+#[derive(Debug)]
+pub struct RStarIndex<T> {
+    boundary: Rectangle,
+    tree: RTree<Entry<T>>,
+}
+
+impl<T> RStarIndex<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        RStarIndex {
+            boundary,
+            tree: RTree::new(),
+        }
+    }
+
+    /// Builds the tree with rstar's bulk loading, the counterpart of
+    /// `LinearQuadTree::from_points`.
+    pub fn from_points(boundary: Rectangle, points: Vec<Point2D<T>>) -> Result<Self, RejectReason> {
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+        Ok(RStarIndex {
+            boundary,
+            tree: RTree::bulk_load(points.into_iter().map(Entry).collect()),
+        })
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        self.tree.insert(Entry(point));
+        Ok(())
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let envelope = AABB::from_corners(
+            [region.x, region.y],
+            [region.x + region.width, region.y + region.height],
+        );
+        self.tree
+            .locate_in_envelope(&envelope)
+            .map(|entry| &entry.0)
+            .collect()
+    }
+
+    /// The `k` points nearest to (x, y) with their distance, nearest first.
+    pub fn k_nearest(&self, x: f64, y: f64, k: usize) -> Vec<(&Point2D<T>, f64)> {
+        self.tree
+            .nearest_neighbor_iter_with_distance_2(&[x, y])
+            .take(k)
+            .map(|(entry, d)| (&entry.0, d.sqrt()))
+            .collect()
+    }
+}
+
+impl<T> SpatialIndex<T> for RStarIndex<T> {
+    fn new(boundary: Rectangle) -> Self {
+        RStarIndex::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+/// A kd-tree of the `kdtree` crate behind the `SpatialIndex` interface. It
+/// only answers radius queries, so range queries search the circle around
+/// the region and keep the points inside it. Its payloads must be
+/// comparable, so it stores indices into a vector of the points.
+#[derive(Debug)]
+pub struct KdtreeIndex<T> {
+    boundary: Rectangle,
+    tree: kdtree::KdTree<f64, usize, [f64; 2]>,
+    points: Vec<Point2D<T>>,
+}
+
+impl<T> KdtreeIndex<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        KdtreeIndex {
+            boundary,
+            tree: kdtree::KdTree::new(2),
+            points: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        // kdtree only rejects coordinates that aren't finite
+        if self.tree.add([point.x, point.y], self.points.len()).is_err() {
+            let reason = RejectReason::InvalidCoordinates;
+            return Err(InsertError { point, reason });
+        }
+        self.points.push(point);
+        Ok(())
+    }
+
+    /// Regions with NaN or infinite coordinates find nothing, kdtree refuses
+    /// to search around them.
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let (cx, cy) = region.center();
+        let radius = (region.width * region.width + region.height * region.height) / 4.0;
+        let Ok(found) = self.tree.within(&[cx, cy], radius, &squared_euclidean) else {
+            return Vec::new();
+        };
+        found
+            .into_iter()
+            .map(|(_, &index)| &self.points[index])
+            .filter(|point| region.contains(point.x, point.y))
+            .collect()
+    }
+
+    /// The `k` points nearest to (x, y) with their distance, nearest first.
+    /// Positions with NaN or infinite coordinates find nothing.
+    pub fn k_nearest(&self, x: f64, y: f64, k: usize) -> Vec<(&Point2D<T>, f64)> {
+        let Ok(found) = self.tree.nearest(&[x, y], k, &squared_euclidean) else {
+            return Vec::new();
+        };
+        found.into_iter().map(|(d, &index)| (&self.points[index], d.sqrt())).collect()
+    }
+}
+
+impl<T> SpatialIndex<T> for KdtreeIndex<T> {
+    fn new(boundary: Rectangle) -> Self {
+        KdtreeIndex::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+// kiddo's mutable tree layout, with `u64` items so every index into the
+// points fits
+type KiddoTree = kiddo::KdTree<f64, u64, Eytzinger, VecOfArrays<f64, u64, 2, 32>, 2, 32>;
+
+/// A kd-tree of the `kiddo` crate behind the `SpatialIndex` interface. Like
+/// `KdtreeIndex` it stores indices into a vector of the points and answers
+/// range queries with a radius search around the region.
+#[derive(Debug)]
+pub struct KiddoIndex<T> {
+    boundary: Rectangle,
+    tree: KiddoTree,
+    points: Vec<Point2D<T>>,
+}
+
+impl<T> KiddoIndex<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        KiddoIndex {
+            boundary,
+            tree: KiddoTree::default(),
+            points: Vec::new(),
+        }
+    }
+
+    /// Builds the tree with kiddo's bulk construction, the counterpart of
+    /// `LinearQuadTree::from_points`.
+    pub fn from_points(boundary: Rectangle, points: Vec<Point2D<T>>) -> Result<Self, RejectReason> {
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+        let positions: Vec<[f64; 2]> = points.iter().map(|point| [point.x, point.y]).collect();
+        let tree = KiddoTree::new_from_slice(&positions)
+            .map_err(|_| RejectReason::InvalidCoordinates)?;
+        Ok(KiddoIndex {
+            boundary,
+            tree,
+            points,
+        })
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        if self.tree.add(&[point.x, point.y], self.points.len() as u64).is_err() {
+            let reason = RejectReason::InvalidCoordinates;
+            return Err(InsertError { point, reason });
+        }
+        self.points.push(point);
+        Ok(())
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let (cx, cy) = region.center();
+        let radius = (region.width * region.width + region.height * region.height) / 4.0;
+        self.tree
+            .query(&[cx, cy])
+            .within::<SquaredEuclidean<f64>>(radius)
+            .unsorted()
+            .execute()
+            .into_iter()
+            .map(|found| &self.points[found.item as usize])
+            .filter(|point| region.contains(point.x, point.y))
+            .collect()
+    }
+
+    /// The `k` points nearest to (x, y) with their distance, nearest first.
+    pub fn k_nearest(&self, x: f64, y: f64, k: usize) -> Vec<(&Point2D<T>, f64)> {
+        let Some(k) = NonZeroUsize::new(k) else {
+            return Vec::new();
+        };
+        self.tree
+            .query(&[x, y])
+            .nearest_n::<SquaredEuclidean<f64>>(k)
+            .execute()
+            .into_iter()
+            .map(|found| (&self.points[found.item as usize], found.distance.sqrt()))
+            .collect()
+    }
+}
+
+impl<T> SpatialIndex<T> for KiddoIndex<T> {
+    fn new(boundary: Rectangle) -> Self {
+        KiddoIndex::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::QuadTree;
+
+    use super::*;
+
+    #[test]
+    fn it_answers_like_the_quadtree() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(854);
+        let points: Vec<Point2D<usize>> = (0..1000)
+            .map(|i| Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })
+            .collect();
+        let rstar = RStarIndex::from_points(boundary, points.clone())?;
+        let kiddo = KiddoIndex::from_points(boundary, points.clone())?;
+        let mut kdtree = KdtreeIndex::new(boundary);
+        let mut quadtree = QuadTree::new(boundary);
+        for point in points {
+            kdtree.insert(point)?;
+            quadtree.insert(point)?;
+        }
+        assert_eq!(rstar.count(), 1000);
+        assert_eq!(kiddo.count(), 1000);
+        assert_eq!(kdtree.count(), 1000);
+
+        let region = Rectangle::new(20.0, 30.0, 25.0, 10.0);
+        let mut expected: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        let mut found: Vec<usize> = rstar.query(region).iter().map(|p| p.data).collect();
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+        let mut found: Vec<usize> = kdtree.query(region).iter().map(|p| p.data).collect();
+        found.sort();
+        assert_eq!(found, expected);
+        let mut found: Vec<usize> = kiddo.query(region).iter().map(|p| p.data).collect();
+        found.sort();
+        assert_eq!(found, expected);
+
+        let nearest = |points: Vec<(&Point2D<usize>, f64)>| -> Vec<usize> {
+            points.iter().map(|(p, _)| p.data).collect()
+        };
+        let expected = nearest(quadtree.k_nearest(50.0, 50.0, 5));
+        assert_eq!(nearest(rstar.k_nearest(50.0, 50.0, 5)), expected);
+        assert_eq!(nearest(kdtree.k_nearest(50.0, 50.0, 5)), expected);
+        assert_eq!(nearest(kiddo.k_nearest(50.0, 50.0, 5)), expected);
+
+        let mut index = RStarIndex::new(boundary);
+        let outside = Point2D { x: 120.0, y: 0.0, data: 0 };
+        let result = index.insert(outside).map_err(|error| error.reason);
+        assert_eq!(result, Err(RejectReason::OutOfBounds));
+        let result = kdtree.insert(outside).map_err(|error| error.reason);
+        assert_eq!(result, Err(RejectReason::OutOfBounds));
+        let mut kiddo = KiddoIndex::new(boundary);
+        let result = kiddo.insert(outside).map_err(|error| error.reason);
+        assert_eq!(result, Err(RejectReason::OutOfBounds));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_nothing_around_nan() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut kdtree = KdtreeIndex::new(boundary);
+        let mut kiddo = KiddoIndex::new(boundary);
+        for i in 0..10 {
+            let point = Point2D::new(10.0 * i as f64, 5.0, i);
+            kdtree.insert(point)?;
+            kiddo.insert(point)?;
+        }
+
+        let region = Rectangle::new(f64::NAN, 0.0, 10.0, 10.0);
+        assert!(kdtree.query(region).is_empty());
+        assert!(kiddo.query(region).is_empty());
+        assert!(kdtree.k_nearest(f64::NAN, 5.0, 3).is_empty());
+        assert!(kiddo.k_nearest(f64::NAN, 5.0, 3).is_empty());
+        assert_eq!(kdtree.k_nearest(0.0, 5.0, 3).len(), 3);
+        assert!(kiddo.k_nearest(0.0, 5.0, 0).is_empty());
+
+        Ok(())
+    }
+}
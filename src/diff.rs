@@ -0,0 +1,71 @@
+use crate::geometry::Point2D;
+
+/// Changes turning one tree into another, see `QuadTree::diff`. Points are
+/// matched by coordinates and payload; a payload found at different
+/// coordinates counts as moved.
+#[derive(Debug)]
+pub struct TreeDiff<'a, T> {
+    pub added: Vec<&'a Point2D<T>>,
+    pub removed: Vec<&'a Point2D<T>>,
+    /// Pairs of the old and the new point.
+    pub moved: Vec<(&'a Point2D<T>, &'a Point2D<T>)>,
+}
+
+impl<T> TreeDiff<'_, T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+fn same<T: PartialEq>(a: &Point2D<T>, b: &Point2D<T>) -> bool {
+    a.x == b.x && a.y == b.y && a.data == b.data
+}
+
+// cancels points present on both sides of two nodes covering the same region,
+// the rest is left for `resolve`
+pub(crate) fn compare_nodes<'a, T: PartialEq>(
+    old: &'a [Point2D<T>],
+    new: &'a [Point2D<T>],
+    removed: &mut Vec<&'a Point2D<T>>,
+    added: &mut Vec<&'a Point2D<T>>,
+) {
+    let mut matched = vec![false; new.len()];
+    for point in old {
+        match (0..new.len()).find(|&i| !matched[i] && same(point, &new[i])) {
+            Some(i) => matched[i] = true,
+            None => removed.push(point),
+        }
+    }
+    added.extend(new.iter().zip(matched).filter(|(_, m)| !m).map(|(point, _)| point));
+}
+
+// points stored at different places in the two trees may still be equal, the
+// remaining ones are paired up by payload. Quadratic in the size of the
+// change, not the size of the trees.
+pub(crate) fn resolve<'a, T: PartialEq>(
+    mut removed: Vec<&'a Point2D<T>>,
+    mut added: Vec<&'a Point2D<T>>,
+) -> TreeDiff<'a, T> {
+    removed.retain(|old| match added.iter().position(|new| same(old, new)) {
+        Some(i) => {
+            added.swap_remove(i);
+            false
+        }
+        None => true,
+    });
+
+    let mut moved = Vec::new();
+    removed.retain(|old| match added.iter().position(|new| old.data == new.data) {
+        Some(i) => {
+            moved.push((*old, added.swap_remove(i)));
+            false
+        }
+        None => true,
+    });
+
+    TreeDiff {
+        added,
+        removed,
+        moved,
+    }
+}
@@ -0,0 +1,117 @@
+use std::marker::PhantomData;
+
+use crate::error::InsertError;
+use crate::geometry::Point2D;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Exact-coordinate lookups the entry API is built on. Implemented by the
+/// pointer-based trees and sealed, so it can't be implemented outside this
+/// crate.
+///
+/// Points are addressed by slot: the node `depth` levels down the route to
+/// their coordinates and their index among that node's points. Entries keep
+/// the slot a lookup or insert ended at, so no tree is searched twice.
+pub trait EntryTree<T>: sealed::Sealed {
+    /// The slot of the point stored at exactly (x, y).
+    fn find_exact_slot(&self, x: f64, y: f64) -> Option<(usize, usize)>;
+    /// The point in a slot of the route to (x, y), marking the nodes on the
+    /// way as changed.
+    fn point_at_mut(&mut self, x: f64, y: f64, slot: (usize, usize)) -> &mut Point2D<T>;
+    /// Inserts the point and returns the slot it ended up in.
+    fn insert_point(&mut self, point: Point2D<T>) -> Result<(usize, usize), InsertError<T>>;
+}
+
+pub(crate) use sealed::Sealed;
+
+/// A view into the point stored at exact coordinates, which may not exist
+/// yet. Created by the trees' `entry` methods.
+pub enum Entry<'a, T, Q: EntryTree<T>> {
+    Occupied(OccupiedEntry<'a, T>),
+    Vacant(VacantEntry<'a, T, Q>),
+}
+
+pub struct OccupiedEntry<'a, T> {
+    point: &'a mut Point2D<T>,
+}
+
+pub struct VacantEntry<'a, T, Q: EntryTree<T>> {
+    tree: &'a mut Q,
+    x: f64,
+    y: f64,
+    data: PhantomData<T>,
+}
+
+impl<'a, T, Q: EntryTree<T>> Entry<'a, T, Q> {
+    pub(crate) fn new(tree: &'a mut Q, x: f64, y: f64) -> Self {
+        if let Some(slot) = tree.find_exact_slot(x, y) {
+            Entry::Occupied(OccupiedEntry {
+                point: tree.point_at_mut(x, y, slot),
+            })
+        } else {
+            Entry::Vacant(VacantEntry {
+                tree,
+                x,
+                y,
+                data: PhantomData,
+            })
+        }
+    }
+
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> T) -> &'a mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut T)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, T> OccupiedEntry<'a, T> {
+    pub fn point(&self) -> &Point2D<T> {
+        self.point
+    }
+
+    pub fn get(&self) -> &T {
+        &self.point.data
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.point.data
+    }
+
+    pub fn into_mut(self) -> &'a mut T {
+        &mut self.point.data
+    }
+}
+
+impl<'a, T, Q: EntryTree<T>> VacantEntry<'a, T, Q> {
+    pub fn insert(self, data: T) -> &'a mut T {
+        let (x, y) = (self.x, self.y);
+        // the coordinates were validated when the entry was created
+        let slot = self
+            .tree
+            .insert_point(Point2D { x, y, data })
+            .expect("entry coordinates are inside the tree");
+        &mut self.tree.point_at_mut(x, y, slot).data
+    }
+}
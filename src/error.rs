@@ -0,0 +1,90 @@
+use std::fmt;
+
+use crate::geometry::Point2D;
+
+/// Why a point was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The point lies outside the boundary of the tree.
+    OutOfBounds,
+    /// A coordinate of the point is NaN or infinite.
+    InvalidCoordinates,
+}
+
+impl RejectReason {
+    // the reason a point at (x, y) can't be stored in `boundary`, if any
+    pub(crate) fn check(boundary: &crate::Rectangle, x: f64, y: f64) -> Result<(), RejectReason> {
+        if !x.is_finite() || !y.is_finite() {
+            return Err(RejectReason::InvalidCoordinates);
+        }
+        if !boundary.contains(x, y) {
+            return Err(RejectReason::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::OutOfBounds => write!(f, "Boundary doesn't contain point"),
+            RejectReason::InvalidCoordinates => write!(f, "Point coordinates must be finite"),
+        }
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
+/// A failed insert, handing back the rejected point so it isn't lost, e.g.
+/// to retry it in a larger tree.
+pub struct InsertError<T> {
+    pub point: Point2D<T>,
+    pub reason: RejectReason,
+}
+
+impl<T> InsertError<T> {
+    // hands `point` back if it can't be stored in `boundary`
+    pub(crate) fn check(
+        boundary: &crate::Rectangle,
+        point: Point2D<T>,
+    ) -> Result<Point2D<T>, InsertError<T>> {
+        match RejectReason::check(boundary, point.x, point.y) {
+            Ok(()) => Ok(point),
+            Err(reason) => Err(InsertError { point, reason }),
+        }
+    }
+
+    /// Converts the payload of the rejected point, e.g. to strip a wrapper
+    /// an index added around it.
+    pub fn map_data<U>(self, f: impl FnOnce(T) -> U) -> InsertError<U> {
+        InsertError {
+            point: self.point.map_data(f),
+            reason: self.reason,
+        }
+    }
+}
+
+impl<T> From<InsertError<T>> for RejectReason {
+    fn from(error: InsertError<T>) -> Self {
+        error.reason
+    }
+}
+
+// written out, so payloads don't need to implement Debug
+impl<T> fmt::Debug for InsertError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InsertError")
+            .field("x", &self.point.x)
+            .field("y", &self.point.y)
+            .field("reason", &self.reason)
+            .finish()
+    }
+}
+
+impl<T> fmt::Display for InsertError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at ({}, {})", self.reason, self.point.x, self.point.y)
+    }
+}
+
+impl<T> std::error::Error for InsertError<T> {}
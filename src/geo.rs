@@ -0,0 +1,93 @@
+use geo_types::{coord, Point, Rect};
+
+use crate::geometry::{Point2D, Rectangle};
+
+impl From<Rectangle> for Rect<f64> {
+    fn from(rectangle: Rectangle) -> Self {
+        Rect::new(
+            coord! { x: rectangle.x, y: rectangle.y },
+            coord! { x: rectangle.x + rectangle.width, y: rectangle.y + rectangle.height },
+        )
+    }
+}
+
+impl From<Rect<f64>> for Rectangle {
+    fn from(rect: Rect<f64>) -> Self {
+        // geo normalizes the corners, so min() is always the top-left one
+        Rectangle::new(rect.min().x, rect.min().y, rect.width(), rect.height())
+    }
+}
+
+impl<T> From<Point2D<T>> for Point<f64> {
+    fn from(point: Point2D<T>) -> Self {
+        Point::new(point.x, point.y)
+    }
+}
+
+impl<T> From<&Point2D<T>> for Point<f64> {
+    fn from(point: &Point2D<T>) -> Self {
+        Point::new(point.x, point.y)
+    }
+}
+
+impl From<Point<f64>> for Point2D<()> {
+    fn from(point: Point<f64>) -> Self {
+        Point2D {
+            x: point.x(),
+            y: point.y(),
+            data: (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::{coord, Point, Rect};
+
+    use crate::{Point2D, QuadTree, SparseQuadTree, Rectangle};
+
+    #[test]
+    fn it_converts_rectangles() {
+        let rect: Rect<f64> = Rectangle::new(10.0, 20.0, 30.0, 40.0).into();
+        assert_eq!(rect.min(), coord! { x: 10.0, y: 20.0 });
+        assert_eq!(rect.max(), coord! { x: 40.0, y: 60.0 });
+
+        let rect = Rect::new(coord! { x: 40.0, y: 60.0 }, coord! { x: 10.0, y: 20.0 });
+        let rectangle: Rectangle = rect.into();
+        assert_eq!(rectangle.x, 10.0);
+        assert_eq!(rectangle.y, 20.0);
+        assert_eq!(rectangle.width, 30.0);
+        assert_eq!(rectangle.height, 40.0);
+    }
+
+    #[test]
+    fn it_converts_points() {
+        let point: Point<f64> = Point2D { x: 1.0, y: 2.0, data: 42 }.into();
+        assert_eq!(point, Point::new(1.0, 2.0));
+
+        let point: Point2D<()> = Point::new(3.0, 4.0).into();
+        assert_eq!(point.x, 3.0);
+        assert_eq!(point.y, 4.0);
+    }
+
+    #[test]
+    fn it_queries_with_geo_rects() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut sparse = SparseQuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..10 {
+            let point = Point2D {
+                x: 10.0 * i as f64,
+                y: 10.0 * i as f64,
+                data: i,
+            };
+            quadtree.insert(point)?;
+            sparse.insert(point)?;
+        }
+
+        let region = Rect::new(coord! { x: 15.0, y: 15.0 }, coord! { x: 45.0, y: 45.0 });
+        assert_eq!(quadtree.query(region).len(), 3);
+        assert_eq!(sparse.query(region).len(), 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+use crate::metric::Haversine;
+use crate::quadtree::QuadTree;
+
+/// Mean earth radius in meters, the sphere `haversine_distance` measures on.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Great-circle distance in meters between two (longitude, latitude)
+/// positions given in degrees.
+pub fn haversine_distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = phi2 - phi1;
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+}
+
+// longitude moved into [-180, 180)
+pub(crate) fn wrap_longitude(lon: f64) -> f64 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// A quadtree over the globe with x as longitude and y as latitude, both in
+/// degrees. Longitudes wrap around at ±180°, so boxes and circles crossing
+/// the antimeridian find points on both sides. Distances are great-circle
+/// distances in meters.
+#[derive(Debug)]
+pub struct GeoQuadTree<T> {
+    tree: QuadTree<T>,
+}
+
+impl<T> Default for GeoQuadTree<T> {
+    fn default() -> Self {
+        GeoQuadTree::new()
+    }
+}
+
+impl<T> GeoQuadTree<T> {
+    pub fn new() -> Self {
+        GeoQuadTree {
+            tree: QuadTree::new(Rectangle::new(-180.0, -90.0, 360.0, 180.0)),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.tree.count()
+    }
+
+    /// Stores the point with its longitude wrapped into [-180, 180). Fails
+    /// for latitudes outside [-90, 90].
+    pub fn insert(&mut self, mut point: Point2D<T>) -> Result<(), InsertError<T>> {
+        if point.x.is_finite() {
+            point.x = wrap_longitude(point.x);
+        }
+        self.tree.insert(point)
+    }
+
+    /// Points in the box from `west` to `east` and `south` to `north`. A box
+    /// whose `west` lies east of `east` crosses the antimeridian.
+    pub fn query_box(&self, west: f64, south: f64, east: f64, north: f64) -> Vec<&Point2D<T>> {
+        let mut result = Vec::new();
+        for (west, east) in Self::longitude_ranges(west, east) {
+            result.extend(self.tree.query(Rectangle::new(west, south, east - west, north - south)));
+        }
+        result
+    }
+
+    /// Points at most `meters` away from (lon, lat) with their distance,
+    /// nearest first.
+    pub fn query_radius(&self, lon: f64, lat: f64, meters: f64) -> Vec<(&Point2D<T>, f64)> {
+        self.tree.query_radius_by(lon, lat, meters, &Haversine)
+    }
+
+    /// The `k` points nearest to (lon, lat) with their distance, nearest
+    /// first.
+    pub fn k_nearest(&self, lon: f64, lat: f64, k: usize) -> Vec<(&Point2D<T>, f64)> {
+        self.tree.k_nearest_by(lon, lat, k, &Haversine)
+    }
+
+    // the box from `west` to `east` as up to two ranges within [-180, 180]
+    fn longitude_ranges(west: f64, east: f64) -> Vec<(f64, f64)> {
+        if east - west >= 360.0 {
+            return vec![(-180.0, 180.0)];
+        }
+        let (west, east) = (wrap_longitude(west), wrap_longitude(east));
+        if west <= east {
+            vec![(west, east)]
+        } else {
+            vec![(west, 180.0), (-180.0, east)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_measures_great_circle_distances() {
+        let quarter = haversine_distance(0.0, 0.0, 90.0, 0.0);
+        assert!((quarter - EARTH_RADIUS * std::f64::consts::FRAC_PI_2).abs() < 1e-6);
+        let across = haversine_distance(179.9, 0.0, -179.9, 0.0);
+        assert!((across - haversine_distance(0.0, 0.0, 0.2, 0.0)).abs() < 1e-6);
+        assert!(across < 25_000.0);
+    }
+
+    #[test]
+    fn it_wraps_around_the_antimeridian() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = GeoQuadTree::new();
+        tree.insert(Point2D { x: 179.9, y: 10.0, data: "east" })?;
+        tree.insert(Point2D { x: -179.9, y: 10.0, data: "west" })?;
+        tree.insert(Point2D { x: 190.0, y: 10.0, data: "wrapped" })?;
+        tree.insert(Point2D { x: 0.0, y: 10.0, data: "greenwich" })?;
+        assert!(tree.insert(Point2D { x: 0.0, y: 95.0, data: "nowhere" }).is_err());
+        assert_eq!(tree.count(), 4);
+
+        let near: Vec<&str> =
+            tree.query_radius(180.0, 10.0, 30_000.0).iter().map(|(p, _)| p.data).collect();
+        assert_eq!(near.len(), 2);
+        assert!(near.contains(&"east") && near.contains(&"west"));
+
+        let mut boxed: Vec<&str> =
+            tree.query_box(170.0, 0.0, -170.0, 20.0).iter().map(|p| p.data).collect();
+        boxed.sort();
+        assert_eq!(boxed, vec!["east", "west", "wrapped"]);
+
+        let nearest = tree.k_nearest(-179.0, 10.0, 3);
+        let names: Vec<&str> = nearest.iter().map(|(p, _)| p.data).collect();
+        assert_eq!(names, vec!["west", "east", "wrapped"]);
+        assert!(nearest[0].1 < nearest[1].1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_covers_every_longitude_near_the_poles() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = GeoQuadTree::new();
+        tree.insert(Point2D { x: 0.0, y: 89.5, data: 0 })?;
+        tree.insert(Point2D { x: 180.0, y: 89.5, data: 1 })?;
+        // the circle reaches over the pole to the other side
+        assert_eq!(tree.query_radius(90.0, 89.9, 100_000.0).len(), 2);
+        assert_eq!(tree.k_nearest(0.0, 89.5, 5).len(), 2);
+
+        Ok(())
+    }
+}
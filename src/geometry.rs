@@ -1,76 +1,214 @@
+//! Geometric primitives: rectangles, points, circles and line segments, with
+//! intersection predicates between them.
+
+mod circle;
+mod line;
+mod point;
+mod rect;
+
+pub use circle::Circle;
+pub use line::LineSegment;
+pub use point::{Point2D, PointMut};
+pub use rect::{Quadrant, QueryEdges, Rectangle, SplitStrategy};
+
+// the comma-separated numbers of "1.5, 2, ..." as written by the Display
+// impls of points and rectangles, whitespace around them is ignored
+pub(crate) fn parse_coordinates<const N: usize>(text: &str) -> Result<[f64; N], &'static str> {
+    let mut values = [0.0; N];
+    let mut parts = text.split(',');
+    for value in values.iter_mut() {
+        let part = parts.next().ok_or("Too few comma-separated values")?;
+        *value = part.trim().parse().map_err(|_| "Invalid number")?;
+    }
+    if parts.next().is_some() {
+        return Err("Too many comma-separated values");
+    }
+    Ok(values)
+}
+
+// grows the (min_x, min_y, max_x, max_y) extent to include the point
+pub(crate) fn extend_extent<T>(
+    extent: Option<(f64, f64, f64, f64)>,
+    point: &Point2D<T>,
+) -> Option<(f64, f64, f64, f64)> {
+    let (min_x, min_y, max_x, max_y) = extent.unwrap_or((point.x, point.y, point.x, point.y));
+    Some((min_x.min(point.x), min_y.min(point.y), max_x.max(point.x), max_y.max(point.y)))
+}
+
+pub(crate) fn extent_to_rectangle(extent: (f64, f64, f64, f64)) -> Rectangle {
+    let (min_x, min_y, max_x, max_y) = extent;
+    Rectangle::new(min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+pub(crate) fn merge_extents(
+    a: Option<(f64, f64, f64, f64)>,
+    b: Option<(f64, f64, f64, f64)>,
+) -> Option<(f64, f64, f64, f64)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))),
+        (a, b) => a.or(b),
+    }
+}
+
+// like `extent_to_rectangle`, but grown where rounding the size would leave
+// the far edges short of the extent, so no point of it falls outside
+pub(crate) fn covering_rectangle(extent: (f64, f64, f64, f64)) -> Rectangle {
+    let mut rectangle = extent_to_rectangle(extent);
+    while rectangle.x + rectangle.width < extent.2 {
+        rectangle.width = rectangle.width.next_up();
+    }
+    while rectangle.y + rectangle.height < extent.3 {
+        rectangle.height = rectangle.height.next_up();
+    }
+    rectangle
+}
+
+// a box around the extent whose edges and size are multiples of powers of
+// two along both axes, so halving it into quadrants is exact at every depth
+// and no point falls into a gap between computed quadrant edges
+pub(crate) fn aligned_rectangle(extent: (f64, f64, f64, f64)) -> Rectangle {
+    let (min_x, min_y, max_x, max_y) = extent;
+    let (x, width) = aligned_interval(min_x, max_x);
+    let (y, height) = aligned_interval(min_y, max_y);
+    Rectangle::new(x, y, width, height)
+}
+
+// an interval covering `min..=max` that starts at a multiple of a power of
+// two `size` at least as long as the extent and spans one or two of it
+fn aligned_interval(min: f64, max: f64) -> (f64, f64) {
+    if !(max - min).is_finite() {
+        return (min, max - min);
+    }
+    let mut size = if max > min {
+        2f64.powi((max - min).log2().ceil() as i32)
+    } else {
+        1.0
+    };
+    // log2 may round down right above a power of two
+    if size < max - min {
+        size *= 2.0;
+    }
+    let mut start = (min / size).floor() * size;
+    if start > min {
+        start -= size;
+    }
+    if start + size >= max {
+        (start, size)
+    } else {
+        (start, 2.0 * size)
+    }
+}
+
+// the positions a node holds points at: between the lines its ancestors
+// split at, and up to the root's boundary. Points on a split line belong to
+// the east or south side, so only the root's east and south edges are owned
+// from both sides. Unlike node boundaries, whose edges are computed and may
+// be off by rounding, cells follow insert's routing exactly.
 #[derive(Debug, Clone, Copy)]
-pub struct Rectangle {
-    pub x: f64,
-    pub y: f64,
-    pub width: f64,
-    pub height: f64,
+pub(crate) struct Cell {
+    min: (f64, f64),
+    max: (f64, f64),
+    // whether the east and south edges belong to the cell
+    closed: (bool, bool),
 }
 
-impl Rectangle {
-    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
-        Rectangle {
-            x,
-            y,
-            width,
-            height,
+impl Cell {
+    pub(crate) fn root(boundary: &Rectangle) -> Cell {
+        Cell {
+            min: (boundary.x, boundary.y),
+            max: (boundary.x + boundary.width, boundary.y + boundary.height),
+            closed: (true, true),
+        }
+    }
+
+    // the cell of the child in `quadrant` of a node splitting at `split`
+    pub(crate) fn child(&self, quadrant: Quadrant, split: (f64, f64)) -> Cell {
+        let (east, south) = match quadrant {
+            Quadrant::NE => (true, false),
+            Quadrant::SE => (true, true),
+            Quadrant::SW => (false, true),
+            Quadrant::NW => (false, false),
+        };
+        let (min_x, max_x) = if east { (split.0, self.max.0) } else { (self.min.0, split.0) };
+        let (min_y, max_y) = if south { (split.1, self.max.1) } else { (self.min.1, split.1) };
+        Cell {
+            min: (min_x, min_y),
+            max: (max_x, max_y),
+            closed: (east && self.closed.0, south && self.closed.1),
         }
     }
 
-    pub fn contains(&self, x: f64, y: f64) -> bool {
-        x >= self.x &&
-        x <= self.x + self.width &&
-        y >= self.y &&
-        y <= self.y + self.height
-    }
-
-    pub fn new_nw(&self) -> Rectangle {
-        // x.
-        // ..
-        Rectangle::new(
-            self.x,
-            self.y,
-            self.width / 2.0,
-            self.height / 2.0
-        )
-    }
-
-    pub fn new_ne(&self) -> Rectangle {
-        // .x
-        // ..
-        Rectangle::new(
-            self.x + self.width / 2.0,
-            self.y,
-            self.width / 2.0,
-            self.height / 2.0,
-        )
-    }
-
-    pub fn new_sw(&self) -> Rectangle {
-        // ..
-        // x.
-        Rectangle::new(
-            self.x,
-            self.y + self.height / 2.0,
-            self.width / 2.0,
-            self.height / 2.0,
-        )
-    }
-
-    pub fn new_se(&self) -> Rectangle {
-        // ..
-        // .x
-        Rectangle::new(
-            self.x + self.width / 2.0,
-            self.y + self.height / 2.0,
-            self.width / 2.0,
-            self.height / 2.0,
-        )
+    pub(crate) fn owns(&self, x: f64, y: f64) -> bool {
+        x >= self.min.0
+            && y >= self.min.1
+            && (x < self.max.0 || (self.closed.0 && x == self.max.0))
+            && (y < self.max.1 || (self.closed.1 && y == self.max.1))
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Point2D<T: std::fmt::Debug> {
-    pub x: f64,
-    pub y: f64,
-    pub data: T,
+// moves the points with `f` and those `cell` no longer owns into `stray`
+pub(crate) fn remap_points<T>(
+    points: &mut Vec<Point2D<T>>,
+    cell: &Cell,
+    f: &impl Fn(f64, f64) -> (f64, f64),
+    stray: &mut Vec<Point2D<T>>,
+) {
+    for point in points.iter_mut() {
+        (point.x, point.y) = f(point.x, point.y);
+    }
+    // rounding rarely moves a point across an edge
+    if points.iter().any(|point| !cell.owns(point.x, point.y)) {
+        let (kept, moved) = std::mem::take(points)
+            .into_iter()
+            .partition(|point| cell.owns(point.x, point.y));
+        *points = kept;
+        stray.extend::<Vec<_>>(moved);
+    }
+}
+
+// the position a node splits at after its boundary was mapped from `old` to
+// `new` with `f`. Nodes split at their center stay split there.
+pub(crate) fn remap_split(
+    split: (f64, f64),
+    old: &Rectangle,
+    new: &Rectangle,
+    f: &impl Fn(f64, f64) -> (f64, f64),
+) -> (f64, f64) {
+    if split == old.center() {
+        return new.center();
+    }
+    let (x, y) = f(split.0, split.1);
+    (x.clamp(new.x, new.x + new.width), y.clamp(new.y, new.y + new.height))
+}
+
+// raster of `cols` x `rows` equal cells over `region`. Cells are half-open,
+// except the last column and row which also own the region's far edges.
+pub(crate) struct Grid {
+    pub(crate) region: Rectangle,
+    pub(crate) cols: usize,
+    pub(crate) rows: usize,
+}
+
+impl Grid {
+    fn index(value: f64, origin: f64, extent: f64, cells: usize) -> usize {
+        let cell = ((value - origin) / extent * cells as f64).floor();
+        (cell.max(0.0) as usize).min(cells - 1)
+    }
+
+    // row-major index of the cell containing (x, y)
+    pub(crate) fn cell(&self, x: f64, y: f64) -> Option<usize> {
+        if !self.region.contains(x, y) {
+            return None;
+        }
+        let col = Grid::index(x, self.region.x, self.region.width, self.cols);
+        let row = Grid::index(y, self.region.y, self.region.height, self.rows);
+        Some(row * self.cols + col)
+    }
+
+    // the single cell containing every point of `rectangle`, if there is one
+    pub(crate) fn enclosing_cell(&self, rectangle: &Rectangle) -> Option<usize> {
+        let first = self.cell(rectangle.x, rectangle.y)?;
+        let last = self.cell(rectangle.x + rectangle.width, rectangle.y + rectangle.height)?;
+        (first == last).then_some(first)
+    }
 }
@@ -0,0 +1,198 @@
+use super::{LineSegment, Rectangle};
+
+/// A circle around (x, y). Like for rectangles, the border counts as inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+}
+
+impl Circle {
+    pub fn new(x: f64, y: f64, radius: f64) -> Self {
+        Circle { x, y, radius }
+    }
+
+    /// Like `new`, but rejects NaN or infinite values and negative radii.
+    pub fn try_new(x: f64, y: f64, radius: f64) -> Result<Self, &'static str> {
+        let circle = Circle::new(x, y, radius);
+        if !circle.is_valid() {
+            return Err("Circle must be finite and have a non-negative radius");
+        }
+        Ok(circle)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.radius.is_finite() && self.radius >= 0.0
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let dx = x - self.x;
+        let dy = y - self.y;
+        dx * dx + dy * dy <= self.radius * self.radius
+    }
+
+    pub fn contains_rectangle(&self, rectangle: &Rectangle) -> bool {
+        rectangle.contained_in_circle(self.x, self.y, self.radius)
+    }
+
+    pub fn intersects(&self, other: &Circle) -> bool {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        let reach = self.radius + other.radius;
+        self.radius >= 0.0 && other.radius >= 0.0 && dx * dx + dy * dy <= reach * reach
+    }
+
+    pub fn intersects_rectangle(&self, rectangle: &Rectangle) -> bool {
+        rectangle.intersects_circle(self.x, self.y, self.radius)
+    }
+
+    pub fn intersects_segment(&self, segment: &LineSegment) -> bool {
+        segment.intersects_circle(self)
+    }
+
+    /// The smallest rectangle containing the circle.
+    pub fn bounding_rectangle(&self) -> Rectangle {
+        Rectangle::new(
+            self.x - self.radius,
+            self.y - self.radius,
+            2.0 * self.radius,
+            2.0 * self.radius,
+        )
+    }
+
+    /// The smallest circle containing all positions, `None` without any.
+    /// Welzl's algorithm, in expected linear time as the positions are
+    /// visited in shuffled order.
+    pub fn enclosing(positions: impl IntoIterator<Item = (f64, f64)>) -> Option<Circle> {
+        let mut positions: Vec<(f64, f64)> = positions.into_iter().collect();
+        // a fixed xorshift sequence keeps the result reproducible
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for i in (1..positions.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            positions.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        let &(x, y) = positions.first()?;
+        let mut circle = Circle::new(x, y, 0.0);
+        for i in 1..positions.len() {
+            let a = positions[i];
+            if covers(&circle, a) {
+                continue;
+            }
+            circle = Circle::new(a.0, a.1, 0.0);
+            for j in 0..i {
+                let b = positions[j];
+                if covers(&circle, b) {
+                    continue;
+                }
+                circle = diameter(a, b);
+                for &c in positions[..j].iter() {
+                    if !covers(&circle, c) {
+                        circle = circumcircle(a, b, c);
+                    }
+                }
+            }
+        }
+        // rounding may leave positions on the border just outside
+        for &(x, y) in positions.iter() {
+            circle.radius = circle.radius.max((x - circle.x).hypot(y - circle.y));
+            while !circle.contains(x, y) {
+                circle.radius = circle.radius.next_up();
+            }
+        }
+        Some(circle)
+    }
+}
+
+// with some slack for positions the circle was constructed through
+fn covers(circle: &Circle, (x, y): (f64, f64)) -> bool {
+    (x - circle.x).hypot(y - circle.y) <= circle.radius * (1.0 + 1e-12)
+}
+
+fn diameter(a: (f64, f64), b: (f64, f64)) -> Circle {
+    let (x, y) = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    Circle::new(x, y, (a.0 - x).hypot(a.1 - y).max((b.0 - x).hypot(b.1 - y)))
+}
+
+// the circle through all three positions. Collinear ones have none, the
+// circle around the two farthest apart contains the third one then.
+fn circumcircle(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> Circle {
+    let (bx, by) = (b.0 - a.0, b.1 - a.1);
+    let (cx, cy) = (c.0 - a.0, c.1 - a.1);
+    let d = 2.0 * (bx * cy - by * cx);
+    let b2 = bx * bx + by * by;
+    let c2 = cx * cx + cy * cy;
+    let (ux, uy) = ((cy * b2 - by * c2) / d, (bx * c2 - cx * b2) / d);
+    if !(ux.is_finite() && uy.is_finite()) {
+        return [diameter(a, b), diameter(a, c), diameter(b, c)]
+            .into_iter()
+            .max_by(|p, q| p.radius.total_cmp(&q.radius))
+            .unwrap_or(diameter(a, b));
+    }
+    let (x, y) = (a.0 + ux, a.1 + uy);
+    let radius = [a, b, c].iter().map(|p| (p.0 - x).hypot(p.1 - y)).fold(0.0, f64::max);
+    Circle::new(x, y, radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_intersects_circles_with_other_shapes() {
+        let circle = Circle::new(0.0, 0.0, 5.0);
+        assert!(circle.contains(3.0, 4.0));
+        assert!(!circle.contains(3.0, 4.1));
+        assert!(Circle::try_new(0.0, 0.0, -1.0).is_err());
+        assert!(Circle::try_new(f64::NAN, 0.0, 1.0).is_err());
+
+        assert!(circle.intersects(&Circle::new(8.0, 0.0, 3.0)));
+        assert!(!circle.intersects(&Circle::new(8.0, 0.0, 2.9)));
+
+        assert!(circle.intersects_rectangle(&Rectangle::new(4.0, 0.0, 10.0, 10.0)));
+        assert!(!circle.intersects_rectangle(&Rectangle::new(4.0, 4.0, 10.0, 10.0)));
+        assert!(circle.contains_rectangle(&Rectangle::new(-3.0, -4.0, 6.0, 8.0)));
+        assert!(!circle.contains_rectangle(&Rectangle::new(-3.0, -4.0, 6.0, 8.5)));
+
+        assert!(circle.intersects_segment(&LineSegment::new(-10.0, 4.0, 10.0, 4.0)));
+        assert!(!circle.intersects_segment(&LineSegment::new(-10.0, 6.0, 10.0, 6.0)));
+
+        assert_eq!(circle.bounding_rectangle(), Rectangle::new(-5.0, -5.0, 10.0, 10.0));
+    }
+
+    #[test]
+    fn it_finds_the_smallest_enclosing_circle() {
+        assert_eq!(Circle::enclosing([]), None);
+        assert_eq!(Circle::enclosing([(1.0, 2.0)]), Some(Circle::new(1.0, 2.0, 0.0)));
+
+        // the corners of a square lie on the circle, its center inside
+        let square = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (2.0, 2.0)];
+        let circle = Circle::enclosing(square).unwrap();
+        assert!((circle.x - 2.0).abs() < 1e-9 && (circle.y - 2.0).abs() < 1e-9);
+        assert!((circle.radius - 8f64.sqrt()).abs() < 1e-9);
+
+        // an obtuse triangle is enclosed by the circle over its longest side
+        let circle = Circle::enclosing([(0.0, 0.0), (10.0, 0.0), (5.0, 1.0)]).unwrap();
+        assert!((circle.x - 5.0).abs() < 1e-9 && circle.y.abs() < 1e-9);
+        assert!((circle.radius - 5.0).abs() < 1e-9);
+
+        // collinear and repeated positions
+        let line = [(1.0, 1.0), (3.0, 3.0), (2.0, 2.0), (3.0, 3.0), (1.0, 1.0)];
+        let circle = Circle::enclosing(line).unwrap();
+        assert!((circle.radius - 2f64.sqrt()).abs() < 1e-9);
+
+        // a ring of positions around a noisy center
+        let ring: Vec<(f64, f64)> = (0..500)
+            .map(|i| {
+                let angle = i as f64 * 0.7;
+                let r = if i % 5 == 0 { 3.0 } else { (i % 3) as f64 };
+                (10.0 + r * angle.cos(), -4.0 + r * angle.sin())
+            })
+            .collect();
+        let circle = Circle::enclosing(ring.iter().copied()).unwrap();
+        assert!(ring.iter().all(|&(x, y)| circle.contains(x, y)));
+        assert!(circle.radius <= 3.0 + 1e-9);
+    }
+}
@@ -0,0 +1,123 @@
+use super::{Circle, Rectangle};
+
+/// The straight segment between (x1, y1) and (x2, y2), both ends included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub x1: f64,
+    pub y1: f64,
+    pub x2: f64,
+    pub y2: f64,
+}
+
+// twice the signed area of the triangle (a, b, c), positive if c lies left of
+// the line from a to b and zero if the three points are collinear
+fn cross(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+impl LineSegment {
+    pub fn new(x1: f64, y1: f64, x2: f64, y2: f64) -> Self {
+        LineSegment { x1, y1, x2, y2 }
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.x2 - self.x1).hypot(self.y2 - self.y1)
+    }
+
+    /// Squared distance from (x, y) to the closest point of the segment.
+    pub fn distance_squared(&self, x: f64, y: f64) -> f64 {
+        let dx = self.x2 - self.x1;
+        let dy = self.y2 - self.y1;
+        let length_squared = dx * dx + dy * dy;
+        // position of the closest point along the segment, 0 at the start
+        let t = if length_squared > 0.0 {
+            (((x - self.x1) * dx + (y - self.y1) * dy) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let px = self.x1 + t * dx - x;
+        let py = self.y1 + t * dy - y;
+        px * px + py * py
+    }
+
+    pub fn intersects(&self, other: &LineSegment) -> bool {
+        let (a, b) = ((self.x1, self.y1), (self.x2, self.y2));
+        let (c, d) = ((other.x1, other.y1), (other.x2, other.y2));
+        let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+        let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+        if d1 == 0.0 && d2 == 0.0 && d3 == 0.0 && d4 == 0.0 {
+            // all on one line, they touch if their extents overlap
+            return self.bounding_rectangle().intersects(&other.bounding_rectangle());
+        }
+        d1 * d2 <= 0.0 && d3 * d4 <= 0.0
+    }
+
+    pub fn intersects_rectangle(&self, rectangle: &Rectangle) -> bool {
+        if rectangle.contains(self.x1, self.y1) || rectangle.contains(self.x2, self.y2) {
+            return true;
+        }
+        let (left, top) = (rectangle.x, rectangle.y);
+        let (right, bottom) = (left + rectangle.width, top + rectangle.height);
+        [
+            LineSegment::new(left, top, right, top),
+            LineSegment::new(right, top, right, bottom),
+            LineSegment::new(left, bottom, right, bottom),
+            LineSegment::new(left, top, left, bottom),
+        ]
+        .iter()
+        .any(|edge| self.intersects(edge))
+    }
+
+    pub fn intersects_circle(&self, circle: &Circle) -> bool {
+        circle.radius >= 0.0
+            && self.distance_squared(circle.x, circle.y) <= circle.radius * circle.radius
+    }
+
+    /// The smallest rectangle containing the segment.
+    pub fn bounding_rectangle(&self) -> Rectangle {
+        let x = self.x1.min(self.x2);
+        let y = self.y1.min(self.y2);
+        Rectangle::new(x, y, (self.x2 - self.x1).abs(), (self.y2 - self.y1).abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_measures_segments() {
+        let segment = LineSegment::new(0.0, 0.0, 4.0, 0.0);
+        assert_eq!(segment.length(), 4.0);
+        assert_eq!(segment.distance_squared(2.0, 3.0), 9.0);
+        assert_eq!(segment.distance_squared(-3.0, 4.0), 25.0);
+        assert_eq!(segment.distance_squared(5.0, 0.0), 1.0);
+        assert_eq!(LineSegment::new(1.0, 1.0, 1.0, 1.0).distance_squared(1.0, 2.0), 1.0);
+        assert_eq!(
+            LineSegment::new(4.0, 3.0, 0.0, 1.0).bounding_rectangle(),
+            Rectangle::new(0.0, 1.0, 4.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn it_intersects_segments_with_other_shapes() {
+        let segment = LineSegment::new(0.0, 0.0, 4.0, 4.0);
+        assert!(segment.intersects(&LineSegment::new(0.0, 4.0, 4.0, 0.0)));
+        assert!(segment.intersects(&LineSegment::new(4.0, 4.0, 6.0, 0.0)));
+        assert!(!segment.intersects(&LineSegment::new(1.0, 0.0, 5.0, 4.0)));
+        // collinear segments
+        assert!(segment.intersects(&LineSegment::new(3.0, 3.0, 6.0, 6.0)));
+        assert!(!segment.intersects(&LineSegment::new(5.0, 5.0, 6.0, 6.0)));
+        // degenerate segments are points
+        assert!(segment.intersects(&LineSegment::new(2.0, 2.0, 2.0, 2.0)));
+        assert!(!segment.intersects(&LineSegment::new(2.0, 3.0, 2.0, 3.0)));
+
+        assert!(segment.intersects_rectangle(&Rectangle::new(1.0, 1.0, 0.5, 0.5)));
+        assert!(segment.intersects_rectangle(&Rectangle::new(-1.0, 1.0, 3.0, 0.5)));
+        assert!(segment.intersects_rectangle(&Rectangle::new(-10.0, -10.0, 20.0, 20.0)));
+        assert!(!segment.intersects_rectangle(&Rectangle::new(3.0, 0.0, 1.0, 1.0)));
+
+        assert!(segment.intersects_circle(&Circle::new(4.0, 0.0, 8.0_f64.sqrt())));
+        assert!(!segment.intersects_circle(&Circle::new(4.0, 0.0, 2.8)));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::geometry;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Point2D<T> {
+    pub x: f64,
+    pub y: f64,
+    pub data: T,
+}
+
+impl<T> Point2D<T> {
+    pub fn new(x: f64, y: f64, data: T) -> Self {
+        Point2D { x, y, data }
+    }
+
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    /// The coordinates as an (x, y) tuple.
+    pub fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    /// Squared euclidean distance, cheaper than `distance_to` and enough to
+    /// compare distances. The payloads don't matter.
+    pub fn distance_squared_to<U>(&self, other: &Point2D<U>) -> f64 {
+        let dx = self.x - other.x;
+        let dy = self.y - other.y;
+        dx * dx + dy * dy
+    }
+
+    pub(crate) fn distance_squared_to_position(&self, x: f64, y: f64) -> f64 {
+        let dx = self.x - x;
+        let dy = self.y - y;
+        dx * dx + dy * dy
+    }
+
+    pub fn distance_to<U>(&self, other: &Point2D<U>) -> f64 {
+        self.distance_squared_to(other).sqrt()
+    }
+
+    /// The same point with its payload transformed by `f`.
+    pub fn map_data<U>(self, f: impl FnOnce(T) -> U) -> Point2D<U> {
+        Point2D {
+            x: self.x,
+            y: self.y,
+            data: f(self.data),
+        }
+    }
+}
+
+/// Writes the position as "x,y", leaving out the payload. Formatting
+/// options like a precision apply to both coordinates.
+impl<T> fmt::Display for Point2D<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.x, f)?;
+        f.write_str(",")?;
+        fmt::Display::fmt(&self.y, f)
+    }
+}
+
+/// Parses a coordinate pair "x,y" into a point without payload, attach one
+/// with `map_data`. Rejects NaN and infinite coordinates.
+impl FromStr for Point2D<()> {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let [x, y] = geometry::parse_coordinates(text)?;
+        if !x.is_finite() || !y.is_finite() {
+            return Err("Point coordinates must be finite");
+        }
+        Ok(Point2D::new(x, y, ()))
+    }
+}
+
+/// Mutable access to the payload of a stored point, handed out by
+/// `query_mut`. The position stays read-only, so a point can't be moved out
+/// of the node that stores it.
+#[derive(Debug)]
+pub struct PointMut<'a, T> {
+    point: &'a mut Point2D<T>,
+}
+
+impl<'a, T> PointMut<'a, T> {
+    pub(crate) fn new(point: &'a mut Point2D<T>) -> Self {
+        PointMut { point }
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.point.data
+    }
+
+    /// The payload, borrowed for as long as the tree is.
+    pub fn into_data_mut(self) -> &'a mut T {
+        &mut self.point.data
+    }
+}
+
+impl<T> Deref for PointMut<'_, T> {
+    type Target = Point2D<T>;
+
+    fn deref(&self) -> &Point2D<T> {
+        self.point
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_and_measures_points() {
+        let mut point = Point2D::new(1.0, 2.0, "a");
+        assert_eq!(point.position(), (1.0, 2.0));
+        assert_eq!((point.x(), point.y(), *point.data()), (1.0, 2.0, "a"));
+        *point.data_mut() = "b";
+
+        let other = Point2D::new(4.0, 6.0, ());
+        assert_eq!(point.distance_squared_to(&other), 25.0);
+        assert_eq!(point.distance_to(&other), 5.0);
+
+        let mapped = point.map_data(|data| data.len());
+        assert_eq!((mapped.x, mapped.y, mapped.data), (1.0, 2.0, 1));
+        assert_eq!(mapped.into_data(), 1);
+    }
+
+    #[test]
+    fn it_formats_and_parses_coordinate_pairs() -> Result<(), Box<dyn std::error::Error>> {
+        let point = Point2D::new(1.25, -3.0, "payload");
+        assert_eq!(point.to_string(), "1.25,-3");
+        assert_eq!(format!("{:.1}", point), "1.2,-3.0");
+
+        let parsed: Point2D<()> = " 1.25 , -3 ".parse()?;
+        assert_eq!(parsed.position(), point.position());
+        assert_eq!(parsed.map_data(|_| 7).data, 7);
+        let point = Point2D::new(0.1 + 0.2, 1e-300, ());
+        assert_eq!(point.to_string().parse::<Point2D<()>>()?.position(), point.position());
+
+        for text in ["1", "1,2,3", "a,2", "NaN,2", "1,-inf", " , "] {
+            assert!(text.parse::<Point2D<()>>().is_err(), "{text}");
+        }
+
+        Ok(())
+    }
+}
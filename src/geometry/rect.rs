@@ -0,0 +1,527 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::geometry::{self, Point2D};
+
+/// One of the four quadrants a node splits into, with y growing southwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quadrant {
+    NE,
+    SE,
+    SW,
+    NW,
+}
+
+impl Quadrant {
+    /// All quadrants in the order children are stored and visited.
+    pub const ALL: [Quadrant; 4] = [Quadrant::NE, Quadrant::SE, Quadrant::SW, Quadrant::NW];
+
+    /// Position of the quadrant in `ALL`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // the quadrant of (x, y) around `split`, where the quadrants of a node
+    // meet. Points on the dividing lines belong to the east and south.
+    pub(crate) fn of(split: (f64, f64), x: f64, y: f64) -> Quadrant {
+        let east = x >= split.0;
+        let south = y >= split.1;
+        match (east, south) {
+            (true, false) => Quadrant::NE,
+            (true, true) => Quadrant::SE,
+            (false, true) => Quadrant::SW,
+            (false, false) => Quadrant::NW,
+        }
+    }
+}
+
+/// Where a node divides into its quadrants once it overflows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitStrategy {
+    /// At the center of the node's boundary.
+    #[default]
+    Midpoint,
+    /// At the median x and y of the node's points, which keeps trees over
+    /// heavily skewed data (e.g. clustered around a city center) balanced.
+    /// Falls back to the center along an axis where the median wouldn't
+    /// separate any points.
+    Median,
+}
+
+impl SplitStrategy {
+    // the position a node covering `boundary` splits at to share out `points`
+    pub(crate) fn split_point<T>(self, boundary: &Rectangle, points: &[Point2D<T>]) -> (f64, f64) {
+        let center = boundary.center();
+        if self == SplitStrategy::Midpoint || points.len() < 2 {
+            return center;
+        }
+        // the upper median, points below it go west or north
+        let median = |mut values: Vec<f64>, fallback: f64| {
+            let middle = values.len() / 2;
+            let (lower, median, _) = values.select_nth_unstable_by(middle, f64::total_cmp);
+            if lower.is_empty() || lower.iter().all(|value| value == median) {
+                // nothing lies strictly below the median
+                fallback
+            } else {
+                *median
+            }
+        };
+        (
+            median(points.iter().map(|point| point.x).collect(), center.0),
+            median(points.iter().map(|point| point.y).collect(), center.1),
+        )
+    }
+}
+
+/// Whether a region query includes points on the region's edges. Tiles of
+/// a grid share their edges, so querying each tile with `HalfOpen` returns
+/// every point on a seam exactly once. Tiles reaching the tree's right or
+/// bottom edge keep it, so points on the boundary are returned too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryEdges {
+    /// All four edges belong to the region.
+    #[default]
+    Inclusive,
+    /// The left and top edges belong to the region, the right and bottom
+    /// ones to the neighbouring tiles.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rectangle {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rectangle {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Like `new`, but rejects NaN or infinite values and negative sizes,
+    /// which would create a region that can never contain anything.
+    pub fn try_new(x: f64, y: f64, width: f64, height: f64) -> Result<Self, &'static str> {
+        let rectangle = Rectangle::new(x, y, width, height);
+        if !rectangle.is_valid() {
+            return Err("Rectangle must be finite and have a non-negative size");
+        }
+        Ok(rectangle)
+    }
+
+    /// Rectangle spanning two opposite corners, given in any order.
+    pub fn from_corners(x1: f64, y1: f64, x2: f64, y2: f64) -> Result<Self, &'static str> {
+        Rectangle::try_new(x1.min(x2), y1.min(y2), (x2 - x1).abs(), (y2 - y1).abs())
+    }
+
+    pub fn from_center(
+        cx: f64,
+        cy: f64,
+        half_width: f64,
+        half_height: f64,
+    ) -> Result<Self, &'static str> {
+        Rectangle::try_new(
+            cx - half_width,
+            cy - half_height,
+            2.0 * half_width,
+            2.0 * half_height,
+        )
+    }
+
+    pub fn is_valid(&self) -> bool {
+        [self.x, self.y, self.width, self.height]
+            .iter()
+            .all(|value| value.is_finite())
+            && self.width >= 0.0
+            && self.height >= 0.0
+    }
+
+    pub fn area(&self) -> f64 {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x &&
+        x <= self.x + self.width &&
+        y >= self.y &&
+        y <= self.y + self.height
+    }
+
+    /// The closest position to (x, y) inside the rectangle, (x, y) itself if
+    /// it is already inside.
+    pub fn clamp_point(&self, x: f64, y: f64) -> (f64, f64) {
+        (x.clamp(self.x, self.x + self.width), y.clamp(self.y, self.y + self.height))
+    }
+
+    /// Like `contains`, but excludes the right and bottom edges, so a grid of
+    /// adjacent rectangles claims every point exactly once.
+    pub fn contains_half_open(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// `contains` or `contains_half_open`, depending on `edges`.
+    pub fn contains_with_edges(&self, x: f64, y: f64, edges: QueryEdges) -> bool {
+        match edges {
+            QueryEdges::Inclusive => self.contains(x, y),
+            QueryEdges::HalfOpen => self.contains_half_open(x, y),
+        }
+    }
+
+    pub fn contains_rectangle(&self, other: &Rectangle) -> bool {
+        self.contains(other.x, other.y) && self.contains(other.x + other.width, other.y + other.height)
+    }
+
+    pub fn intersects(&self, other: &Rectangle) -> bool {
+        other.x <= self.x + self.width
+            && self.x <= other.x + other.width
+            && other.y <= self.y + self.height
+            && self.y <= other.y + other.height
+    }
+
+    pub fn distance_squared(&self, x: f64, y: f64) -> f64 {
+        // distance from the point to the closest point of the rectangle,
+        // zero if the point is inside
+        let dx = (self.x - x).max(0.0).max(x - (self.x + self.width));
+        let dy = (self.y - y).max(0.0).max(y - (self.y + self.height));
+        dx * dx + dy * dy
+    }
+
+    /// Whether any part of the rectangle lies within `r` of (cx, cy).
+    pub fn intersects_circle(&self, cx: f64, cy: f64, r: f64) -> bool {
+        r >= 0.0 && self.distance_squared(cx, cy) <= r * r
+    }
+
+    /// Whether the whole rectangle lies within `r` of (cx, cy), so every point
+    /// inside it is in the circle without checking them one by one.
+    pub fn contained_in_circle(&self, cx: f64, cy: f64, r: f64) -> bool {
+        r >= 0.0 && self.max_distance_squared(cx, cy) <= r * r
+    }
+
+    // squared distance from the point to the farthest corner of the rectangle
+    pub(crate) fn max_distance_squared(&self, x: f64, y: f64) -> f64 {
+        let dx = (x - self.x).abs().max((x - (self.x + self.width)).abs());
+        let dy = (y - self.y).abs().max((y - (self.y + self.height)).abs());
+        dx * dx + dy * dy
+    }
+
+    // smallest squared distance between any two points of the rectangles,
+    // zero if they intersect
+    pub(crate) fn distance_squared_to_rectangle(&self, other: &Rectangle) -> f64 {
+        let dx = (self.x - (other.x + other.width)).max(0.0).max(other.x - (self.x + self.width));
+        let dy = (self.y - (other.y + other.height)).max(0.0).max(other.y - (self.y + self.height));
+        dx * dx + dy * dy
+    }
+
+    /// The quadrant of the rectangle responsible for (x, y). Points on the
+    /// vertical center line belong to the east quadrants and points on the
+    /// horizontal center line to the south quadrants, so the center itself
+    /// is in `SE`.
+    pub fn which_quadrant(&self, x: f64, y: f64) -> Quadrant {
+        Quadrant::of(self.center(), x, y)
+    }
+
+    // where `which_quadrant` and `quadrant` divide the rectangle
+    pub(crate) fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+
+    // the rectangle between the images of the corners under `f`, which must
+    // keep the order of coordinates along each axis
+    pub(crate) fn mapped(&self, f: impl Fn(f64, f64) -> (f64, f64)) -> Rectangle {
+        let (x, y) = f(self.x, self.y);
+        let (east, south) = f(self.x + self.width, self.y + self.height);
+        Rectangle::new(x, y, east - x, south - y)
+    }
+
+    /// The boundary of one quadrant of the rectangle.
+    pub fn quadrant(&self, quadrant: Quadrant) -> Rectangle {
+        match quadrant {
+            Quadrant::NE => self.new_ne(),
+            Quadrant::SE => self.new_se(),
+            Quadrant::SW => self.new_sw(),
+            Quadrant::NW => self.new_nw(),
+        }
+    }
+
+    // like `quadrant` for quadrants meeting at `split` instead of the center.
+    // Their far edges are built from the edges of this rectangle and the
+    // split, so every point `Quadrant::of` sends to a quadrant lies inside it.
+    pub(crate) fn quadrant_at(&self, quadrant: Quadrant, split: (f64, f64)) -> Rectangle {
+        let (x, y) = split;
+        let (east, south) = (self.x + self.width, self.y + self.height);
+        match quadrant {
+            Quadrant::NE => Rectangle::spanning(x, self.y, east, y),
+            Quadrant::SE => Rectangle::spanning(x, y, east, south),
+            Quadrant::SW => Rectangle::spanning(self.x, y, x, south),
+            Quadrant::NW => Rectangle::spanning(self.x, self.y, x, y),
+        }
+    }
+
+    // the rectangle from (west, north) to (east, south), its size rounded up
+    // where adding it to the corner would fall short of the far edges
+    fn spanning(west: f64, north: f64, east: f64, south: f64) -> Rectangle {
+        let span = |start: f64, end: f64| {
+            let mut span = end - start;
+            while start + span < end {
+                span = span.next_up();
+            }
+            span
+        };
+        Rectangle::new(west, north, span(west, east), span(north, south))
+    }
+
+    /// The same rectangle moved by (dx, dy).
+    pub fn translated(&self, dx: f64, dy: f64) -> Rectangle {
+        Rectangle::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    /// The rectangle scaled by `factor` around its center. Panics unless
+    /// `factor` is positive and finite.
+    pub fn scaled(&self, factor: f64) -> Rectangle {
+        assert!(factor > 0.0 && factor.is_finite(), "invalid scale {}", factor);
+        self.resized(self.width * factor, self.height * factor)
+    }
+
+    /// The rectangle grown by `margin` on every side, or shrunk for negative
+    /// margins. Shrinking stops at zero size around the center.
+    pub fn inflated(&self, margin: f64) -> Rectangle {
+        self.resized(
+            (self.width + 2.0 * margin).max(0.0),
+            (self.height + 2.0 * margin).max(0.0),
+        )
+    }
+
+    /// The part of the rectangle inside `other`. Disjoint rectangles clamp to
+    /// a zero-size rectangle on the border of `other`.
+    pub fn clamped_to(&self, other: &Rectangle) -> Rectangle {
+        let clamp_x = |x: f64| x.clamp(other.x, other.x + other.width);
+        let clamp_y = |y: f64| y.clamp(other.y, other.y + other.height);
+        let (x1, x2) = (clamp_x(self.x), clamp_x(self.x + self.width));
+        let (y1, y2) = (clamp_y(self.y), clamp_y(self.y + self.height));
+        Rectangle::new(x1, y1, x2 - x1, y2 - y1)
+    }
+
+    fn resized(&self, width: f64, height: f64) -> Rectangle {
+        let cx = self.x + self.width / 2.0;
+        let cy = self.y + self.height / 2.0;
+        Rectangle::new(cx - width / 2.0, cy - height / 2.0, width, height)
+    }
+
+    pub fn new_nw(&self) -> Rectangle {
+        // x.
+        // ..
+        self.quadrant_at(Quadrant::NW, self.center())
+    }
+
+    pub fn new_ne(&self) -> Rectangle {
+        // .x
+        // ..
+        self.quadrant_at(Quadrant::NE, self.center())
+    }
+
+    pub fn new_sw(&self) -> Rectangle {
+        // ..
+        // x.
+        self.quadrant_at(Quadrant::SW, self.center())
+    }
+
+    pub fn new_se(&self) -> Rectangle {
+        // ..
+        // .x
+        self.quadrant_at(Quadrant::SE, self.center())
+    }
+}
+
+/// Writes "x,y,width,height", which `FromStr` parses back. Formatting
+/// options like a precision apply to each number.
+impl fmt::Display for Rectangle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.x, f)?;
+        for value in [self.y, self.width, self.height] {
+            f.write_str(",")?;
+            fmt::Display::fmt(&value, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses "x,y,width,height", e.g. from a command line argument. Rejects
+/// the same rectangles as `try_new`.
+impl FromStr for Rectangle {
+    type Err = &'static str;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let [x, y, width, height] = geometry::parse_coordinates(text)?;
+        Rectangle::try_new(x, y, width, height)
+    }
+}
+
+// lets region arguments be passed by reference
+impl From<&Rectangle> for Rectangle {
+    fn from(rectangle: &Rectangle) -> Self {
+        *rectangle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_builds_rectangles_from_corners_and_centers() -> Result<(), Box<dyn std::error::Error>> {
+        let expected = Rectangle::new(10.0, 20.0, 20.0, 40.0);
+        assert_eq!(Rectangle::from_corners(10.0, 20.0, 30.0, 60.0)?, expected);
+        assert_eq!(Rectangle::from_corners(30.0, 60.0, 10.0, 20.0)?, expected);
+
+        let expected = Rectangle::new(45.0, 40.0, 10.0, 20.0);
+        assert_eq!(Rectangle::from_center(50.0, 50.0, 5.0, 10.0)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_rectangles() {
+        assert!(Rectangle::try_new(0.0, 0.0, -5.0, 10.0).is_err());
+        assert!(Rectangle::try_new(0.0, 0.0, 10.0, -5.0).is_err());
+        assert!(Rectangle::try_new(f64::NAN, 0.0, 10.0, 10.0).is_err());
+        assert!(Rectangle::try_new(0.0, 0.0, f64::INFINITY, 10.0).is_err());
+        assert!(Rectangle::from_corners(0.0, f64::NAN, 1.0, 1.0).is_err());
+        assert!(Rectangle::from_center(0.0, 0.0, -1.0, 1.0).is_err());
+        assert!(!Rectangle::new(0.0, 0.0, -5.0, 10.0).is_valid());
+        assert!(Rectangle::try_new(0.0, 0.0, 0.0, 0.0).is_ok());
+    }
+
+    #[test]
+    fn it_moves_and_resizes_rectangles() {
+        let rectangle = Rectangle::new(10.0, 20.0, 40.0, 20.0);
+        assert_eq!(rectangle.translated(5.0, -20.0), Rectangle::new(15.0, 0.0, 40.0, 20.0));
+        assert_eq!(rectangle.scaled(2.0), Rectangle::new(-10.0, 10.0, 80.0, 40.0));
+        assert_eq!(rectangle.scaled(0.5), Rectangle::new(20.0, 25.0, 20.0, 10.0));
+        assert_eq!(rectangle.inflated(5.0), Rectangle::new(5.0, 15.0, 50.0, 30.0));
+        assert_eq!(rectangle.inflated(-15.0), Rectangle::new(25.0, 30.0, 10.0, 0.0));
+
+        let window = Rectangle::new(0.0, 0.0, 30.0, 30.0);
+        assert_eq!(rectangle.clamped_to(&window), Rectangle::new(10.0, 20.0, 20.0, 10.0));
+        assert_eq!(window.clamped_to(&rectangle), Rectangle::new(10.0, 20.0, 20.0, 10.0));
+        let far = Rectangle::new(100.0, 100.0, 10.0, 10.0);
+        assert_eq!(far.clamped_to(&window), Rectangle::new(30.0, 30.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn it_clamps_points_into_rectangles() {
+        let rectangle = Rectangle::new(0.0, 0.0, 4.0, 3.0);
+        assert_eq!(rectangle.clamp_point(1.0, 2.0), (1.0, 2.0));
+        assert_eq!(rectangle.clamp_point(-1.0, 5.0), (0.0, 3.0));
+        assert_eq!(rectangle.clamp_point(4.5, -0.1), (4.0, 0.0));
+    }
+
+    #[test]
+    fn it_tests_rectangles_against_circles() {
+        let rectangle = Rectangle::new(0.0, 0.0, 4.0, 3.0);
+        assert!(rectangle.intersects_circle(2.0, 1.0, 0.5));
+        assert!(rectangle.intersects_circle(7.0, 7.0, 5.0));
+        assert!(!rectangle.intersects_circle(7.0, 7.0, 4.9));
+        assert!(!rectangle.intersects_circle(2.0, 1.0, -1.0));
+
+        assert!(rectangle.contained_in_circle(0.0, 0.0, 5.0));
+        assert!(!rectangle.contained_in_circle(0.0, 0.0, 4.9));
+        assert!(rectangle.contained_in_circle(2.0, 1.5, 2.5));
+        assert!(!rectangle.contained_in_circle(2.0, 1.0, 2.5));
+    }
+
+    #[test]
+    fn it_assigns_seams_to_the_east_and_south_quadrants() {
+        let rectangle = Rectangle::new(0.0, 0.0, 10.0, 10.0);
+        assert_eq!(rectangle.which_quadrant(7.0, 2.0), Quadrant::NE);
+        assert_eq!(rectangle.which_quadrant(7.0, 7.0), Quadrant::SE);
+        assert_eq!(rectangle.which_quadrant(2.0, 7.0), Quadrant::SW);
+        assert_eq!(rectangle.which_quadrant(2.0, 2.0), Quadrant::NW);
+
+        assert_eq!(rectangle.which_quadrant(5.0, 5.0), Quadrant::SE);
+        assert_eq!(rectangle.which_quadrant(5.0, 0.0), Quadrant::NE);
+        assert_eq!(rectangle.which_quadrant(0.0, 5.0), Quadrant::SW);
+        assert_eq!(rectangle.which_quadrant(10.0, 10.0), Quadrant::SE);
+
+        for quadrant in Quadrant::ALL {
+            let child = rectangle.quadrant(quadrant);
+            assert_eq!(rectangle.which_quadrant(child.x, child.y), quadrant);
+            assert_eq!(Quadrant::ALL[quadrant.index()], quadrant);
+        }
+    }
+
+    #[test]
+    fn it_formats_and_parses_rectangles() -> Result<(), Box<dyn std::error::Error>> {
+        let rectangle = Rectangle::new(-10.5, 20.0, 0.1, 1e-3);
+        assert_eq!(rectangle.to_string(), "-10.5,20,0.1,0.001");
+        assert_eq!(format!("{:.2}", rectangle), "-10.50,20.00,0.10,0.00");
+        assert_eq!(" 1, 2 ,3,4 ".parse::<Rectangle>()?, Rectangle::new(1.0, 2.0, 3.0, 4.0));
+
+        // any rectangle survives the round trip exactly
+        let mut rng = StdRng::seed_from_u64(883);
+        for _ in 0..1000 {
+            let rectangle = Rectangle::new(
+                rng.gen_range(-1e6..1e6),
+                rng.gen_range(-1e-6..1e-6),
+                rng.gen_range(0.0..1e12),
+                rng.gen_range(0.0..1.0),
+            );
+            assert_eq!(rectangle.to_string().parse::<Rectangle>()?, rectangle);
+        }
+
+        for text in ["1,2,3", "1,2,3,4,5", "1,2,x,4", "", "1,2,-3,4", "1,2,inf,4", "1;2;3;4"] {
+            assert!(text.parse::<Rectangle>().is_err(), "{text}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_far_edges_of_quadrants_on_the_parent() {
+        // halving this width rounds the far edge of the east quadrants short
+        // of the parent's when it is added to the center
+        let side = 165.43751721195542;
+        let rectangle = Rectangle::new(-852.5608418732809, -852.5608418732809, side, side);
+        let (east, south) = (rectangle.x + rectangle.width, rectangle.y + rectangle.height);
+        let (cx, cy) = rectangle.center();
+        assert!(cx + rectangle.width / 2.0 < east);
+
+        let se = rectangle.quadrant(Quadrant::SE);
+        assert!(se.contains(east, south));
+        assert_eq!(rectangle.which_quadrant(east, south), Quadrant::SE);
+        assert!(rectangle.quadrant(Quadrant::NE).contains(east, rectangle.y));
+        assert!(rectangle.quadrant(Quadrant::SW).contains(rectangle.x, south));
+        assert!(rectangle.quadrant(Quadrant::NW).contains(cx.next_down(), cy.next_down()));
+
+        let mut rng = StdRng::seed_from_u64(811);
+        for _ in 0..1000 {
+            let rectangle = Rectangle::new(
+                rng.gen_range(-1e3..1e3),
+                rng.gen_range(-1e3..1e3),
+                rng.gen_range(1e-3..1e3),
+                rng.gen_range(1e-3..1e3),
+            );
+            let split = (
+                rng.gen_range(rectangle.x..rectangle.x + rectangle.width),
+                rng.gen_range(rectangle.y..rectangle.y + rectangle.height),
+            );
+            let corners = [
+                (rectangle.x, rectangle.y),
+                (rectangle.x + rectangle.width, rectangle.y + rectangle.height),
+                (split.0.next_down(), split.1.next_down()),
+                split,
+            ];
+            for (x, y) in corners {
+                let quadrant = Quadrant::of(split, x, y);
+                assert!(rectangle.quadrant_at(quadrant, split).contains(x, y), "{:?}", rectangle);
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::error::RejectReason;
+use crate::geometry::{Point2D, Rectangle};
+use crate::morton;
+
+// children are four consecutive entries of `nodes` starting at `children` in
+// Z-order, 0 marks a leaf. Leaves own `indices[start..start + len]`.
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    children: u32,
+    start: u32,
+    len: u32,
+}
+
+/// A static quadtree over points that live in a slice owned by the caller.
+/// The tree only stores indices into the slice, so it has no payload type
+/// and no lifetime: build it once, hand the slice to every query and get
+/// back indices. `rebuild` reuses the tree's buffers when the points move.
+///
+/// Queries must be given the slice the tree was last built over, otherwise
+/// results are meaningless. Only the length is checked.
+#[derive(Debug, Clone)]
+pub struct IndexQuadTree {
+    boundary: Rectangle,
+    nodes: Vec<Node>,
+    // into the points, sorted by Z-order
+    indices: Vec<u32>,
+    // scratch space for rebuilds
+    codes: Vec<(u64, u32)>,
+}
+
+impl IndexQuadTree {
+    const MAX_CAPACITY: usize = 8;
+    const DEPTH: u32 = 32;
+
+    /// Builds the tree over all `points`. Panics with 2^32 or more points.
+    pub fn index_over<T>(boundary: Rectangle, points: &[Point2D<T>]) -> Result<Self, RejectReason> {
+        let mut tree = IndexQuadTree {
+            boundary,
+            nodes: Vec::new(),
+            indices: Vec::new(),
+            codes: Vec::new(),
+        };
+        tree.rebuild(points)?;
+        Ok(tree)
+    }
+
+    /// Builds the tree again over `points`, e.g. after they moved, without
+    /// allocating unless there are more points than before. On error the
+    /// tree is left empty.
+    pub fn rebuild<T>(&mut self, points: &[Point2D<T>]) -> Result<(), RejectReason> {
+        assert!(u32::try_from(points.len()).is_ok(), "too many points for u32 indices");
+        self.nodes.clear();
+        self.indices.clear();
+        self.codes.clear();
+        for point in points.iter() {
+            RejectReason::check(&self.boundary, point.x, point.y)?;
+        }
+
+        let boundary = self.boundary;
+        self.codes.extend(points.iter().enumerate().map(|(index, point)| {
+            (morton::encode(point.x, point.y, &boundary, Self::DEPTH), index as u32)
+        }));
+        self.codes.sort_unstable();
+        self.indices.extend(self.codes.iter().map(|(_, index)| *index));
+        self.nodes.push(Node::default());
+        let codes = std::mem::take(&mut self.codes);
+        self.build(0, &codes, 0, 0);
+        self.codes = codes;
+        Ok(())
+    }
+
+    // fills node `index` with `codes`, which start at `start` in the sorted
+    // order and all share the cell prefix of `level`
+    fn build(&mut self, index: usize, codes: &[(u64, u32)], start: usize, level: u32) {
+        if codes.len() <= Self::MAX_CAPACITY || level == Self::DEPTH {
+            // the lengths fit into u32, checked in rebuild
+            self.nodes[index] = Node {
+                children: 0,
+                start: start as u32,
+                len: codes.len() as u32,
+            };
+            return;
+        }
+
+        let children = self.nodes.len();
+        self.nodes.resize(children + 4, Node::default());
+        self.nodes[index].children = children as u32;
+        let shift = 2 * (Self::DEPTH - level - 1);
+        let mut offset = 0;
+        for quadrant in 0..4 {
+            let end = offset
+                + codes[offset..].partition_point(|(code, _)| (code >> shift) & 3 <= quadrant);
+            self.build(
+                children + quadrant as usize,
+                &codes[offset..end],
+                start + offset,
+                level + 1,
+            );
+            offset = end;
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Indices of the points inside `region`.
+    pub fn query<T>(&self, points: &[Point2D<T>], region: impl Into<Rectangle>) -> Vec<usize> {
+        let region = region.into();
+        let mut result = Vec::new();
+        self.for_each_candidate(points, &region, |index, point| {
+            if region.contains(point.x, point.y) {
+                result.push(index);
+            }
+        });
+        result
+    }
+
+    /// Indices of the points within `radius` of (x, y).
+    pub fn query_radius<T>(
+        &self,
+        points: &[Point2D<T>],
+        x: f64,
+        y: f64,
+        radius: f64,
+    ) -> Vec<usize> {
+        let region = Rectangle::new(x - radius, y - radius, 2.0 * radius, 2.0 * radius);
+        let mut result = Vec::new();
+        self.for_each_candidate(points, &region, |index, point| {
+            let (dx, dy) = (point.x - x, point.y - y);
+            if dx * dx + dy * dy <= radius * radius {
+                result.push(index);
+            }
+        });
+        result
+    }
+
+    // calls `f` for the points of every leaf whose cells overlap `region`.
+    // Overlap is decided on the cell grid the points were sorted by, so
+    // rounding can't drop a point that lies on a cell edge.
+    fn for_each_candidate<'a, T>(
+        &self,
+        points: &'a [Point2D<T>],
+        region: &Rectangle,
+        mut f: impl FnMut(usize, &'a Point2D<T>),
+    ) {
+        assert_eq!(points.len(), self.indices.len(), "not the points the tree was built over");
+        if self.indices.is_empty() {
+            return;
+        }
+        let corner = |x: f64, y: f64| {
+            morton::decode_cell(morton::encode(x, y, &self.boundary, Self::DEPTH))
+        };
+        let (min_x, min_y) = corner(region.x, region.y);
+        let (max_x, max_y) = corner(region.x + region.width, region.y + region.height);
+
+        let mut stack = vec![(0, 0u64, 0)];
+        while let Some((index, prefix, level)) = stack.pop() {
+            let node = self.nodes[index];
+            // the range of grid cells the node covers along each axis
+            let (cx, cy) = morton::decode_cell(prefix);
+            let shift = Self::DEPTH - level;
+            let (low_x, low_y) = ((cx as u64) << shift, (cy as u64) << shift);
+            let size = 1u64 << shift;
+            if low_x > max_x as u64
+                || low_y > max_y as u64
+                || low_x + size <= min_x as u64
+                || low_y + size <= min_y as u64
+            {
+                continue;
+            }
+            if node.children == 0 {
+                let range = node.start as usize..(node.start + node.len) as usize;
+                for &index in self.indices[range].iter() {
+                    f(index as usize, &points[index as usize]);
+                }
+                continue;
+            }
+            // reversed, so children are visited in Z-order
+            for quadrant in (0..4).rev() {
+                stack.push((
+                    node.children as usize + quadrant as usize,
+                    (prefix << 2) | quadrant,
+                    level + 1,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_returns_indices_into_the_points() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(866);
+        let mut points: Vec<Point2D<()>> = (0..2000)
+            .map(|_| Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), ()))
+            .collect();
+        // points on the boundary's edges and a pile of identical points
+        points.push(Point2D::new(100.0, 100.0, ()));
+        points.push(Point2D::new(0.0, 50.0, ()));
+        points.extend((0..50).map(|_| Point2D::new(12.5, 12.5, ())));
+        let mut tree = IndexQuadTree::index_over(boundary, &points)?;
+        assert_eq!(tree.count(), points.len());
+
+        let check = |tree: &IndexQuadTree, points: &[Point2D<()>]| {
+            for region in [
+                Rectangle::new(10.0, 10.0, 30.0, 5.0),
+                Rectangle::new(0.0, 0.0, 100.0, 100.0),
+                Rectangle::new(12.5, 12.5, 0.0, 0.0),
+                Rectangle::new(90.0, 90.0, 50.0, 50.0),
+                Rectangle::new(-20.0, -20.0, 10.0, 10.0),
+            ] {
+                let mut found = tree.query(points, region);
+                found.sort();
+                let expected: Vec<usize> = (0..points.len())
+                    .filter(|&i| region.contains(points[i].x, points[i].y))
+                    .collect();
+                assert_eq!(found, expected);
+            }
+            let mut found = tree.query_radius(points, 50.0, 50.0, 20.0);
+            found.sort();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&i| (points[i].x - 50.0).powi(2) + (points[i].y - 50.0).powi(2) <= 400.0)
+                .collect();
+            assert_eq!(found, expected);
+        };
+        check(&tree, &points);
+
+        for point in points.iter_mut() {
+            point.x = (point.x + rng.gen_range(-5.0..5.0)).clamp(0.0, 100.0);
+        }
+        let capacity = (tree.indices.capacity(), tree.codes.capacity());
+        tree.rebuild(&points)?;
+        assert_eq!((tree.indices.capacity(), tree.codes.capacity()), capacity);
+        check(&tree, &points);
+
+        points.push(Point2D::new(101.0, 0.0, ()));
+        assert_eq!(tree.rebuild(&points), Err(RejectReason::OutOfBounds));
+        assert_eq!(tree.count(), 0);
+        assert!(tree.query(&[] as &[Point2D<()>], boundary).is_empty());
+
+        Ok(())
+    }
+}
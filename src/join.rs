@@ -0,0 +1,78 @@
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+/// Range joins of one structure's points against any `SpatialIndex`, e.g.
+/// to cross-check a tree against the `VecIndex` baseline or to combine
+/// different structures in one pipeline.
+pub trait RangeJoin<T> {
+    /// Visits every stored point once.
+    fn visit_points(&self, f: &mut dyn FnMut(&Point2D<T>));
+
+    /// Calls `f` for every pair of a point of `self` and a point of `other`
+    /// at most `radius` apart. Each point of `self` becomes one query
+    /// against `other`.
+    fn range_join<U>(
+        &self,
+        other: &impl SpatialIndex<U>,
+        radius: f64,
+        mut f: impl FnMut(&Point2D<T>, &Point2D<U>),
+    ) {
+        if radius < 0.0 {
+            return;
+        }
+        self.visit_points(&mut |point| {
+            let side = 2.0 * radius;
+            let window = Rectangle::new(point.x - radius, point.y - radius, side, side);
+            for other_point in other.query(window) {
+                if point.distance_squared_to(other_point) <= radius * radius {
+                    f(point, other_point);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{LinearQuadTree, QuadTree, SparseQuadTree, VecIndex};
+
+    use super::*;
+
+    fn joined(
+        points: &impl RangeJoin<usize>,
+        index: &impl SpatialIndex<usize>,
+    ) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        points.range_join(index, 3.0, |a, b| pairs.push((a.data, b.data)));
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn it_joins_across_structures() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(830);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut baseline = VecIndex::new(boundary);
+        let mut linear = LinearQuadTree::new(boundary);
+        for i in 0..300 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            quadtree.insert(point)?;
+            baseline.insert(point)?;
+            let other = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            sparse.insert(other)?;
+            linear.insert(other)?;
+        }
+
+        let expected = joined(&baseline, &linear);
+        assert!(!expected.is_empty());
+        assert_eq!(joined(&quadtree, &sparse), expected);
+        assert_eq!(joined(&quadtree, &linear), expected);
+        assert_eq!(joined(&baseline, &sparse), expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use crate::error::{InsertError, RejectReason};
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+// the children of the node storing the point at the same index. Nodes split
+// at their point along x on even depths and along y on odd ones. The lower
+// child holds coordinates up to the split, the upper one from it on, so
+// equal coordinates may end up on either side.
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    lower: Option<usize>,
+    upper: Option<usize>,
+}
+
+fn coordinate<T>(point: &Point2D<T>, depth: usize) -> f64 {
+    if depth.is_multiple_of(2) {
+        point.x
+    } else {
+        point.y
+    }
+}
+
+/// A 2D kd-tree with one point per node, splitting alternately along x and
+/// y. `from_points` builds a balanced tree, while inserting points one by
+/// one keeps the tree's shape as it grows, so sorted input degrades it into
+/// long chains. Benchmarked against the quadtrees.
+#[derive(Debug, Clone)]
+pub struct KdTree<T> {
+    boundary: Rectangle,
+    points: Vec<Point2D<T>>,
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+impl<T> KdTree<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        KdTree {
+            boundary,
+            points: Vec::new(),
+            nodes: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// Builds a balanced tree from all points at once, splitting every node
+    /// at the median of its sub-tree.
+    pub fn from_points(boundary: Rectangle, points: Vec<Point2D<T>>) -> Result<Self, RejectReason> {
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+        let mut tree = KdTree {
+            boundary,
+            nodes: vec![Node::default(); points.len()],
+            points,
+            root: None,
+        };
+        tree.root = tree.build(0, tree.points.len(), 0);
+        Ok(tree)
+    }
+
+    // arranges `points[start..end]` into a sub-tree and returns its root.
+    // Recursion only goes as deep as the balanced tree.
+    fn build(&mut self, start: usize, end: usize, depth: usize) -> Option<usize> {
+        if start == end {
+            return None;
+        }
+        let middle = start + (end - start) / 2;
+        self.points[start..end].select_nth_unstable_by(middle - start, |a, b| {
+            coordinate(a, depth).total_cmp(&coordinate(b, depth))
+        });
+        self.nodes[middle] = Node {
+            lower: self.build(start, middle, depth + 1),
+            upper: self.build(middle + 1, end, depth + 1),
+        };
+        Some(middle)
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Levels from the root to the deepest node, 0 for an empty tree.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack: Vec<(usize, usize)> = self.root.map(|root| (root, 1)).into_iter().collect();
+        while let Some((index, level)) = stack.pop() {
+            depth = depth.max(level);
+            let node = self.nodes[index];
+            stack.extend([node.lower, node.upper].into_iter().flatten().map(|i| (i, level + 1)));
+        }
+        depth
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        let index = self.points.len();
+        let mut link = &mut self.root;
+        let mut depth = 0;
+        while let Some(current) = *link {
+            let node = &mut self.nodes[current];
+            link = if coordinate(&point, depth) < coordinate(&self.points[current], depth) {
+                &mut node.lower
+            } else {
+                &mut node.upper
+            };
+            depth += 1;
+        }
+        *link = Some(index);
+        self.points.push(point);
+        self.nodes.push(Node::default());
+        Ok(())
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let mut result = Vec::new();
+        let mut stack: Vec<(usize, usize)> = self.root.map(|root| (root, 0)).into_iter().collect();
+        while let Some((index, depth)) = stack.pop() {
+            let point = &self.points[index];
+            if region.contains(point.x, point.y) {
+                result.push(point);
+            }
+            let (min, max) = if depth.is_multiple_of(2) {
+                (region.x, region.x + region.width)
+            } else {
+                (region.y, region.y + region.height)
+            };
+            let split = coordinate(point, depth);
+            let node = self.nodes[index];
+            if let Some(upper) = node.upper.filter(|_| max >= split) {
+                stack.push((upper, depth + 1));
+            }
+            if let Some(lower) = node.lower.filter(|_| min <= split) {
+                stack.push((lower, depth + 1));
+            }
+        }
+        result
+    }
+
+    /// The point closest to (x, y), the first one found among equally close
+    /// points.
+    pub fn nearest(&self, x: f64, y: f64) -> Option<&Point2D<T>> {
+        let mut best: Option<(usize, f64)> = None;
+        let mut stack: Vec<(usize, usize, f64)> =
+            self.root.map(|root| (root, 0, 0.0)).into_iter().collect();
+        while let Some((index, depth, bound)) = stack.pop() {
+            // `bound` is the squared distance to the slab the node lies in
+            if best.is_some_and(|(_, distance)| bound >= distance) {
+                continue;
+            }
+            let point = &self.points[index];
+            let distance = point.distance_squared_to_position(x, y);
+            if best.is_none_or(|(_, best)| distance < best) {
+                best = Some((index, distance));
+            }
+            let offset = if depth.is_multiple_of(2) { x } else { y } - coordinate(point, depth);
+            let node = self.nodes[index];
+            let (near, far) = if offset < 0.0 {
+                (node.lower, node.upper)
+            } else {
+                (node.upper, node.lower)
+            };
+            // the near side is searched first, so it goes on top
+            if let Some(far) = far {
+                stack.push((far, depth + 1, bound.max(offset * offset)));
+            }
+            if let Some(near) = near {
+                stack.push((near, depth + 1, bound));
+            }
+        }
+        best.map(|(index, _)| &self.points[index])
+    }
+}
+
+impl<T> SpatialIndex<T> for KdTree<T> {
+    fn new(boundary: Rectangle) -> Self {
+        KdTree::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_answers_like_a_scan() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(885);
+        let mut points: Vec<Point2D<usize>> = (0..2000)
+            .map(|i| Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))
+            .collect();
+        // points on the edges and a pile of identical coordinates
+        points.push(Point2D::new(100.0, 100.0, 2000));
+        points.push(Point2D::new(0.0, 50.0, 2001));
+        points.extend((2002..2052).map(|i| Point2D::new(25.0, 25.0 + (i % 2) as f64, i)));
+
+        let built = KdTree::from_points(boundary, points.clone())?;
+        let mut inserted = KdTree::new(boundary);
+        for point in points.iter() {
+            inserted.insert(*point)?;
+        }
+        assert_eq!((built.count(), inserted.count()), (points.len(), points.len()));
+        assert!(built.depth() <= 12);
+
+        for tree in [&built, &inserted] {
+            for region in [
+                Rectangle::new(10.0, 10.0, 30.0, 5.0),
+                Rectangle::new(0.0, 0.0, 100.0, 100.0),
+                Rectangle::new(25.0, 25.0, 0.0, 1.0),
+                Rectangle::new(90.0, 90.0, 50.0, 50.0),
+                Rectangle::new(-20.0, -20.0, 10.0, 10.0),
+            ] {
+                let mut found: Vec<usize> =
+                    tree.query(region).iter().map(|point| point.data).collect();
+                found.sort();
+                let expected: Vec<usize> = points
+                    .iter()
+                    .filter(|point| region.contains(point.x, point.y))
+                    .map(|point| point.data)
+                    .collect();
+                assert_eq!(found, expected);
+            }
+
+            for _ in 0..100 {
+                let (x, y) = (rng.gen_range(-10.0..110.0), rng.gen_range(-10.0..110.0));
+                let nearest = tree.nearest(x, y).ok_or("empty")?;
+                let closest = points
+                    .iter()
+                    .map(|point| point.distance_squared_to_position(x, y))
+                    .fold(f64::INFINITY, f64::min);
+                assert_eq!(nearest.distance_squared_to_position(x, y), closest);
+            }
+        }
+
+        let error = KdTree::from_points(boundary, vec![Point2D::new(101.0, 0.0, 0)]).err();
+        assert_eq!(error, Some(RejectReason::OutOfBounds));
+        assert!(KdTree::<()>::new(boundary).nearest(1.0, 1.0).is_none());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,76 @@
+mod aggregate;
+mod broadphase;
+mod bytes;
+mod compact;
+#[cfg(feature = "bench-competitors")]
+pub mod competitors;
+mod diff;
+mod entry;
+mod error;
+#[cfg(feature = "geo")]
+mod geo;
+mod geographic;
 mod geometry;
+mod index;
+mod join;
+mod kdtree;
+mod linear;
+mod metric;
+pub mod morton;
+mod observer;
+mod persistent;
+mod point_set;
+pub mod prelude;
 mod quadtree;
 mod quadtree_option;
+mod region;
+#[cfg(feature = "f32-scan")]
+mod scan;
+mod sequence;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+mod spatial_index;
+mod temporal;
+mod traversal;
+#[cfg(test)]
+mod tree_tests;
+mod vec_index;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "wkt")]
+mod wkt;
 
-pub use geometry::{Point2D, Rectangle};
+pub use aggregate::{Aggregate, AggregateQuadTree, Centroid, Cluster, Count, MinMax, Sum};
+pub use broadphase::BroadPhase;
+pub use bytes::Packable;
+pub use compact::CompactQuadTree;
+pub use diff::TreeDiff;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{InsertError, RejectReason};
+pub use geographic::{haversine_distance, GeoQuadTree, EARTH_RADIUS};
+pub use geometry::{
+    Circle, LineSegment, Point2D, PointMut, Quadrant, QueryEdges, Rectangle, SplitStrategy,
+};
+pub use index::IndexQuadTree;
+pub use join::RangeJoin;
+pub use kdtree::KdTree;
+pub use linear::QuadTree as LinearQuadTree;
+pub use metric::{Euclidean, Haversine, Manhattan, Metric};
+pub use observer::TreeObserver;
+pub use persistent::PersistentQuadTree;
+pub use point_set::PointSet;
 pub use quadtree::QuadTree;
-pub use quadtree_option::QuadTree as QuadTreeOption;
+pub use quadtree_option::{InsertHint, QuadTree as SparseQuadTree};
+pub use region::RegionQuadTree;
+pub use sequence::{Sequenced, SequencedIndex};
+#[cfg(feature = "snapshot")]
+pub use snapshot::{SnapshotQuadTree, SnapshotReader};
+pub use spatial_index::SpatialIndex;
+pub use temporal::{TemporalQuadTree, Timed};
+pub use traversal::{NodePath, NodeView, QueryResume, QueryStats, TraversalBudget};
+pub use vec_index::VecIndex;
+
+#[deprecated(note = "renamed to `SparseQuadTree`")]
+pub type QuadTreeOption<T> = SparseQuadTree<T>;
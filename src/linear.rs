@@ -0,0 +1,419 @@
+use crate::error::{InsertError, RejectReason};
+use crate::geometry::{Point2D, Rectangle};
+use crate::morton;
+#[cfg(feature = "f32-scan")]
+use crate::scan::{self, F32Region};
+use crate::join::RangeJoin;
+use crate::spatial_index::SpatialIndex;
+
+/// A linear quadtree: points sorted by the Z-order (Morton) code of the
+/// smallest cell containing them. Every quadtree node maps to a contiguous
+/// range of codes, so queries become binary searches over a flat array.
+///
+/// Codes, x coordinates and y coordinates live in parallel arrays next to
+/// the points, so the binary searches only touch the codes and scans only
+/// the coordinates. The points stay whole, as queries hand out references
+/// to them; `positions` lends out the coordinate arrays.
+#[derive(Debug)]
+pub struct QuadTree<T> {
+    boundary: Rectangle,
+    // sorted Z-order codes, `codes[i]` belongs to `points[i]`, as do `xs[i]`
+    // and `ys[i]`
+    codes: Vec<u64>,
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    points: Vec<Point2D<T>>,
+    // f32 copies of the coordinates in the same order
+    #[cfg(feature = "f32-scan")]
+    xs_f32: Vec<f32>,
+    #[cfg(feature = "f32-scan")]
+    ys_f32: Vec<f32>,
+}
+
+// a query region and the range of grid cells it overlaps. Overlap is decided
+// on the cell grid the points were sorted by, so rounding can't drop a point
+// that lies on a cell edge.
+struct CellRange {
+    region: Rectangle,
+    min: (u32, u32),
+    max: (u32, u32),
+}
+
+impl<T> QuadTree<T> {
+    const DEPTH: u32 = 16;
+    // ranges this small are scanned instead of split into further cells
+    const SCAN_THRESHOLD: usize = 16;
+
+    pub fn new(boundary: Rectangle) -> Self {
+        debug_assert!(boundary.is_valid(), "invalid boundary {:?}", boundary);
+        QuadTree {
+            boundary,
+            codes: Vec::new(),
+            xs: Vec::new(),
+            ys: Vec::new(),
+            points: Vec::new(),
+            #[cfg(feature = "f32-scan")]
+            xs_f32: Vec::new(),
+            #[cfg(feature = "f32-scan")]
+            ys_f32: Vec::new(),
+        }
+    }
+
+    /// Builds the tree from all points at once, which is much faster than
+    /// inserting them one by one.
+    pub fn from_points(boundary: Rectangle, points: Vec<Point2D<T>>) -> Result<Self, RejectReason> {
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+
+        let mut points: Vec<(u64, Point2D<T>)> = points
+            .into_iter()
+            .map(|point| (morton::encode(point.x, point.y, &boundary, Self::DEPTH), point))
+            .collect();
+        points.sort_by_key(|(code, _)| *code);
+        let mut tree = QuadTree::new(boundary);
+        tree.codes.reserve_exact(points.len());
+        tree.xs.reserve_exact(points.len());
+        tree.ys.reserve_exact(points.len());
+        tree.points.reserve_exact(points.len());
+        for (code, point) in points {
+            tree.codes.push(code);
+            tree.xs.push(point.x);
+            tree.ys.push(point.y);
+            tree.points.push(point);
+        }
+        #[cfg(feature = "f32-scan")]
+        {
+            tree.xs_f32 = tree.xs.iter().map(|x| *x as f32).collect();
+            tree.ys_f32 = tree.ys.iter().map(|y| *y as f32).collect();
+        }
+        Ok(tree)
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Tight bounding box of the stored points, `None` for an empty tree.
+    pub fn bounds(&self) -> Option<Rectangle> {
+        let fold = |values: &[f64]| {
+            let first = *values.first()?;
+            Some(values.iter().fold((first, first), |(min, max), v| (min.min(*v), max.max(*v))))
+        };
+        let ((min_x, max_x), (min_y, max_y)) = (fold(&self.xs)?, fold(&self.ys)?);
+        Some(Rectangle::new(min_x, min_y, max_x - min_x, max_y - min_y))
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+
+        let code = morton::encode(point.x, point.y, &self.boundary, Self::DEPTH);
+        let index = self.codes.partition_point(|other| *other <= code);
+        #[cfg(feature = "f32-scan")]
+        {
+            self.xs_f32.insert(index, point.x as f32);
+            self.ys_f32.insert(index, point.y as f32);
+        }
+        self.codes.insert(index, code);
+        self.xs.insert(index, point.x);
+        self.ys.insert(index, point.y);
+        self.points.insert(index, point);
+        Ok(())
+    }
+
+    /// Every stored point, in Z-order.
+    pub fn iter(&self) -> impl Iterator<Item = &Point2D<T>> {
+        self.points.iter()
+    }
+
+    /// The x and the y coordinates of the stored points as two arrays, in
+    /// the order of `iter`.
+    pub fn positions(&self) -> (&[f64], &[f64]) {
+        (&self.xs, &self.ys)
+    }
+
+    /// Consumes the tree and returns the stored points, in Z-order.
+    pub fn into_points(self) -> Vec<Point2D<T>> {
+        self.points
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let mut result = Vec::new();
+        if !region.intersects(&self.boundary) {
+            return result;
+        }
+        let corner = |x: f64, y: f64| {
+            morton::decode_cell(morton::encode(x, y, &self.boundary, Self::DEPTH))
+        };
+        let cells = CellRange {
+            region,
+            min: corner(region.x, region.y),
+            max: corner(region.x + region.width, region.y + region.height),
+        };
+        self.query_cell(&cells, &self.codes, 0, 0, 0, &mut result);
+        result
+    }
+
+    // `codes` are those of the points at `offset..offset + codes.len()`
+    fn query_cell<'a>(
+        &'a self,
+        cells: &CellRange,
+        codes: &[u64],
+        offset: usize,
+        level: u32,
+        prefix: u64,
+        result: &mut Vec<&'a Point2D<T>>,
+    ) {
+        if codes.is_empty() {
+            return;
+        }
+        // the range of grid cells this cell covers along each axis
+        let (cx, cy) = morton::decode_cell(prefix);
+        let shift = Self::DEPTH - level;
+        let (low_x, low_y) = ((cx as u64) << shift, (cy as u64) << shift);
+        let size = 1u64 << shift;
+        if low_x > cells.max.0 as u64
+            || low_y > cells.max.1 as u64
+            || low_x + size <= cells.min.0 as u64
+            || low_y + size <= cells.min.1 as u64
+        {
+            return;
+        }
+
+        let region = &cells.region;
+        if level == Self::DEPTH
+            || codes.len() <= Self::SCAN_THRESHOLD
+            || region.contains_rectangle(&morton::cell_rectangle(prefix, level, &self.boundary))
+        {
+            self.scan(region, offset, codes.len(), result);
+            return;
+        }
+
+        // the four children split the code range of this cell into quarters
+        let shift = 2 * (Self::DEPTH - level - 1);
+        let mut rest = codes;
+        let mut offset = offset;
+        for quadrant in 0..4 {
+            let child = (prefix << 2) | quadrant;
+            let end = (child + 1) << shift;
+            let split = rest.partition_point(|code| *code < end);
+            let (inside, tail) = rest.split_at(split);
+            self.query_cell(cells, inside, offset, level + 1, child, result);
+            rest = tail;
+            offset += split;
+        }
+    }
+
+    #[cfg(not(feature = "f32-scan"))]
+    fn scan<'a>(
+        &'a self,
+        region: &Rectangle,
+        offset: usize,
+        len: usize,
+        result: &mut Vec<&'a Point2D<T>>,
+    ) {
+        let range = offset..offset + len;
+        let positions = self.xs[range.clone()].iter().zip(&self.ys[range]);
+        for (index, (x, y)) in positions.enumerate() {
+            if region.contains(*x, *y) {
+                result.push(&self.points[offset + index]);
+            }
+        }
+    }
+
+    #[cfg(feature = "f32-scan")]
+    fn scan<'a>(
+        &'a self,
+        region: &Rectangle,
+        offset: usize,
+        len: usize,
+        result: &mut Vec<&'a Point2D<T>>,
+    ) {
+        let range = offset..offset + len;
+        let xs = &self.xs_f32[range.clone()];
+        let ys = &self.ys_f32[range];
+        scan::candidates(xs, ys, &F32Region::enclosing(region), |index| {
+            let index = offset + index;
+            if region.contains(self.xs[index], self.ys[index]) {
+                result.push(&self.points[index]);
+            }
+        });
+    }
+}
+
+impl<T> RangeJoin<T> for QuadTree<T> {
+    fn visit_points(&self, f: &mut dyn FnMut(&Point2D<T>)) {
+        self.points.iter().for_each(f);
+    }
+}
+
+impl<T> SpatialIndex<T> for QuadTree<T> {
+    fn new(boundary: Rectangle) -> Self {
+        QuadTree::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::geometry::{Point2D, Rectangle};
+
+    use super::*;
+
+    #[test]
+    fn it_inserts_a_point() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(quadtree.count(), 0);
+
+        quadtree.insert(Point2D {
+            x: 10.0,
+            y: 10.0,
+            data: 42,
+        })?;
+        assert_eq!(quadtree.count(), 1);
+
+        let points = quadtree.query(Rectangle::new(9.0, 9.0, 11.0, 11.0));
+        assert_eq!(points.len(), 1);
+        assert!(points[0].data == 42);
+
+        assert!(quadtree
+            .insert(Point2D {
+                x: 110.0,
+                y: 10.0,
+                data: 42,
+            })
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_bulk_builds_and_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(800);
+        let points: Vec<Point2D<usize>> = (0..2000)
+            .map(|i| Point2D {
+                x: rng.gen_range(0.0..=100.0),
+                y: rng.gen_range(0.0..=100.0),
+                data: i,
+            })
+            .collect();
+        let quadtree = QuadTree::from_points(Rectangle::new(0.0, 0.0, 100.0, 100.0), points.clone())?;
+        assert_eq!(quadtree.count(), points.len());
+
+        for _ in 0..50 {
+            let region = Rectangle::new(
+                rng.gen_range(-10.0..100.0),
+                rng.gen_range(-10.0..100.0),
+                rng.gen_range(0.0..60.0),
+                rng.gen_range(0.0..60.0),
+            );
+            let mut expected: Vec<usize> = points
+                .iter()
+                .filter(|p| region.contains(p.x, p.y))
+                .map(|p| p.data)
+                .collect();
+            let mut actual: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_computes_the_bounds_of_stored_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(quadtree.bounds().is_none());
+
+        quadtree.insert(Point2D { x: 20.0, y: 30.0, data: 0 })?;
+        assert_eq!(quadtree.bounds(), Some(Rectangle::new(20.0, 30.0, 0.0, 0.0)));
+
+        for (x, y) in [(25.0, 70.0), (60.0, 35.0), (40.0, 40.0), (30.0, 31.0), (55.0, 65.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+        }
+        assert_eq!(quadtree.bounds(), Some(Rectangle::new(20.0, 30.0, 40.0, 40.0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_positions_parallel_to_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(869);
+        let points: Vec<Point2D<String>> = (0..500)
+            .map(|i| {
+                let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+                Point2D::new(x, y, i.to_string())
+            })
+            .collect();
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::from_points(boundary, points.clone())?;
+        quadtree.insert(Point2D::new(50.0, 50.0, "inserted".to_string()))?;
+
+        let (xs, ys) = quadtree.positions();
+        let positions: Vec<_> = xs.iter().copied().zip(ys.iter().copied()).collect();
+        let expected: Vec<_> = quadtree.iter().map(|point| (point.x, point.y)).collect();
+        assert_eq!(positions, expected);
+        assert_eq!(positions.len(), points.len() + 1);
+        let key = |point: &Point2D<String>| (point.data.clone(), point.x, point.y);
+        let mut stored: Vec<_> = quadtree.into_points().iter().map(key).collect();
+        stored.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut expected: Vec<_> = points.iter().map(key).collect();
+        expected.push(("inserted".to_string(), 50.0, 50.0));
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(stored, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_points_on_edges_of_cells_that_round() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(800);
+        for _ in 0..100 {
+            let boundary = Rectangle::new(
+                rng.gen_range(-1e3..1e3),
+                rng.gen_range(-1e3..1e3),
+                rng.gen_range(1e-3..1e3),
+                rng.gen_range(1e-3..1e3),
+            );
+            let (east, south) = (boundary.x + boundary.width, boundary.y + boundary.height);
+            let mut quadtree = QuadTree::new(boundary);
+            let mut positions = vec![(east, south)];
+            for _ in 0..50 {
+                positions.push((east, rng.gen_range(boundary.y..south)));
+                positions.push((rng.gen_range(boundary.x..east), south));
+            }
+            for (i, &(x, y)) in positions.iter().enumerate() {
+                quadtree.insert(Point2D { x, y, data: i })?;
+            }
+            for (x, y) in positions {
+                let found = quadtree.query(Rectangle::new(x, y, 0.0, 0.0));
+                assert!(!found.is_empty(), "({}, {}) in {:?}", x, y, boundary);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use crate::geographic::{haversine_distance, wrap_longitude};
+use crate::geometry::Rectangle;
+
+/// A distance between positions for `query_radius_by` and `k_nearest_by`.
+/// Besides the distance itself a metric bounds the distance to a node's
+/// boundary, which the queries use to skip nodes.
+pub trait Metric {
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64;
+
+    /// A lower bound of the distance from (x, y) to any position inside
+    /// `rect`. Must never be larger than the real distance, otherwise
+    /// queries miss points.
+    fn min_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64;
+
+    /// An upper bound of the distance from (x, y) to any position inside
+    /// `rect`. Nodes within the radius of a query are taken whole without
+    /// checking their points. The default never takes a node whole.
+    fn max_distance(&self, _rect: &Rectangle, _x: f64, _y: f64) -> f64 {
+        f64::INFINITY
+    }
+}
+
+/// Straight-line distance, what `query_radius` and `k_nearest` use.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Euclidean;
+
+impl Metric for Euclidean {
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        let (dx, dy) = (x1 - x2, y1 - y2);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    fn min_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64 {
+        rect.distance_squared(x, y).sqrt()
+    }
+
+    fn max_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64 {
+        rect.max_distance_squared(x, y).sqrt()
+    }
+}
+
+/// Sum of the distances along both axes, e.g. for grid movement.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Manhattan;
+
+impl Metric for Manhattan {
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        (x1 - x2).abs() + (y1 - y2).abs()
+    }
+
+    fn min_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64 {
+        let (cx, cy) = rect.clamp_point(x, y);
+        self.distance(x, y, cx, cy)
+    }
+
+    fn max_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64 {
+        let dx = (x - rect.x).abs().max((x - (rect.x + rect.width)).abs());
+        let dy = (y - rect.y).abs().max((y - (rect.y + rect.height)).abs());
+        dx + dy
+    }
+}
+
+/// Great-circle distance in meters with x as longitude and y as latitude in
+/// degrees. Longitudes wrap around, so nodes across the antimeridian count
+/// as near.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Haversine;
+
+impl Metric for Haversine {
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        haversine_distance(x1, y1, x2, y2)
+    }
+
+    // the exact distance to the closest position inside `rect`
+    fn min_distance(&self, rect: &Rectangle, x: f64, y: f64) -> f64 {
+        let (south, north) = (rect.y, rect.y + rect.height);
+        // within the box's longitudes the closest position lies due north or
+        // south, otherwise on the nearer of its west and east edge
+        if rect.width >= 360.0 || (x - rect.x).rem_euclid(360.0) <= rect.width {
+            return haversine_distance(x, y, x, y.clamp(south, north));
+        }
+        let west = wrap_longitude(x - rect.x).abs();
+        let east = wrap_longitude(x - (rect.x + rect.width)).abs();
+        let (edge, dlon) = if west <= east {
+            (rect.x, west)
+        } else {
+            (rect.x + rect.width, east)
+        };
+        // more than a quarter turn away the edge passes the farthest position
+        // of its meridian, so one of its ends is the closest
+        if dlon >= 90.0 {
+            return haversine_distance(x, y, edge, south).min(haversine_distance(x, y, edge, north));
+        }
+        // otherwise the distance grows from the closest position of the
+        // meridian towards both poles
+        let lat = (y.to_radians().tan() / dlon.to_radians().cos()).atan().to_degrees();
+        haversine_distance(x, y, edge, lat.clamp(south, north))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{Point2D, QuadTree, SparseQuadTree};
+
+    use super::*;
+
+    #[test]
+    fn it_never_overestimates_the_distance_to_a_rectangle() {
+        let mut rng = StdRng::seed_from_u64(862);
+        let metrics: [&dyn Metric; 3] = [&Euclidean, &Manhattan, &Haversine];
+        for _ in 0..2000 {
+            let (west, south) = (rng.gen_range(-180.0..150.0), rng.gen_range(-90.0..60.0));
+            let (width, height) = (rng.gen_range(0.0..30.0), rng.gen_range(0.0..30.0));
+            let rect = Rectangle::new(west, south, width, height);
+            let (x, y) = (rng.gen_range(-180.0..180.0), rng.gen_range(-90.0..90.0));
+            let (px, py) = (
+                rng.gen_range(rect.x..=rect.x + rect.width),
+                rng.gen_range(rect.y..=rect.y + rect.height),
+            );
+            for metric in metrics {
+                let distance = metric.distance(x, y, px, py);
+                assert!(metric.min_distance(&rect, x, y) <= distance + 1e-6);
+                assert!(metric.max_distance(&rect, x, y) >= distance - 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn it_finds_the_nearest_points_under_any_metric() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(-180.0, -90.0, 360.0, 180.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut points = Vec::new();
+        let mut rng = StdRng::seed_from_u64(862);
+        for i in 0..1000 {
+            let point = Point2D::new(rng.gen_range(-180.0..180.0), rng.gen_range(-90.0..90.0), i);
+            quadtree.insert(point)?;
+            sparse.insert(point)?;
+            points.push(point);
+        }
+
+        let metrics: [&dyn Metric; 3] = [&Euclidean, &Manhattan, &Haversine];
+        for metric in metrics {
+            let (x, y) = (175.0, -60.0);
+            let mut expected: Vec<f64> =
+                points.iter().map(|p| metric.distance(x, y, p.x, p.y)).collect();
+            expected.sort_by(f64::total_cmp);
+            let distances = |found: Vec<(&Point2D<usize>, f64)>| -> Vec<f64> {
+                found.into_iter().map(|(_, d)| d).collect()
+            };
+            assert_eq!(distances(quadtree.k_nearest_by(x, y, 10, metric)), expected[..10]);
+            assert_eq!(distances(sparse.k_nearest_by(x, y, 10, metric)), expected[..10]);
+            let radius = expected[25];
+            let within = quadtree.query_radius_by(x, y, radius, metric);
+            assert_eq!(distances(within), expected[..26]);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Z-order (Morton) codes, the keys `LinearQuadTree` sorts its points by.
+//! Sorting input by `encode` groups nearby points together, which makes bulk
+//! loading faster, and the codes work as range-friendly database keys.
+
+use crate::geometry::Rectangle;
+
+// spreads the lower 32 bits of `value` to the even bits of the result
+fn spread(value: u32) -> u64 {
+    let mut v = value as u64;
+    v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+    v
+}
+
+// inverse of `spread`, collects the even bits of `value`
+fn compact(value: u64) -> u32 {
+    let mut v = value & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v >> 16)) & 0x0000_0000_ffff_ffff;
+    v as u32
+}
+
+/// Interleaves cell coordinates into a Z-order code, x in the even bits.
+pub fn encode_cell(cx: u32, cy: u32) -> u64 {
+    spread(cx) | (spread(cy) << 1)
+}
+
+/// Cell coordinates of a Z-order code, the inverse of `encode_cell`.
+pub fn decode_cell(code: u64) -> (u32, u32) {
+    (compact(code), compact(code >> 1))
+}
+
+/// Z-order code of the cell containing (x, y) when `boundary` is split into
+/// `2^depth` cells per axis. Coordinates outside the boundary are clamped.
+/// Panics if `depth` is above 32.
+pub fn encode(x: f64, y: f64, boundary: &Rectangle, depth: u32) -> u64 {
+    assert!(depth <= 32, "depth {} doesn't fit into a u64 code", depth);
+    let cells = (1u64 << depth) as f64;
+    let quantize = |value: f64, origin: f64, extent: f64| {
+        let cell = ((value - origin) / extent * cells).floor();
+        cell.clamp(0.0, cells - 1.0) as u32
+    };
+    encode_cell(
+        quantize(x, boundary.x, boundary.width),
+        quantize(y, boundary.y, boundary.height),
+    )
+}
+
+/// Region covered by the cell with the given code prefix at `level`.
+pub(crate) fn cell_rectangle(prefix: u64, level: u32, boundary: &Rectangle) -> Rectangle {
+    let (cx, cy) = decode_cell(prefix);
+    let cells = (1u64 << level) as f64;
+    let width = boundary.width / cells;
+    let height = boundary.height / cells;
+    Rectangle::new(
+        boundary.x + cx as f64 * width,
+        boundary.y + cy as f64 * height,
+        width,
+        height,
+    )
+}
+
+/// Region of the cell a code from `encode` with the same `boundary` and
+/// `depth` stands for.
+pub fn decode(code: u64, boundary: &Rectangle, depth: u32) -> Rectangle {
+    cell_rectangle(code, depth, boundary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_codes() {
+        assert_eq!(encode_cell(0b11, 0b01), 0b0111);
+        assert_eq!(decode_cell(0b0111), (0b11, 0b01));
+        assert_eq!(decode_cell(encode_cell(u32::MAX, 12345)), (u32::MAX, 12345));
+
+        let boundary = Rectangle::new(0.0, 0.0, 16.0, 16.0);
+        let code = encode(5.5, 9.0, &boundary, 2);
+        assert_eq!(decode_cell(code), (1, 2));
+        assert_eq!(decode(code, &boundary, 2), Rectangle::new(4.0, 8.0, 4.0, 4.0));
+        // outside the boundary clamps to the border cells
+        assert_eq!(decode_cell(encode(-3.0, 40.0, &boundary, 2)), (0, 3));
+        assert_eq!(decode_cell(encode(16.0, 16.0, &boundary, 32)), (u32::MAX, u32::MAX));
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geometry::Rectangle;
+
+/// Told about the changes of a `QuadTree` or `SparseQuadTree` it is
+/// registered on with `set_observer`, e.g. to invalidate render caches or
+/// collect metrics. All methods do nothing by default.
+///
+/// Observers are called while the tree is being changed, so they can't
+/// access it. Implementations keep their state behind a `Mutex` or atomics,
+/// as the tree may move between threads. They see positions but no payloads,
+/// so trees of borrowed payloads can have observers too.
+pub trait TreeObserver: Send + Sync {
+    /// A point was stored at (x, y).
+    fn on_insert(&self, _x: f64, _y: f64) {}
+
+    /// A point was removed from (x, y).
+    fn on_remove(&self, _x: f64, _y: f64) {}
+
+    /// The leaf covering `boundary` moved its points into new sub-trees.
+    fn on_split(&self, _boundary: &Rectangle) {}
+
+    /// The node covering `boundary` dropped its last sub-trees and is a leaf
+    /// again.
+    fn on_merge(&self, _boundary: &Rectangle) {}
+}
+
+// the observer of a tree, if there is one. Only the root's is ever set.
+#[derive(Clone, Default)]
+pub(crate) struct Observer(Option<Arc<dyn TreeObserver>>);
+
+impl Observer {
+    pub(crate) fn new(observer: Arc<dyn TreeObserver>) -> Self {
+        Observer(Some(observer))
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn into_inner(self) -> Option<Arc<dyn TreeObserver>> {
+        self.0
+    }
+
+    pub(crate) fn inserted(&self, x: f64, y: f64) {
+        if let Some(observer) = &self.0 {
+            observer.on_insert(x, y);
+        }
+    }
+
+    pub(crate) fn removed(&self, x: f64, y: f64) {
+        if let Some(observer) = &self.0 {
+            observer.on_remove(x, y);
+        }
+    }
+
+    pub(crate) fn split(&self, boundary: &Rectangle) {
+        if let Some(observer) = &self.0 {
+            observer.on_split(boundary);
+        }
+    }
+
+    pub(crate) fn merged(&self, boundary: &Rectangle) {
+        if let Some(observer) = &self.0 {
+            observer.on_merge(boundary);
+        }
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.is_set() { "Observer(set)" } else { "Observer(none)" })
+    }
+}
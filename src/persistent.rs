@@ -0,0 +1,219 @@
+use std::sync::Arc;
+
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+#[derive(Debug)]
+enum Node<T> {
+    Leaf {
+        boundary: Rectangle,
+        points: Vec<Point2D<T>>,
+    },
+    Branch {
+        boundary: Rectangle,
+        // ne, se, sw, nw
+        children: [Arc<Node<T>>; 4],
+    },
+}
+
+/// An immutable quadtree: `insert` returns a new tree which shares every
+/// node off the path to the new point with the old one, so keeping many
+/// versions around (e.g. for undo) only costs the changed paths. Cloning a
+/// tree is O(1).
+#[derive(Debug, Clone)]
+pub struct PersistentQuadTree<T> {
+    root: Arc<Node<T>>,
+    count: usize,
+}
+
+impl<T: Clone> PersistentQuadTree<T> {
+    const MAX_CAPACITY: usize = 4;
+    const MAX_DEPTH: usize = 32;
+
+    pub fn new(boundary: Rectangle) -> Self {
+        debug_assert!(boundary.is_valid(), "invalid boundary {:?}", boundary);
+        PersistentQuadTree {
+            root: Arc::new(Node::Leaf {
+                boundary,
+                points: Vec::new(),
+            }),
+            count: 0,
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        self.root.boundary()
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// A new tree with `point` added, `self` stays unchanged.
+    pub fn insert(&self, point: Point2D<T>) -> Result<Self, InsertError<T>> {
+        let point = InsertError::check(self.boundary(), point)?;
+        Ok(PersistentQuadTree {
+            root: Self::insert_into(&self.root, point, 0),
+            count: self.count + 1,
+        })
+    }
+
+    // copies the path down to the leaf receiving the point, every other
+    // node is shared
+    fn insert_into(node: &Arc<Node<T>>, point: Point2D<T>, depth: usize) -> Arc<Node<T>> {
+        match node.as_ref() {
+            Node::Branch { boundary, children } => {
+                let mut children = children.clone();
+                let index = boundary.which_quadrant(point.x, point.y).index();
+                children[index] = Self::insert_into(&children[index], point, depth + 1);
+                Arc::new(Node::Branch {
+                    boundary: *boundary,
+                    children,
+                })
+            }
+            Node::Leaf { boundary, points } => {
+                let mut points = points.clone();
+                points.push(point);
+                Arc::new(Self::leaf(*boundary, points, depth))
+            }
+        }
+    }
+
+    // a leaf for the points, split like in SparseQuadTree when it overflows
+    fn leaf(boundary: Rectangle, points: Vec<Point2D<T>>, depth: usize) -> Node<T> {
+        let first = &points[0];
+        if points.len() <= Self::MAX_CAPACITY
+            || depth >= Self::MAX_DEPTH
+            || points.iter().all(|p| p.x == first.x && p.y == first.y)
+        {
+            return Node::Leaf { boundary, points };
+        }
+
+        let mut quadrants: [Vec<Point2D<T>>; 4] = Default::default();
+        for point in points {
+            quadrants[boundary.which_quadrant(point.x, point.y).index()].push(point);
+        }
+        let [ne, se, sw, nw] = quadrants;
+        let child = |boundary, points: Vec<Point2D<T>>| {
+            Arc::new(if points.is_empty() {
+                Node::Leaf { boundary, points }
+            } else {
+                Self::leaf(boundary, points, depth + 1)
+            })
+        };
+        Node::Branch {
+            boundary,
+            children: [
+                child(boundary.new_ne(), ne),
+                child(boundary.new_se(), se),
+                child(boundary.new_sw(), sw),
+                child(boundary.new_nw(), nw),
+            ],
+        }
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let region = boundary.into();
+        let mut result = Vec::new();
+        let mut stack = vec![self.root.as_ref()];
+        while let Some(node) = stack.pop() {
+            if !region.intersects(node.boundary()) {
+                continue;
+            }
+            match node {
+                Node::Leaf { points, .. } => {
+                    result.extend(points.iter().filter(|p| region.contains(p.x, p.y)))
+                }
+                // reversed, so children are visited in ne, se, sw, nw order
+                Node::Branch { children, .. } => {
+                    stack.extend(children.iter().rev().map(|child| child.as_ref()))
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<T> Node<T> {
+    fn boundary(&self) -> &Rectangle {
+        match self {
+            Node::Leaf { boundary, .. } | Node::Branch { boundary, .. } => boundary,
+        }
+    }
+}
+
+impl<T: Clone> SpatialIndex<T> for PersistentQuadTree<T> {
+    fn new(boundary: Rectangle) -> Self {
+        PersistentQuadTree::new(boundary)
+    }
+
+    // replaces this version by the new one
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        *self = PersistentQuadTree::insert(self, point)?;
+        Ok(())
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_keeps_old_versions_intact() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(831);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut versions = vec![PersistentQuadTree::new(boundary)];
+        for i in 0..500 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            let next = versions.last().unwrap().insert(point)?;
+            versions.push(next);
+        }
+        assert!(versions[0].insert(Point2D::new(-1.0, 0.0, 0)).is_err());
+
+        for (i, version) in versions.iter().enumerate() {
+            assert_eq!(version.count(), i);
+            let mut found: Vec<i32> = version.query(boundary).iter().map(|p| p.data).collect();
+            found.sort();
+            assert_eq!(found, (0..i as i32).collect::<Vec<_>>());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_shares_untouched_nodes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = PersistentQuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for (x, y) in [(10.0, 10.0), (20.0, 20.0), (70.0, 10.0), (70.0, 70.0), (10.0, 70.0)] {
+            tree = tree.insert(Point2D::new(x, y, ()))?;
+        }
+        let next = tree.insert(Point2D::new(30.0, 30.0, ()))?;
+
+        let (Node::Branch { children: old, .. }, Node::Branch { children: new, .. }) =
+            (tree.root.as_ref(), next.root.as_ref())
+        else {
+            panic!("expected the root to be split");
+        };
+        // only the north-west quadrant received the point
+        assert!(!Arc::ptr_eq(&old[3], &new[3]));
+        for i in 0..3 {
+            assert!(Arc::ptr_eq(&old[i], &new[i]));
+        }
+
+        Ok(())
+    }
+}
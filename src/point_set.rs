@@ -0,0 +1,141 @@
+use crate::entry::Entry;
+use crate::error::RejectReason;
+use crate::geometry::{Point2D, Rectangle};
+use crate::quadtree_option::QuadTree;
+
+/// A set of positions without payloads, for when only where the points are
+/// matters. Backed by a `SparseQuadTree<()>`, whose points are just their two
+/// coordinates as `()` takes no space, and works with plain `(x, y)` pairs.
+/// Each position is stored once, inserting it again leaves the set as is.
+#[derive(Debug, Clone)]
+pub struct PointSet {
+    tree: QuadTree<()>,
+}
+
+impl PointSet {
+    pub fn new(boundary: Rectangle) -> Self {
+        PointSet {
+            tree: QuadTree::new(boundary),
+        }
+    }
+
+    /// A set of the given positions, failing on the first one it can't hold.
+    pub fn from_positions(
+        boundary: Rectangle,
+        positions: impl IntoIterator<Item = (f64, f64)>,
+    ) -> Result<Self, RejectReason> {
+        let mut set = PointSet::new(boundary);
+        for (x, y) in positions {
+            set.insert(x, y)?;
+        }
+        Ok(set)
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        self.tree.boundary()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds (x, y) to the set, returning whether it wasn't in it yet.
+    pub fn insert(&mut self, x: f64, y: f64) -> Result<bool, RejectReason> {
+        match self.tree.entry(x, y)? {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(());
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        !self.tree.find_all(x, y, 0.0).is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+
+    /// The positions inside `boundary`.
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<(f64, f64)> {
+        self.tree.query_map(boundary, Point2D::position)
+    }
+
+    /// The positions at most `radius` away from (x, y), with their distance.
+    pub fn query_radius(&self, x: f64, y: f64, radius: f64) -> Vec<((f64, f64), f64)> {
+        let found = self.tree.query_radius(x, y, radius);
+        found.into_iter().map(|(point, distance)| (point.position(), distance)).collect()
+    }
+
+    /// The position closest to (x, y).
+    pub fn nearest(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        self.tree.nearest_iter(x, y).next().map(|(point, _)| point.position())
+    }
+
+    /// Every position in the set, in no particular order.
+    pub fn positions(&self) -> Vec<(f64, f64)> {
+        self.query(*self.boundary())
+    }
+
+    /// The underlying tree, for everything the set doesn't forward.
+    pub fn tree(&self) -> &QuadTree<()> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_stores_each_position_once() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(std::mem::size_of::<Point2D<()>>(), 16);
+
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(894);
+        let positions: Vec<(f64, f64)> = (0..1000)
+            .map(|_| (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)))
+            .collect();
+        let mut set = PointSet::from_positions(boundary, positions.iter().copied())?;
+        assert_eq!(set.len(), 1000);
+        assert!(!set.insert(positions[10].0, positions[10].1)?);
+        assert!(set.insert(100.0, 100.0)?);
+        assert_eq!(set.len(), 1001);
+        assert!(set.contains(positions[500].0, positions[500].1));
+        assert!(!set.contains(positions[500].0 + 1e-9, positions[500].1));
+        assert_eq!(set.insert(f64::NAN, 1.0), Err(RejectReason::InvalidCoordinates));
+        assert_eq!(set.insert(101.0, 1.0), Err(RejectReason::OutOfBounds));
+
+        let region = Rectangle::new(20.0, 20.0, 30.0, 30.0);
+        let mut found = set.query(region);
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut expected: Vec<(f64, f64)> =
+            positions.iter().copied().filter(|&(x, y)| region.contains(x, y)).collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found, expected);
+
+        let (x, y) = set.nearest(50.0, 50.0).ok_or("empty")?;
+        let closest = set
+            .positions()
+            .iter()
+            .map(|&(px, py)| (px - 50.0).hypot(py - 50.0))
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!((x - 50.0).hypot(y - 50.0), closest);
+        assert!(set.query_radius(50.0, 50.0, 10.0).iter().all(|&(_, distance)| distance <= 10.0));
+        assert_eq!(set.positions().len(), set.len());
+
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.nearest(50.0, 50.0), None);
+
+        Ok(())
+    }
+}
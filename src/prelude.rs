@@ -0,0 +1,11 @@
+//! The traits, tree types, geometry and errors most code needs:
+//!
+//! ```
+//! use quadtree::prelude::*;
+//! ```
+
+pub use crate::{
+    Aggregate, AggregateQuadTree, InsertError, LinearQuadTree, Packable, PersistentQuadTree,
+    Point2D, QuadTree, Quadrant, RangeJoin, Rectangle, RejectReason, SparseQuadTree, SpatialIndex,
+    VecIndex,
+};
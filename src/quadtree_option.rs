@@ -1,185 +1,3964 @@
-use crate::geometry::{Point2D, Rectangle};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{fmt, io, mem, ptr};
 
-#[derive(Debug)]
-pub struct QuadTree<T: std::fmt::Debug> {
+use rand::Rng;
+
+use crate::aggregate::{Aggregate, Centroid};
+use crate::bytes::{self, ByteReader, Packable, Source, StreamReader};
+use crate::diff::{self, TreeDiff};
+use crate::entry::{Entry, EntryTree, Sealed};
+use crate::error::{InsertError, RejectReason};
+use crate::geometry::{
+    self, Cell, Circle, Grid, Point2D, PointMut, Quadrant, QueryEdges, Rectangle, SplitStrategy,
+};
+use crate::join::RangeJoin;
+use crate::metric::{Euclidean, Metric};
+use crate::observer::{Observer, TreeObserver};
+use crate::spatial_index::SpatialIndex;
+use crate::traversal::{
+    self, NodePath, NodeView, QueryResume, QueryStats, TraversalBudget, TreeNode,
+};
+
+type ClosestPair<'a, T> = Option<(&'a Point2D<T>, &'a Point2D<T>, f64)>;
+
+// shared by all trees, so a hint never matches a tree it doesn't come from
+static GENERATIONS: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    GENERATIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Where `insert_with_hint` stored a point. Handing it to the next call lets
+/// a point nearby, e.g. the next fix of a GPS trace, follow the same path
+/// without being routed at every node. A hint goes stale once nodes split,
+/// merge or move, or when the point lies outside the hinted leaf, and the
+/// insert then finds its way from the root as usual.
+#[derive(Debug, Clone)]
+pub struct InsertHint {
+    generation: u64,
+    path: Vec<Quadrant>,
+    // the positions the hinted leaf holds
+    cell: Cell,
+}
+
+// a run of matching points when sampling: either a whole sub-tree inside the
+// region or a single matching point of a partially overlapping node
+enum SampleBlock<'a, T> {
+    Subtree(&'a QuadTree<T>, usize),
+    Point(&'a Point2D<T>),
+}
+
+#[derive(Debug, Clone)]
+pub struct QuadTree<T> {
     boundary: Rectangle,
     points: Vec<Point2D<T>>,
+    // points stored in this node and all sub-trees below it
+    count: usize,
+    // this node or a sub-tree below it changed since the last
+    // `serialize_dirty`
+    dirty: bool,
     ne: Option<Box<QuadTree<T>>>,
     se: Option<Box<QuadTree<T>>>,
     sw: Option<Box<QuadTree<T>>>,
     nw: Option<Box<QuadTree<T>>>,
+    // points a node at the given depth holds before it splits, the same
+    // policy is copied into every node of the tree
+    capacity: fn(usize) -> usize,
+    // picks `split_point` when the node splits, copied like the capacity
+    strategy: SplitStrategy,
+    // where the sub-trees meet, only meaningful while there are any
+    split_point: (f64, f64),
+    // (min_x, min_y, max_x, max_y) of the points stored in this node and
+    // below, `None` while there are none. Queries skip nodes whose points
+    // all lie outside the region even if their boundary overlaps it.
+    extent: Option<(f64, f64, f64, f64)>,
+    // only set on the root
+    observer: Observer,
+    // renewed on the root whenever nodes split, merge or move, which makes
+    // outstanding `InsertHint`s stale
+    generation: u64,
 }
 
-impl<T: std::fmt::Debug> QuadTree<T> {
+impl<T> QuadTree<T> {
     const MAX_CAPACITY: usize = 4;
+    // leaves this deep grow beyond MAX_CAPACITY instead of splitting, so
+    // nearly identical points can't build endless chains of sub-trees
+    const MAX_DEPTH: usize = 32;
 
     pub fn new(boundary: Rectangle) -> Self {
+        QuadTree::with_capacity_policy(boundary, |_| QuadTree::<T>::MAX_CAPACITY)
+    }
+
+    /// A tree whose nodes at depth `d` split once they hold more than
+    /// `capacity(d)` points, the root being at depth 0. Letting shallow nodes
+    /// hold more points avoids over-splitting sparse areas while dense
+    /// hotspots still get deep, small leaves.
+    pub fn with_capacity_policy(boundary: Rectangle, capacity: fn(usize) -> usize) -> Self {
+        QuadTree::with_policies(boundary, capacity, SplitStrategy::Midpoint)
+    }
+
+    /// A tree whose nodes split according to `strategy`, e.g. at the median
+    /// of their points for skewed data. Sub-trees of a node split elsewhere
+    /// than at its center no longer cover equal quarters of it.
+    pub fn with_split_strategy(boundary: Rectangle, strategy: SplitStrategy) -> Self {
+        QuadTree::with_policies(boundary, |_| QuadTree::<T>::MAX_CAPACITY, strategy)
+    }
+
+    fn with_policies(
+        boundary: Rectangle,
+        capacity: fn(usize) -> usize,
+        strategy: SplitStrategy,
+    ) -> Self {
+        debug_assert!(boundary.is_valid(), "invalid boundary {:?}", boundary);
         QuadTree {
             boundary,
             points: Vec::new(),
+            count: 0,
+            dirty: true,
             ne: None,
             se: None,
             sw: None,
             nw: None,
+            capacity,
+            strategy,
+            split_point: boundary.center(),
+            extent: None,
+            observer: Observer::default(),
+            generation: next_generation(),
         }
     }
 
+    // an empty sub-tree covering `boundary` with the policies of this node
+    fn subtree(&self, boundary: Rectangle) -> Box<QuadTree<T>> {
+        Box::new(QuadTree::with_policies(boundary, self.capacity, self.strategy))
+    }
+
+    pub fn split_strategy(&self) -> SplitStrategy {
+        self.strategy
+    }
+
+    /// Registers `observer` to be told about inserted and removed points and
+    /// nodes splitting and merging, replacing the previous one. `rebuild`,
+    /// `translate`, `scale`, `apply_dirty` and `query_mut` change the tree
+    /// without telling it. Clones of the tree share the observer.
+    pub fn set_observer(&mut self, observer: Arc<dyn TreeObserver>) {
+        self.observer = Observer::new(observer);
+    }
+
+    /// Unregisters the observer and returns it.
+    pub fn remove_observer(&mut self) -> Option<Arc<dyn TreeObserver>> {
+        mem::take(&mut self.observer).into_inner()
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    /// Capacity of the root node, see `capacity_at` for deeper nodes.
+    pub fn capacity(&self) -> usize {
+        self.capacity_at(0)
+    }
+
+    pub fn capacity_at(&self, depth: usize) -> usize {
+        (self.capacity)(depth)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.subtrees().next().is_none()
+    }
+
     pub fn count(&self) -> usize {
-        return self.points.len()
-            + self.ne.as_ref().map_or(0, |ne| ne.count())
-            + self.se.as_ref().map_or(0, |se| se.count())
-            + self.sw.as_ref().map_or(0, |sw| sw.count())
-            + self.nw.as_ref().map_or(0, |nw| nw.count());
+        self.count
     }
 
-    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), &'static str> {
-        if !self.boundary.contains(point.x, point.y) {
-            return Err("Boundary doesn't contain point");
+    /// Depth of the deepest node, the root being at depth 0.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(self, 0)];
+        while let Some((node, level)) = stack.pop() {
+            depth = depth.max(level);
+            stack.extend(node.subtrees().map(|subtree| (subtree, level + 1)));
         }
-        
-        if self.points.len() < QuadTree::<T>::MAX_CAPACITY {
-            self.points.push(point);
-            return Ok(());
+        depth
+    }
+
+    // insert and query walk the tree with an explicit stack instead of
+    // recursion, so degenerate trees (many near-identical points) can't
+    // overflow the call stack
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        self.observer.inserted(point.x, point.y);
+        let observer = self.observer.clone();
+
+        // points are stored in leaves, descend until we reach one
+        // if the sub-tree on the way doesn't exist, create it
+        let mut node = &mut *self;
+        let mut depth = 0;
+        while !node.is_leaf() {
+            node.count += 1;
+            node.dirty = true;
+            node.extent = geometry::extend_extent(node.extent, &point);
+            let (policy, strategy) = (node.capacity, node.strategy);
+            let (subtree, boundary) = node.quadrant_mut(point.x, point.y);
+            node = subtree.get_or_insert_with(|| {
+                Box::new(QuadTree::with_policies(boundary, policy, strategy))
+            });
+            depth += 1;
+        }
+        if node.store(point, depth, &observer) {
+            self.generation = next_generation();
         }
+        self.validate("insert");
+        Ok(())
+    }
 
-        // we need to insert the point in a sub-tree
-        // if the sub-tree doesn't exist, create it
-        let half_width = self.boundary.width / 2.0;
-        let half_height = self.boundary.height / 2.0;
-        let half_x = half_width + self.boundary.x;
-        let half_y = half_height + self.boundary.y;
+    /// Like `insert`, but follows the path of `hint`, which an earlier call
+    /// returned, if it is still valid and leads to where the point belongs.
+    /// Returns the hint for the next insert. Only the routing is skipped,
+    /// the nodes on the path still update their count and extent. `insert`
+    /// doesn't hand out hints itself, as it shares its signature with
+    /// `SpatialIndex::insert` and the other trees.
+    pub fn insert_with_hint(
+        &mut self,
+        point: Point2D<T>,
+        hint: Option<InsertHint>,
+    ) -> Result<InsertHint, InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        let (x, y) = (point.x, point.y);
+        let mut hint = match hint {
+            Some(hint) if hint.generation == self.generation && hint.cell.owns(x, y) => hint,
+            _ => self.route(x, y),
+        };
+        self.observer.inserted(x, y);
+        let observer = self.observer.clone();
 
-        let subtree = if point.x < half_x {
-            if point.y < half_y {
-                if self.nw.is_none() {
-                    self.nw = Some(Box::new(QuadTree::new(self.boundary.new_nw())));
-                }
-                self.nw.as_mut().unwrap()
-            } else {
-                if self.sw.is_none() {
-                    self.sw = Some(Box::new(QuadTree::new(self.boundary.new_sw())));
-                }
-                self.sw.as_mut().unwrap()
+        let mut node = &mut *self;
+        for (depth, &quadrant) in hint.path.iter().enumerate() {
+            node.count += 1;
+            node.dirty = true;
+            node.extent = geometry::extend_extent(node.extent, &point);
+            if node.child(quadrant).is_none() {
+                // only the last sub-tree on the path may be missing
+                debug_assert_eq!(depth + 1, hint.path.len());
+                let subtree = node.subtree(node.boundary.quadrant_at(quadrant, node.split_point));
+                *node.slot_mut(quadrant) = Some(subtree);
             }
-        } else {
-            if point.y < half_y {
-                if self.ne.is_none() {
-                    self.ne = Some(Box::new(QuadTree::new(self.boundary.new_ne())));
-                }
-                self.ne.as_mut().unwrap()
-            } else {
-                if self.se.is_none() {
-                    self.se = Some(Box::new(QuadTree::new(self.boundary.new_se())));
+            node = node.slot_mut(quadrant).as_deref_mut().expect("sub-tree on the path");
+        }
+        debug_assert!(node.is_leaf());
+        if node.store(point, hint.path.len(), &observer) {
+            // the split makes all hints stale, this one moves on to the
+            // leaf the point went to
+            node.extend_route(x, y, &mut hint);
+            self.generation = next_generation();
+            hint.generation = self.generation;
+        }
+        self.validate("insert_with_hint");
+        Ok(hint)
+    }
+
+    // the path insert takes for (x, y), ending at a leaf or a missing
+    // sub-tree, and the cell of the node it ends at
+    fn route(&self, x: f64, y: f64) -> InsertHint {
+        let mut hint = InsertHint {
+            generation: self.generation,
+            path: Vec::new(),
+            cell: Cell::root(&self.boundary),
+        };
+        self.extend_route(x, y, &mut hint);
+        hint
+    }
+
+    // continues the route of `hint`, which leads to this node, below it
+    fn extend_route(&self, x: f64, y: f64, hint: &mut InsertHint) {
+        let mut node = self;
+        while !node.is_leaf() {
+            let quadrant = Quadrant::of(node.split_point, x, y);
+            hint.path.push(quadrant);
+            hint.cell = hint.cell.child(quadrant, node.split_point);
+            match node.child(quadrant) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+    }
+
+    // adds the point to this leaf at `depth` and subdivides it once it
+    // overflows. Returns whether it did.
+    fn store(&mut self, point: Point2D<T>, depth: usize, observer: &Observer) -> bool {
+        debug_assert!(self.boundary.contains(point.x, point.y));
+        self.extent = geometry::extend_extent(self.extent, &point);
+        self.points.push(point);
+        self.count += 1;
+        self.dirty = true;
+        if self.points.len() > self.capacity_at(depth) {
+            self.subdivide(depth, observer);
+        }
+        !self.is_leaf()
+    }
+
+    // moves the points of an overflowing leaf into its sub-trees, creating only
+    // the ones receiving points. As in the enum tree no points stay behind, so
+    // only leaves store points. Identical points can't be separated by any
+    // split, so leaves holding only those are allowed to grow instead, as are
+    // leaves at MAX_DEPTH.
+    fn subdivide(&mut self, depth: usize, observer: &Observer) {
+        let first = &self.points[0];
+        if depth >= QuadTree::<T>::MAX_DEPTH
+            || self.points.iter().all(|p| p.x == first.x && p.y == first.y)
+        {
+            return;
+        }
+
+        self.split_point = self.strategy.split_point(&self.boundary, &self.points);
+        let (policy, strategy) = (self.capacity, self.strategy);
+        for point in mem::take(&mut self.points) {
+            let (subtree, boundary) = self.quadrant_mut(point.x, point.y);
+            let subtree = subtree.get_or_insert_with(|| {
+                Box::new(QuadTree::with_policies(boundary, policy, strategy))
+            });
+            subtree.extent = geometry::extend_extent(subtree.extent, &point);
+            subtree.points.push(point);
+            subtree.count += 1;
+            subtree.dirty = true;
+        }
+        observer.split(&self.boundary);
+        for subtree in [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw]
+            .into_iter()
+            .flatten()
+        {
+            if subtree.points.len() > subtree.capacity_at(depth + 1) {
+                subtree.subdivide(depth + 1, observer);
+            }
+        }
+    }
+
+    /// Like `insert`, but points outside the boundary are moved onto its
+    /// closest edge instead of being rejected. Returns the offset from the
+    /// stored position back to the original one, (0, 0) for points inside.
+    pub fn insert_clamped(
+        &mut self,
+        mut point: Point2D<T>,
+    ) -> Result<(f64, f64), InsertError<T>> {
+        let (x, y) = self.boundary.clamp_point(point.x, point.y);
+        let offset = (point.x - x, point.y - y);
+        (point.x, point.y) = (x, y);
+        self.insert(point)?;
+        Ok(offset)
+    }
+
+    /// Merges `data` into the point stored at exactly (x, y), or inserts a
+    /// new point there if there is none.
+    pub fn upsert(
+        &mut self,
+        x: f64,
+        y: f64,
+        data: T,
+        mut merge: impl FnMut(&mut T, T),
+    ) -> Result<(), InsertError<T>> {
+        match self.find_exact_mut(x, y) {
+            Some(point) => {
+                merge(&mut point.data, data);
+                Ok(())
+            }
+            None => self.insert(Point2D { x, y, data }),
+        }
+    }
+
+    // follows the path insert takes for (x, y), which is the only place a
+    // point with these exact coordinates can be stored. The path is marked
+    // dirty, as the caller may change the point.
+    fn find_exact_mut(&mut self, x: f64, y: f64) -> Option<&mut Point2D<T>> {
+        if !self.boundary.contains(x, y) {
+            return None;
+        }
+        self.dirty = true;
+        if let Some(index) = self.points.iter().position(|p| p.x == x && p.y == y) {
+            return Some(&mut self.points[index]);
+        }
+        self.quadrant_mut(x, y)
+            .0
+            .as_mut()
+            .and_then(|subtree| subtree.find_exact_mut(x, y))
+    }
+
+    /// Gives access to the payload stored at exactly (x, y), inserting it on
+    /// demand.
+    pub fn entry(&mut self, x: f64, y: f64) -> Result<Entry<'_, T, Self>, RejectReason> {
+        RejectReason::check(&self.boundary, x, y)?;
+        Ok(Entry::new(self, x, y))
+    }
+
+    /// The stored point closest to (x, y) among those at most `epsilon` away
+    /// along each axis. Unlike exact lookups this survives rounding errors
+    /// from coordinate transformations.
+    pub fn find(&self, x: f64, y: f64, epsilon: f64) -> Option<&Point2D<T>> {
+        self.find_all(x, y, epsilon).into_iter().min_by(|a, b| {
+            let a = a.distance_squared_to_position(x, y);
+            let b = b.distance_squared_to_position(x, y);
+            a.total_cmp(&b)
+        })
+    }
+
+    /// Every stored point at most `epsilon` away from (x, y) along each axis.
+    /// Only the quadrants overlapping that box are visited.
+    pub fn find_all(&self, x: f64, y: f64, epsilon: f64) -> Vec<&Point2D<T>> {
+        match Rectangle::from_center(x, y, epsilon, epsilon) {
+            Ok(region) => self.query(region),
+            // negative or NaN tolerances match nothing
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn find_exact_slot(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        if !self.boundary.contains(x, y) {
+            return None;
+        }
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            if let Some(index) = node.points.iter().position(|p| p.x == x && p.y == y) {
+                return Some((depth, index));
+            }
+            node = node.quadrant(x, y)?;
+            depth += 1;
+        }
+    }
+
+    // the existing sub-tree responsible for (x, y)
+    fn quadrant(&self, x: f64, y: f64) -> Option<&QuadTree<T>> {
+        let quadrant = Quadrant::of(self.split_point, x, y);
+        [&self.ne, &self.se, &self.sw, &self.nw][quadrant.index()].as_deref()
+    }
+
+    // the sub-tree slot responsible for (x, y) and the boundary it covers
+    fn quadrant_mut(&mut self, x: f64, y: f64) -> (&mut Option<Box<QuadTree<T>>>, Rectangle) {
+        let quadrant = Quadrant::of(self.split_point, x, y);
+        let boundary = self.boundary.quadrant_at(quadrant, self.split_point);
+        (self.slot_mut(quadrant), boundary)
+    }
+
+    fn slot_mut(&mut self, quadrant: Quadrant) -> &mut Option<Box<QuadTree<T>>> {
+        match quadrant {
+            Quadrant::NE => &mut self.ne,
+            Quadrant::SE => &mut self.se,
+            Quadrant::SW => &mut self.sw,
+            Quadrant::NW => &mut self.nw,
+        }
+    }
+
+    /// Removes all points but keeps the allocated sub-trees and their
+    /// capacity, so a tree rebuilt every frame doesn't reallocate its structure.
+    pub fn clear(&mut self) {
+        if self.observer.is_set() {
+            self.for_each_point(&mut |point| self.observer.removed(point.x, point.y));
+        }
+        self.clear_node();
+        self.validate("clear");
+    }
+
+    fn clear_node(&mut self) {
+        self.dirty |= self.count > 0;
+        self.points.clear();
+        self.count = 0;
+        self.extent = None;
+        for subtree in [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw]
+            .into_iter()
+            .flatten()
+        {
+            subtree.clear_node();
+        }
+    }
+
+    /// Removes all points and frees every sub-tree.
+    pub fn clear_and_shrink(&mut self) {
+        let observer = mem::take(&mut self.observer);
+        self.for_each_point(&mut |point| observer.removed(point.x, point.y));
+        if !self.is_leaf() {
+            observer.merged(&self.boundary);
+        }
+        *self = *self.subtree(self.boundary);
+        self.observer = observer;
+        self.validate("clear_and_shrink");
+    }
+
+    /// Releases memory kept for later: shrinks the point vectors and drops
+    /// sub-trees without points, e.g. after `clear`.
+    pub fn shrink_to_fit(&mut self) {
+        let observer = mem::take(&mut self.observer);
+        self.shrink_node(&observer);
+        self.observer = observer;
+        self.generation = next_generation();
+        self.validate("shrink_to_fit");
+    }
+
+    fn shrink_node(&mut self, observer: &Observer) {
+        self.points.shrink_to_fit();
+        let was_leaf = self.is_leaf();
+        for slot in [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw] {
+            if let Some(subtree) = slot {
+                subtree.shrink_node(observer);
+                self.dirty |= subtree.dirty;
+                if subtree.is_leaf() && subtree.points.is_empty() {
+                    *slot = None;
+                    self.dirty = true;
                 }
-                self.se.as_mut().unwrap()
             }
-        };
-        return subtree.insert(point);
+        }
+        if !was_leaf && self.is_leaf() {
+            observer.merged(&self.boundary);
+        }
+    }
+
+    /// Reconstructs the tree from its current points in one pass, dropping
+    /// sub-trees left empty by `clear`.
+    pub fn rebuild(&mut self) {
+        let observer = mem::take(&mut self.observer);
+        let empty = *self.subtree(self.boundary);
+        let points = mem::replace(self, empty).into_points();
+        *self = self.bulk_load(self.boundary, points, 0);
+        self.observer = observer;
+        self.validate("rebuild");
     }
 
-    pub fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
-        let mut result: Vec<&Point2D<T>> = Vec::new();
+    /// Moves the boundary and every point by (dx, dy), e.g. when the world
+    /// origin shifts. Sub-trees move along without re-bucketing, only points
+    /// rounding carries across an edge of their sub-tree are inserted again.
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        assert!(dx.is_finite() && dy.is_finite(), "invalid offset ({}, {})", dx, dy);
+        self.remap(|x, y| (x + dx, y + dy));
+        self.validate("translate");
+    }
 
-        for point in self.points.iter() {
-            if boundary.contains(point.x, point.y) {
-                result.push(point);
+    /// Scales the boundary and every point by `sx` and `sy` about the origin,
+    /// without re-bucketing like `translate`. Panics unless both factors are
+    /// positive and finite.
+    pub fn scale(&mut self, sx: f64, sy: f64) {
+        let valid = |factor: f64| factor > 0.0 && factor.is_finite();
+        assert!(valid(sx) && valid(sy), "invalid scale ({}, {})", sx, sy);
+        self.remap(|x, y| (x * sx, y * sy));
+        self.validate("scale");
+    }
+
+    // moves every sub-tree and point with `f`, which keeps the order of
+    // coordinates along each axis, so the tree's shape stays valid. Points
+    // don't come or go, so the observer isn't told.
+    fn remap(&mut self, f: impl Fn(f64, f64) -> (f64, f64)) {
+        let boundary = self.boundary.mapped(&f);
+        self.generation = next_generation();
+        let mut stray = Vec::new();
+        self.remap_node(boundary, Cell::root(&boundary), &f, &mut stray);
+        let observer = mem::take(&mut self.observer);
+        for point in stray {
+            // the point may lie just outside after rounding the boundary
+            self.insert_clamped(point)
+                .unwrap_or_else(|_| panic!("remapped point outside {:?}", boundary));
+        }
+        self.observer = observer;
+    }
+
+    fn remap_node(
+        &mut self,
+        new: Rectangle,
+        cell: Cell,
+        f: &impl Fn(f64, f64) -> (f64, f64),
+        stray: &mut Vec<Point2D<T>>,
+    ) {
+        self.split_point = geometry::remap_split(self.split_point, &self.boundary, &new, f);
+        self.boundary = new;
+        self.dirty = true;
+        geometry::remap_points(&mut self.points, &cell, f, stray);
+        let slots = [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw];
+        for (slot, quadrant) in slots.into_iter().zip(Quadrant::ALL) {
+            if let Some(subtree) = slot {
+                let boundary = new.quadrant_at(quadrant, self.split_point);
+                subtree.remap_node(boundary, cell.child(quadrant, self.split_point), f, stray);
             }
         }
+        self.recount();
+    }
 
-        self.ne
-            .iter()
-            .chain(self.se.iter())
-            .chain(self.sw.iter())
-            .chain(self.nw.iter())
-            .for_each(|subtree| {
-                result.append(&mut subtree.query(boundary));
+    /// Moves all points of `other` into this tree, e.g. to combine trees built
+    /// on separate threads. When both trees share a boundary, capacity policy
+    /// and split strategy, sub-trees of `other` meeting a missing or empty
+    /// sub-tree are moved over whole instead of being inserted point by point.
+    /// Fails without changing the tree if a point of `other` lies outside the
+    /// boundary. With an observer set, all points are inserted one by one, so
+    /// it is told about each of them.
+    pub fn merge(&mut self, mut other: QuadTree<T>) -> Result<(), RejectReason> {
+        if !self.boundary.contains_rectangle(&other.boundary) {
+            let mut outside = Ok(());
+            other.for_each_point(&mut |point| {
+                outside = outside.and(RejectReason::check(&self.boundary, point.x, point.y));
             });
+            outside?;
+        }
+        // sub-trees moved over whole would carry along `other`'s observer
+        other.observer = Observer::default();
+        self.generation = next_generation();
+        let reuse = ptr::fn_addr_eq(self.capacity, other.capacity)
+            && self.strategy == other.strategy
+            && !self.observer.is_set();
+        if self.boundary != other.boundary {
+            return other
+                .into_points()
+                .into_iter()
+                .try_for_each(|point| self.insert(point).map_err(RejectReason::from));
+        }
 
-        result
+        // walk both trees in lockstep, collecting the points that can't move
+        // along with a whole sub-tree
+        let mut rest = Vec::new();
+        self.merge_node(other, reuse, &mut rest);
+        let merged = rest
+            .into_iter()
+            .try_for_each(|point| self.insert(point).map_err(RejectReason::from));
+        self.validate("merge");
+        merged
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::geometry::{Point2D, Rectangle};
+    // recursive, as the counts are fixed up on the way back. Trees are at
+    // most MAX_DEPTH deep.
+    fn merge_node(&mut self, mut other: QuadTree<T>, reuse: bool, rest: &mut Vec<Point2D<T>>) {
+        if reuse && self.is_leaf() {
+            if self.points.is_empty() {
+                *self = other;
+                self.mark_all_dirty();
+                return;
+            }
+            mem::swap(self, &mut other);
+            self.mark_all_dirty();
+        }
+        self.dirty = true;
+        rest.append(&mut other.points);
+        if !self.is_leaf() && !other.is_leaf() && self.split_point != other.split_point {
+            // the sub-trees cover different regions
+            rest.extend(other.into_points());
+            self.recount();
+            return;
+        }
+        let pairs = [
+            (&mut self.ne, other.ne),
+            (&mut self.se, other.se),
+            (&mut self.sw, other.sw),
+            (&mut self.nw, other.nw),
+        ];
+        for (slot, subtree) in pairs {
+            match (slot, subtree) {
+                (Some(slot), Some(subtree)) => slot.merge_node(*subtree, reuse, rest),
+                (slot @ None, Some(subtree)) if reuse => {
+                    slot.insert(subtree).mark_all_dirty();
+                }
+                (_, Some(subtree)) => rest.extend(subtree.into_points()),
+                (_, None) => {}
+            }
+        }
+        // points moved to `rest` are counted again when they are inserted
+        self.recount();
+    }
 
-    use super::*;
+    /// Consumes the tree and returns the stored points without cloning them,
+    /// in the order a query covering everything would visit them.
+    pub fn into_points(self) -> Vec<Point2D<T>> {
+        let mut points = Vec::with_capacity(self.count());
+        let mut stack = vec![self];
+        while let Some(mut node) = stack.pop() {
+            points.append(&mut node.points);
+            stack.extend(
+                [node.nw, node.sw, node.se, node.ne]
+                    .into_iter()
+                    .flatten()
+                    .map(|subtree| *subtree),
+            );
+        }
+        points
+    }
 
-    #[test]
-    fn it_inserts_a_point() -> Result<(), Box<dyn std::error::Error>> {
-        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
-        assert_eq!(quadtree.count(), 0);
+    // `self` only provides the capacity policy and split strategy
+    fn bulk_load(&self, boundary: Rectangle, points: Vec<Point2D<T>>, depth: usize) -> Self {
+        let mut tree = *self.subtree(boundary);
+        let first = points.first();
+        if points.len() <= self.capacity_at(depth)
+            || depth >= QuadTree::<T>::MAX_DEPTH
+            || points.iter().all(|p| first.is_some_and(|f| p.x == f.x && p.y == f.y))
+        {
+            tree.points = points;
+            tree.recount();
+            return tree;
+        }
 
-        let point = Point2D {
-            x: 10.0,
-            y: 10.0,
-            data: 42,
+        let split = self.strategy.split_point(&boundary, &points);
+        let mut quadrants: [Vec<Point2D<T>>; 4] = Default::default();
+        for point in points {
+            quadrants[Quadrant::of(split, point.x, point.y).index()].push(point);
+        }
+        let [ne, se, sw, nw] = quadrants;
+        let load = |quadrant, points: Vec<Point2D<T>>| {
+            if points.is_empty() {
+                return None;
+            }
+            let boundary = boundary.quadrant_at(quadrant, split);
+            Some(Box::new(self.bulk_load(boundary, points, depth + 1)))
         };
-        quadtree.insert(point)?;
-        assert_eq!(quadtree.count(), 1);
+        tree.split_point = split;
+        tree.ne = load(Quadrant::NE, ne);
+        tree.se = load(Quadrant::SE, se);
+        tree.sw = load(Quadrant::SW, sw);
+        tree.nw = load(Quadrant::NW, nw);
+        tree.recount();
+        tree
+    }
 
-        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
-        assert_eq!(points.len(), 1);
-        assert!(points[0].data == 42);
+    /// Heuristic telling whether `rebuild` is likely to pay off: most
+    /// sub-trees are empty, or points sit much deeper than in a balanced tree
+    /// of the same size.
+    pub fn needs_rebuild(&self) -> bool {
+        let (mut nodes, mut empty, mut points, mut depth_sum) = (0, 0, 0, 0);
+        let mut stack = vec![(self, 0)];
+        while let Some((node, level)) = stack.pop() {
+            nodes += 1;
+            if node.points.is_empty() && node.is_leaf() {
+                empty += 1;
+            }
+            points += node.points.len();
+            depth_sum += node.points.len() * level;
+            stack.extend(node.subtrees().map(|subtree| (subtree, level + 1)));
+        }
+        if points == 0 {
+            return nodes > 1;
+        }
 
-        let points = quadtree.query(Rectangle::new(9.0, 9.0, 11.0, 11.0));
-        assert_eq!(points.len(), 1);
-        assert!(points[0].data == 42);
+        // levels a balanced tree needs to hold all points in full leaves
+        let mut balanced = 0;
+        while self.capacity_at(balanced) << (2 * balanced) < points {
+            balanced += 1;
+        }
+        2 * empty > nodes || depth_sum > points * (2 * balanced + 2)
+    }
 
-        Ok(())
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        self.query_filter(boundary, |_| true)
     }
 
-    #[test]
-    fn it_inserts_many_points() -> Result<(), Box<dyn std::error::Error>> {
-        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+    /// Like `query`, but also tells how many nodes and points it looked at,
+    /// e.g. to notice a tree that degraded into long chains of nodes.
+    pub fn query_with_stats(
+        &self,
+        boundary: impl Into<Rectangle>,
+    ) -> (Vec<&Point2D<T>>, QueryStats) {
+        traversal::query_with_stats(self, &boundary.into())
+    }
 
-        for i in 0..10 {
-            let point = Point2D {
-                x: 10.0 + i as f64,
-                y: 10.0 + i as f64,
-                data: 42,
-            };
-            quadtree.insert(point)?;
+    /// Like `query`, but adds the points to `out` instead of allocating a new
+    /// `Vec`, e.g. a cleared buffer reused between queries or a `SmallVec`
+    /// for the common case of a few hits.
+    pub fn query_into<'a>(
+        &'a self,
+        boundary: impl Into<Rectangle>,
+        out: &mut impl Extend<&'a Point2D<T>>,
+    ) {
+        let _ = traversal::for_each_in_region(
+            self,
+            &boundary.into(),
+            QueryEdges::Inclusive,
+            |point| {
+                out.extend(Some(point));
+                ControlFlow::Continue(())
+            },
+        );
+    }
+
+    /// Like `query`, but with `QueryEdges::HalfOpen` points on the right and
+    /// bottom edges of `boundary` are left to the neighbouring regions,
+    /// unless the edge reaches the tree's own right or bottom edge.
+    pub fn query_with_edges(
+        &self,
+        boundary: impl Into<Rectangle>,
+        edges: QueryEdges,
+    ) -> Vec<&Point2D<T>> {
+        let mut result = Vec::new();
+        let _ = traversal::for_each_in_region(self, &boundary.into(), edges, |point| {
+            result.push(point);
+            ControlFlow::Continue(())
+        });
+        result
+    }
+
+    /// Points inside `include` but not inside `exclude`, e.g. the points
+    /// that became visible when a viewport moved from `exclude` to
+    /// `include`. Nodes inside `exclude` are skipped. Points on the border
+    /// of `exclude` count as inside it.
+    pub fn query_difference(
+        &self,
+        include: impl Into<Rectangle>,
+        exclude: impl Into<Rectangle>,
+    ) -> Vec<&Point2D<T>> {
+        traversal::query_difference(self, &include.into(), &exclude.into())
+    }
+
+    /// Points inside `boundary` whose payload satisfies `predicate`, in the
+    /// same traversal.
+    pub fn query_filter(
+        &self,
+        boundary: impl Into<Rectangle>,
+        predicate: impl Fn(&T) -> bool,
+    ) -> Vec<&Point2D<T>> {
+        let mut result = Vec::new();
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            if predicate(&point.data) {
+                result.push(point);
+            }
+            ControlFlow::Continue(())
+        });
+        result
+    }
+
+    /// Points inside `boundary`, but only the first one found for each
+    /// `key` of their payloads, e.g. one point per entity that was inserted
+    /// as several sample points.
+    pub fn query_unique_by<K: Eq + Hash>(
+        &self,
+        boundary: impl Into<Rectangle>,
+        key: impl Fn(&T) -> K,
+    ) -> Vec<&Point2D<T>> {
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            if seen.insert(key(&point.data)) {
+                result.push(point);
+            }
+            ControlFlow::Continue(())
+        });
+        result
+    }
+
+    /// Mean position of the points inside `boundary`, `None` if there are
+    /// none. Sums up during the traversal instead of collecting the points,
+    /// `AggregateQuadTree<Centroid>` answers from its nodes' sums instead.
+    pub fn centroid_in_region(&self, boundary: impl Into<Rectangle>) -> Option<(f64, f64)> {
+        let mut centroid = Centroid::default();
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            centroid.add(point);
+            ControlFlow::Continue(())
+        });
+        centroid.position()
+    }
+
+    /// The smallest circle containing the points inside `boundary`, `None`
+    /// if there are none. Only their positions are collected.
+    pub fn bounding_circle_in_region(&self, boundary: impl Into<Rectangle>) -> Option<Circle> {
+        let mut positions = Vec::new();
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            positions.push((point.x, point.y));
+            ControlFlow::Continue(())
+        });
+        Circle::enclosing(positions)
+    }
+
+    /// Applies `f` to every point inside `boundary` during the traversal, e.g.
+    /// to collect just the ids instead of references to whole points.
+    pub fn query_map<R>(
+        &self,
+        boundary: impl Into<Rectangle>,
+        f: impl Fn(&Point2D<T>) -> R,
+    ) -> Vec<R> {
+        let mut result = Vec::new();
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            result.push(f(point));
+            ControlFlow::Continue(())
+        });
+        result
+    }
+
+    /// Estimates the number of points inside `region` without visiting every
+    /// node. Returns the estimate and a bound, the exact count lies within
+    /// estimate ± bound and the bound never exceeds `max_error`. With a
+    /// `max_error` of zero the count is exact.
+    pub fn approx_count_in_region(
+        &self,
+        region: impl Into<Rectangle>,
+        max_error: f64,
+    ) -> (f64, f64) {
+        traversal::approx_count(self, &region.into(), max_error)
+    }
+
+    /// Like `query` but stops after `limit` matches. The flag tells whether
+    /// more points matched, the results are the first `limit` ones `query`
+    /// would return.
+    pub fn query_limited(
+        &self,
+        boundary: impl Into<Rectangle>,
+        limit: usize,
+    ) -> (Vec<&Point2D<T>>, bool) {
+        let mut result = Vec::with_capacity(limit.min(64));
+        let region = boundary.into();
+        let more = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            if result.len() == limit {
+                return ControlFlow::Break(());
+            }
+            result.push(point);
+            ControlFlow::Continue(())
+        });
+        (result, more.is_break())
+    }
+
+    /// The first point `query` would return, stopping the traversal there.
+    pub fn first_in_region(&self, boundary: impl Into<Rectangle>) -> Option<&Point2D<T>> {
+        let mut first = None;
+        let region = boundary.into();
+        let _ = traversal::for_each_in_region(self, &region, QueryEdges::Inclusive, |point| {
+            first = Some(point);
+            ControlFlow::Break(())
+        });
+        first
+    }
+
+    /// Whether any point lies inside `boundary`, e.g. for visibility checks.
+    /// Stops at the first match instead of collecting all of them.
+    pub fn contains_any(&self, boundary: impl Into<Rectangle>) -> bool {
+        self.first_in_region(boundary).is_some()
+    }
+
+    /// Points inside `boundary` with mutable access to their payloads, in the
+    /// order `query` returns them. Positions stay read-only, so changing
+    /// points can't break the tree.
+    pub fn query_mut(&mut self, boundary: impl Into<Rectangle>) -> Vec<PointMut<'_, T>> {
+        let mut result = Vec::new();
+        self.collect_mut(&boundary.into(), &mut result);
+        result
+    }
+
+    // collects the points inside `region` below this node, marking the
+    // nodes it hands out points of dirty
+    fn collect_mut<'a>(&'a mut self, region: &Rectangle, result: &mut Vec<PointMut<'a, T>>) {
+        if !region.intersects(&self.boundary) {
+            return;
+        }
+        let found = result.len();
+        let QuadTree { points, dirty, ne, se, sw, nw, .. } = self;
+        let inside = points.iter_mut().filter(|point| region.contains(point.x, point.y));
+        result.extend(inside.map(PointMut::new));
+        for child in [ne, se, sw, nw].into_iter().flatten() {
+            child.collect_mut(region, result);
         }
+        if result.len() > found {
+            *dirty = true;
+        }
+    }
 
-        for i in 0..10 {
-            let point = Point2D {
-                x: 90.0 + i as f64,
-                y: 90.0 + i as f64,
-                data: 42,
-            };
-            quadtree.insert(point)?;
+    /// Like `query`, but gives up when `budget` runs out and returns the
+    /// points found so far with a token to continue later via `resume_query`,
+    /// e.g. to keep frame times bounded.
+    pub fn query_with_budget(
+        &self,
+        boundary: impl Into<Rectangle>,
+        budget: &mut TraversalBudget,
+    ) -> (Vec<&Point2D<T>>, Option<QueryResume<'_, Self>>) {
+        traversal::query_with_budget(QueryResume::new(self, boundary.into()), budget)
+    }
+
+    /// Continues a query stopped by `query_with_budget`.
+    pub fn resume_query<'a>(
+        &'a self,
+        resume: QueryResume<'a, Self>,
+        budget: &mut TraversalBudget,
+    ) -> (Vec<&'a Point2D<T>>, Option<QueryResume<'a, Self>>) {
+        traversal::query_with_budget(resume, budget)
+    }
+
+    pub fn query_many(&self, regions: &[Rectangle]) -> Vec<Vec<&Point2D<T>>> {
+        let mut results = vec![Vec::new(); regions.len()];
+        let active: Vec<usize> = (0..regions.len()).collect();
+        self.query_many_into(regions, &active, &mut results);
+        results
+    }
+
+    fn query_many_into<'a>(
+        &'a self,
+        regions: &[Rectangle],
+        active: &[usize],
+        results: &mut [Vec<&'a Point2D<T>>],
+    ) {
+        // only regions overlapping this node need to be checked further down
+        let active: Vec<usize> = active
+            .iter()
+            .copied()
+            .filter(|&i| regions[i].intersects(&self.boundary))
+            .collect();
+        if active.is_empty() {
+            return;
         }
-        assert_eq!(quadtree.count(), 20);
 
-        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
-        assert_eq!(points.len(), 20);
-        assert!(points[0].data == 42);
+        for point in self.points.iter() {
+            for &i in active.iter() {
+                if regions[i].contains(point.x, point.y) {
+                    results[i].push(point);
+                }
+            }
+        }
 
-        let points = quadtree.query(Rectangle::new(9.0, 9.0, 11.0, 11.0));
-        assert_eq!(points.len(), 10);
-        assert!(points[0].data == 42);
+        self.subtrees()
+            .for_each(|subtree| subtree.query_many_into(regions, &active, results));
+    }
 
-        Ok(())
+    /// A random location inside the boundary that no stored point is closer
+    /// to than `min_clearance`, e.g. to place objects in free space. Empty
+    /// parts of the tree are tried first, `None` means no such location was
+    /// found within a fixed number of attempts, not that there is none.
+    pub fn sample_empty_location(
+        &self,
+        rng: &mut impl Rng,
+        min_clearance: f64,
+    ) -> Option<(f64, f64)> {
+        traversal::sample_empty_location(self, rng, min_clearance)
     }
 
-    #[test]
-    fn it_inserts_the_same_point_often() -> Result<(), Box<dyn std::error::Error>> {
-        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+    /// Returns up to `n` distinct points inside `region`, chosen uniformly at
+    /// random. Sub-trees fully inside the region are sampled through their
+    /// counts without collecting their points.
+    pub fn sample_in_region(
+        &self,
+        region: Rectangle,
+        rng: &mut impl Rng,
+        n: usize,
+    ) -> Vec<&Point2D<T>> {
+        let mut blocks = Vec::new();
+        self.sample_blocks(&region, &mut blocks);
 
-        for _i in 0..10 {
-            let point = Point2D {
-                x: 10.0 as f64,
-                y: 10.0 as f64,
-                data: 42,
+        let mut offsets = Vec::with_capacity(blocks.len());
+        let mut total = 0;
+        for block in blocks.iter() {
+            offsets.push(total);
+            total += match block {
+                SampleBlock::Subtree(_, count) => *count,
+                SampleBlock::Point(_) => 1,
             };
-            quadtree.insert(point)?;
         }
 
-        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
-        assert_eq!(points.len(), 10);
+        rand::seq::index::sample(rng, total, n.min(total))
+            .into_iter()
+            .map(|index| {
+                let block = offsets.partition_point(|&offset| offset <= index) - 1;
+                match blocks[block] {
+                    SampleBlock::Subtree(subtree, _) => subtree.nth_point(index - offsets[block]),
+                    SampleBlock::Point(point) => point,
+                }
+            })
+            .collect()
+    }
+
+    fn sample_blocks<'a>(&'a self, region: &Rectangle, blocks: &mut Vec<SampleBlock<'a, T>>) {
+        if !region.intersects(&self.boundary) {
+            return;
+        }
+        if region.contains_rectangle(&self.boundary) {
+            let count = self.count();
+            if count > 0 {
+                blocks.push(SampleBlock::Subtree(self, count));
+            }
+            return;
+        }
+
+        for point in self.points.iter() {
+            if region.contains(point.x, point.y) {
+                blocks.push(SampleBlock::Point(point));
+            }
+        }
+        self.subtrees()
+            .for_each(|subtree| subtree.sample_blocks(region, blocks));
+    }
+
+    fn nth_point(&self, mut n: usize) -> &Point2D<T> {
+        if n < self.points.len() {
+            return &self.points[n];
+        }
+        n -= self.points.len();
+
+        for subtree in self.subtrees() {
+            let count = subtree.count();
+            if n < count {
+                return subtree.nth_point(n);
+            }
+            n -= count;
+        }
+        panic!("point index out of range");
+    }
+
+    /// Tight bounding box of the stored points, `None` for an empty tree.
+    pub fn bounds(&self) -> Option<Rectangle> {
+        self.extent.map(geometry::extent_to_rectangle)
+    }
+
+    /// Counts the points in each cell of a `cols` x `rows` raster over
+    /// `region`, in row-major order. Cells are half-open except for the last
+    /// column and row, so every point in the region is counted exactly once.
+    /// Nodes falling entirely into one cell are counted without visiting
+    /// their points.
+    pub fn density_grid(&self, region: Rectangle, cols: usize, rows: usize) -> Vec<usize> {
+        let mut grid = vec![0; cols * rows];
+        if grid.is_empty() {
+            return grid;
+        }
+
+        let raster = Grid { region, cols, rows };
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if !region.intersects(&node.boundary) {
+                continue;
+            }
+            if let Some(cell) = raster.enclosing_cell(&node.boundary) {
+                grid[cell] += node.count();
+                continue;
+            }
+            for point in node.points.iter() {
+                if let Some(cell) = raster.cell(point.x, point.y) {
+                    grid[cell] += 1;
+                }
+            }
+            stack.extend(node.subtrees());
+        }
+        grid
+    }
+
+    /// Downsamples the tree onto a grid of `cell` x `cell` squares starting at
+    /// the top left corner of the boundary: the center of every square
+    /// holding points with their number, row by row. Nodes inside one square
+    /// are counted without visiting their points. Panics unless `cell` is
+    /// positive and finite.
+    pub fn snap_to_grid(&self, cell: f64) -> Vec<(Point2D<()>, usize)> {
+        traversal::snap_to_grid(self, cell)
+    }
+
+    /// Every node depth-first in ne, se, sw, nw order, parents before their
+    /// children, for traversals the tree doesn't offer itself.
+    pub fn nodes(&self) -> impl Iterator<Item = NodeView<'_, T>> {
+        traversal::nodes(self)
+    }
+
+    /// The nodes without children, in the same order as `nodes()`.
+    pub fn leaves(&self) -> impl Iterator<Item = NodeView<'_, T>> {
+        self.nodes().filter(|node| node.is_leaf())
+    }
+
+    /// The path to the deepest existing node whose boundary holds (x, y),
+    /// `None` outside the boundary.
+    pub fn locate(&self, x: f64, y: f64) -> Option<NodePath> {
+        traversal::locate(self, x, y)
+    }
+
+    /// The node `path` leads to, `None` if the tree has no such node.
+    pub fn node_at(&self, path: &NodePath) -> Option<NodeView<'_, T>> {
+        traversal::node_at(self, path)
+    }
+
+    /// Level-of-detail query: nodes up to `max_depth` return their points
+    /// inside `region` as usual, while each node at `max_depth` stands in for
+    /// its whole sub-tree with a single representative point.
+    pub fn query_at_depth(&self, region: Rectangle, max_depth: usize) -> Vec<&Point2D<T>> {
+        traversal::query_at_depth(self, &region, max_depth)
+    }
+
+    /// Pairs of a point of this tree and a point of `other` at most `epsilon`
+    /// apart, e.g. the places two datasets both contain. Descends both trees
+    /// together, skipping pairs of nodes too far apart to hold a match. A
+    /// point close to several points of `other` is paired with each of them.
+    pub fn intersection<'a, U>(
+        &'a self,
+        other: &'a QuadTree<U>,
+        epsilon: f64,
+    ) -> Vec<(&'a Point2D<T>, &'a Point2D<U>)> {
+        traversal::intersection(self, other, epsilon)
+    }
+
+    pub fn closest_pair(&self) -> Option<(&Point2D<T>, &Point2D<T>)> {
+        let mut best = None;
+        self.closest_pair_into(&mut best);
+        best.map(|(a, b, _)| (a, b))
+    }
+
+    fn closest_pair_into<'a>(&'a self, best: &mut ClosestPair<'a, T>) {
+        // every pair is found in the lowest node containing both points:
+        // either both live in the same sub-tree (recursion), one of them is
+        // stored in this node (subtree search) or they live in different
+        // sub-trees (cross search)
+        let subtrees: Vec<&QuadTree<T>> = self.subtrees().collect();
+        for subtree in subtrees.iter() {
+            subtree.closest_pair_into(best);
+        }
+        for point in self.points.iter() {
+            self.closest_to(point, best);
+        }
+        for (i, subtree) in subtrees.iter().enumerate() {
+            for other in subtrees[i + 1..].iter() {
+                subtree.for_each_point(&mut |point| other.closest_to(point, best));
+            }
+        }
+    }
+
+    fn closest_to<'a>(&'a self, target: &'a Point2D<T>, best: &mut ClosestPair<'a, T>) {
+        if best.is_some_and(|(_, _, d)| self.boundary.distance_squared(target.x, target.y) >= d) {
+            return;
+        }
+
+        for point in self.points.iter() {
+            if ptr::eq(point, target) {
+                continue;
+            }
+            let d = point.distance_squared_to(target);
+            if best.is_none_or(|(_, _, best_d)| d < best_d) {
+                *best = Some((target, point, d));
+            }
+        }
+
+        self.subtrees()
+            .for_each(|subtree| subtree.closest_to(target, best));
+    }
+
+    /// Every stored point paired with its nearest other point, a tree with a
+    /// single point returns nothing. The points of a node are searched as one
+    /// batch sharing a traversal, pruned with the batch's bounding box and
+    /// visiting the nearest sub-trees first.
+    pub fn nearest_neighbor_map(&self) -> Vec<(&Point2D<T>, &Point2D<T>)> {
+        let mut result = Vec::with_capacity(self.count());
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let batch = &node.points;
+            if let Some(extent) = batch.iter().fold(None, geometry::extend_extent) {
+                let mut best = vec![None; batch.len()];
+                self.nearest_for_batch(batch, &geometry::extent_to_rectangle(extent), &mut best);
+                result.extend(
+                    batch.iter().zip(best).filter_map(|(point, best)| Some((point, best?.0))),
+                );
+            }
+            let first = stack.len();
+            stack.extend(node.subtrees());
+            stack[first..].reverse();
+        }
+        result
+    }
+
+    fn nearest_for_batch<'a>(
+        &'a self,
+        batch: &[Point2D<T>],
+        extent: &Rectangle,
+        best: &mut [Option<(&'a Point2D<T>, f64)>],
+    ) {
+        let bound = best.iter().fold(0.0, |bound: f64, best| {
+            bound.max(best.map_or(f64::INFINITY, |(_, d)| d))
+        });
+        if self.boundary.distance_squared_to_rectangle(extent) > bound {
+            return;
+        }
+
+        for (target, best) in batch.iter().zip(best.iter_mut()) {
+            for point in self.points.iter() {
+                if ptr::eq(point, target) {
+                    continue;
+                }
+                let d = point.distance_squared_to(target);
+                if best.is_none_or(|(_, best_d)| d < best_d) {
+                    *best = Some((point, d));
+                }
+            }
+        }
+
+        let mut subtrees: Vec<&QuadTree<T>> = self.subtrees().collect();
+        subtrees.sort_by(|a, b| {
+            let a = a.boundary.distance_squared_to_rectangle(extent);
+            let b = b.boundary.distance_squared_to_rectangle(extent);
+            a.total_cmp(&b)
+        });
+        for subtree in subtrees {
+            subtree.nearest_for_batch(batch, extent, best);
+        }
+    }
+
+    /// Points at most `radius` away from (x, y) with their distance, nearest
+    /// first.
+    pub fn query_radius(&self, x: f64, y: f64, radius: f64) -> Vec<(&Point2D<T>, f64)> {
+        traversal::within_radius(self, x, y, radius, &Euclidean)
+    }
+
+    /// Like `query_radius` with distances measured by `metric`, e.g.
+    /// `Manhattan`, `Haversine` or a custom `Metric`.
+    pub fn query_radius_by<M: Metric + ?Sized>(
+        &self,
+        x: f64,
+        y: f64,
+        radius: f64,
+        metric: &M,
+    ) -> Vec<(&Point2D<T>, f64)> {
+        traversal::within_radius(self, x, y, radius, metric)
+    }
+
+    /// The `k` points nearest to (x, y) with their distance, nearest first.
+    /// Ties keep the traversal order.
+    pub fn k_nearest(&self, x: f64, y: f64, k: usize) -> Vec<(&Point2D<T>, f64)> {
+        traversal::k_nearest(self, x, y, k, &Euclidean)
+    }
+
+    /// Like `k_nearest` with distances measured by `metric`.
+    pub fn k_nearest_by<M: Metric + ?Sized>(
+        &self,
+        x: f64,
+        y: f64,
+        k: usize,
+        metric: &M,
+    ) -> Vec<(&Point2D<T>, f64)> {
+        traversal::k_nearest(self, x, y, k, metric)
+    }
+
+    /// All points by increasing distance to (x, y), found lazily, so the
+    /// caller can stop at whatever point satisfies it instead of guessing
+    /// `k` up front.
+    pub fn nearest_iter(&self, x: f64, y: f64) -> impl Iterator<Item = (&Point2D<T>, f64)> {
+        traversal::nearest(self, x, y, &Euclidean)
+    }
+
+    /// Like `nearest_iter` with distances measured by `metric`.
+    pub fn nearest_iter_by<'a, M: Metric + ?Sized>(
+        &'a self,
+        x: f64,
+        y: f64,
+        metric: &'a M,
+    ) -> impl Iterator<Item = (&'a Point2D<T>, f64)> {
+        traversal::nearest(self, x, y, metric)
+    }
+
+    /// Calls `f` once for every pair of stored points at most `radius` apart,
+    /// without exporting the points first. Pruning skips sub-trees farther
+    /// than `radius` from a point, so this stays cheap for small radii.
+    pub fn for_each_pair_within(&self, radius: f64, mut f: impl FnMut(&Point2D<T>, &Point2D<T>)) {
+        if radius < 0.0 {
+            return;
+        }
+        self.pairs_within_into(radius * radius, &mut f);
+    }
+
+    fn pairs_within_into<'a>(
+        &'a self,
+        max_d: f64,
+        f: &mut impl FnMut(&'a Point2D<T>, &'a Point2D<T>),
+    ) {
+        // the same decomposition as closest_pair, every pair is reported by
+        // the lowest node containing both points
+        for (i, point) in self.points.iter().enumerate() {
+            for other in self.points[i + 1..].iter() {
+                if point.distance_squared_to(other) <= max_d {
+                    f(point, other);
+                }
+            }
+        }
+        let subtrees: Vec<&QuadTree<T>> = self.subtrees().collect();
+        for subtree in subtrees.iter() {
+            subtree.pairs_within_into(max_d, f);
+            for point in self.points.iter() {
+                subtree.within_of(point, max_d, f);
+            }
+        }
+        for (i, subtree) in subtrees.iter().enumerate() {
+            for other in subtrees[i + 1..].iter() {
+                subtree.for_each_point(&mut |point| other.within_of(point, max_d, f));
+            }
+        }
+    }
+
+    fn within_of<'a>(
+        &'a self,
+        target: &'a Point2D<T>,
+        max_d: f64,
+        f: &mut impl FnMut(&'a Point2D<T>, &'a Point2D<T>),
+    ) {
+        if self.boundary.distance_squared(target.x, target.y) > max_d {
+            return;
+        }
+        if self.boundary.max_distance_squared(target.x, target.y) <= max_d {
+            // the whole node is in range, skip the per-point checks
+            self.for_each_point(&mut |point| f(target, point));
+            return;
+        }
+        for point in self.points.iter() {
+            if point.distance_squared_to(target) <= max_d {
+                f(target, point);
+            }
+        }
+        self.subtrees()
+            .for_each(|subtree| subtree.within_of(target, max_d, f));
+    }
+
+    fn for_each_point<'a>(&'a self, f: &mut impl FnMut(&'a Point2D<T>)) {
+        traversal::for_each_point(self, f);
+    }
+
+    /// Indented outline of the tree with each sub-tree's boundary and number
+    /// of points, without descending below `max_depth`. `Display` prints
+    /// every level.
+    pub fn pretty_print(&self, max_depth: usize) -> String {
+        let mut out = String::new();
+        // writing to a String can't fail
+        let _ = self.write_outline(&mut out, "root", 0, max_depth);
+        out
+    }
+
+    /// The tree as a Graphviz graph with each node's boundary and number of
+    /// points, e.g. for `dot -Tsvg`. Node ids follow the quadrants leading to
+    /// the node, so they stay the same while the tree changes elsewhere.
+    pub fn to_dot(&self) -> String {
+        traversal::to_dot(self)
+    }
+
+    fn write_outline(
+        &self,
+        out: &mut impl fmt::Write,
+        label: &str,
+        depth: usize,
+        max_depth: usize,
+    ) -> fmt::Result {
+        writeln!(
+            out,
+            "{:indent$}{} ({}, {}, {}x{}): {} points, {} in total",
+            "",
+            label,
+            self.boundary.x,
+            self.boundary.y,
+            self.boundary.width,
+            self.boundary.height,
+            self.points.len(),
+            self.count(),
+            indent = 2 * depth,
+        )?;
+        if self.is_leaf() {
+            return Ok(());
+        }
+        if depth >= max_depth {
+            return writeln!(out, "{:indent$}...", "", indent = 2 * (depth + 1));
+        }
+        let slots = [("ne", &self.ne), ("se", &self.se), ("sw", &self.sw), ("nw", &self.nw)];
+        for (label, slot) in slots {
+            if let Some(subtree) = slot {
+                subtree.write_outline(out, label, depth + 1, max_depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    // with the `debug-validate` feature, debug builds check the invariants
+    // after every operation that changes the tree and panic with the first
+    // violation found, which names the path to the offending node
+    #[cfg(all(feature = "debug-validate", debug_assertions))]
+    fn validate(&self, operation: &str) {
+        if let Err(violation) = self.check_invariants() {
+            panic!("{} left the tree invalid: {}", operation, violation);
+        }
+    }
+
+    #[cfg(not(all(feature = "debug-validate", debug_assertions)))]
+    fn validate(&self, _operation: &str) {}
+
+    /// Verifies the structural invariants of the tree: every point lies inside
+    /// the boundary of the node storing it, nodes hold at most their capacity
+    /// in points unless they are all identical or at `MAX_DEPTH`, sub-trees cover
+    /// their quadrant and `count` matches the stored points.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut stored = 0;
+        self.check_node(&mut Vec::new(), &mut stored)?;
+        if stored != self.count() {
+            return Err(format!("count() is {} but {} points are stored", self.count(), stored));
+        }
+        Ok(())
+    }
+
+    // checks the sub-trees first, so a violation is reported with the path to
+    // the deepest node it shows at
+    fn check_node(&self, path: &mut Vec<Quadrant>, stored: &mut usize) -> Result<(), String> {
+        let before = *stored;
+        *stored += self.points.len();
+        let subtrees = [&self.ne, &self.se, &self.sw, &self.nw];
+        for (subtree, quadrant) in subtrees.into_iter().zip(Quadrant::ALL) {
+            if let Some(subtree) = subtree {
+                path.push(quadrant);
+                subtree.check_node(path, stored)?;
+                path.pop();
+            }
+        }
+        let held = *stored - before;
+        self.check_own(path.len(), held)
+            .map_err(|message| format!("{} at {}", message, NodePath::new(path.clone())))
+    }
+
+    // the invariants of the node itself, which holds `held` points together
+    // with its sub-trees
+    fn check_own(&self, depth: usize, held: usize) -> Result<(), String> {
+        if self.points.len() > self.capacity_at(depth)
+            && depth < QuadTree::<T>::MAX_DEPTH
+            && self
+                .points
+                .iter()
+                .any(|p| p.x != self.points[0].x || p.y != self.points[0].y)
+        {
+            return Err(format!("{:?} holds {} points", self.boundary, self.points.len()));
+        }
+        for point in self.points.iter() {
+            if !self.boundary.contains(point.x, point.y) {
+                return Err(format!(
+                    "{:?} doesn't contain ({}, {})",
+                    self.boundary, point.x, point.y
+                ));
+            }
+        }
+
+        let (x, y) = self.split_point;
+        if !self.is_leaf() && !self.boundary.contains(x, y) {
+            return Err(format!("{:?} splits outside at {:?}", self.boundary, self.split_point));
+        }
+        let subtrees = [&self.ne, &self.se, &self.sw, &self.nw];
+        for (subtree, quadrant) in subtrees.into_iter().zip(Quadrant::ALL) {
+            let Some(subtree) = subtree else {
+                continue;
+            };
+            if subtree.boundary != self.boundary.quadrant_at(quadrant, self.split_point) {
+                return Err(format!("{:?} is not a quadrant of {:?}", subtree.boundary, self.boundary));
+            }
+            if subtree.dirty && !self.dirty {
+                return Err(format!("{:?} is dirty below a clean node", subtree.boundary));
+            }
+        }
+        if held != self.count {
+            let boundary = self.boundary;
+            return Err(format!("{:?} counts {} points but holds {}", boundary, self.count, held));
+        }
+        if self.extent != self.node_extent() {
+            return Err(format!("{:?} has a stale extent {:?}", self.boundary, self.extent));
+        }
+        Ok(())
+    }
+
+    // for sub-trees moved in from another tree, which the last checkpoint
+    // doesn't know
+    fn mark_all_dirty(&mut self) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            node.dirty = true;
+            stack.extend(
+                [&mut node.ne, &mut node.se, &mut node.sw, &mut node.nw]
+                    .into_iter()
+                    .flatten()
+                    .map(|subtree| subtree.as_mut()),
+            );
+        }
+    }
+
+    // recomputes the cached count and extent from the points and sub-trees
+    fn recount(&mut self) {
+        self.count = self.points.len() + self.subtrees().map(QuadTree::count).sum::<usize>();
+        self.extent = self.node_extent();
+    }
+
+    // the extent of the node's points and the cached ones of its sub-trees
+    fn node_extent(&self) -> Option<(f64, f64, f64, f64)> {
+        let points = self.points.iter().fold(None, geometry::extend_extent);
+        self.subtrees()
+            .fold(points, |extent, subtree| geometry::merge_extents(extent, subtree.extent))
+    }
+
+    fn subtrees(&self) -> impl Iterator<Item = &QuadTree<T>> {
+        self.ne
+            .iter()
+            .chain(self.se.iter())
+            .chain(self.sw.iter())
+            .chain(self.nw.iter())
+            .map(|subtree| subtree.as_ref())
+    }
+}
+
+impl<'a, E> QuadTree<&'a E> {
+    /// Indexes items owned elsewhere, e.g. components in an ECS, at the
+    /// position `locate` returns for each. The tree holds references, so it
+    /// can't outlive the items but never clones them.
+    pub fn from_refs(
+        boundary: Rectangle,
+        items: impl IntoIterator<Item = &'a E>,
+        locate: impl Fn(&E) -> (f64, f64),
+    ) -> Result<Self, RejectReason> {
+        let points = items
+            .into_iter()
+            .map(|item| {
+                let (x, y) = locate(item);
+                RejectReason::check(&boundary, x, y)?;
+                Ok(Point2D { x, y, data: item })
+            })
+            .collect::<Result<Vec<_>, RejectReason>>()?;
+        Ok(QuadTree::new(boundary).bulk_load(boundary, points, 0))
+    }
+}
+
+/// Builds the tree in one pass, like `rebuild`. Fails if a point can't be
+/// stored in the boundary.
+impl<T> TryFrom<(Rectangle, Vec<Point2D<T>>)> for QuadTree<T> {
+    type Error = RejectReason;
+
+    fn try_from((boundary, points): (Rectangle, Vec<Point2D<T>>)) -> Result<Self, RejectReason> {
+        for point in points.iter() {
+            RejectReason::check(&boundary, point.x, point.y)?;
+        }
+        Ok(QuadTree::new(boundary).bulk_load(boundary, points, 0))
+    }
+}
+
+/// Builds the tree over a boundary around all points, aligned to powers of
+/// two so quadrant edges don't suffer from rounding. Panics if a coordinate
+/// is NaN or infinite.
+impl<T> FromIterator<Point2D<T>> for QuadTree<T> {
+    fn from_iter<I: IntoIterator<Item = Point2D<T>>>(iter: I) -> Self {
+        let points: Vec<Point2D<T>> = iter.into_iter().collect();
+        let boundary = points
+            .iter()
+            .fold(None, geometry::extend_extent)
+            .map_or(Rectangle::new(0.0, 0.0, 1.0, 1.0), geometry::aligned_rectangle);
+        match QuadTree::try_from((boundary, points)) {
+            Ok(tree) => tree,
+            Err(reason) => panic!("can't build a tree from the points: {}", reason),
+        }
+    }
+}
+
+/// Inserts the points one by one. Panics if a point is rejected, use
+/// `insert` to handle those.
+impl<T> Extend<Point2D<T>> for QuadTree<T> {
+    fn extend<I: IntoIterator<Item = Point2D<T>>>(&mut self, iter: I) {
+        for point in iter {
+            if let Err(error) = self.insert(point) {
+                panic!("can't extend the tree: {}", error);
+            }
+        }
+    }
+}
+
+impl<T: PartialEq> QuadTree<T> {
+    /// Points added, removed and moved going from `self` to `other`. Trees
+    /// with the same boundary are walked in lockstep, so unchanged points are
+    /// paired sub-tree by sub-tree instead of comparing whole point sets.
+    pub fn diff<'a>(&'a self, other: &'a QuadTree<T>) -> TreeDiff<'a, T> {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        if self.boundary != other.boundary {
+            self.for_each_point(&mut |point| removed.push(point));
+            other.for_each_point(&mut |point| added.push(point));
+            return diff::resolve(removed, added);
+        }
+
+        let mut stack = vec![(self, other)];
+        while let Some((old, new)) = stack.pop() {
+            diff::compare_nodes(&old.points, &new.points, &mut removed, &mut added);
+            let pairs = [
+                (&old.ne, &new.ne),
+                (&old.se, &new.se),
+                (&old.sw, &new.sw),
+                (&old.nw, &new.nw),
+            ];
+            for pair in pairs {
+                match pair {
+                    (Some(old_subtree), Some(new_subtree)) => {
+                        stack.push((old_subtree, new_subtree))
+                    }
+                    (Some(old_subtree), None) => {
+                        old_subtree.for_each_point(&mut |point| removed.push(point))
+                    }
+                    (None, Some(new_subtree)) => {
+                        new_subtree.for_each_point(&mut |point| added.push(point))
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        diff::resolve(removed, added)
+    }
+}
+
+impl<T: Packable> QuadTree<T> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes::write_rectangle(&mut out, &self.boundary);
+        self.write_node(&mut out);
+        out
+    }
+
+    /// Reads a tree written by `to_bytes`. Nodes keep where they split, the
+    /// capacity policy and split strategy aren't stored though and the tree
+    /// uses the defaults from then on.
+    pub fn from_bytes(input: &[u8]) -> Result<Self, &'static str> {
+        QuadTree::read_tree(&mut ByteReader::new(input))
+    }
+
+    /// Like `from_bytes`, but reads the tree incrementally from `reader`, so
+    /// the serialized form and the tree are never in memory together. The
+    /// reader is buffered internally. Streams nesting nodes deeper than a
+    /// tree grows fail with `InvalidData` before they are read to the end.
+    pub fn from_reader(reader: impl io::Read) -> io::Result<Self> {
+        let mut reader = StreamReader::new(io::BufReader::new(reader));
+        QuadTree::read_tree(&mut reader).map_err(|message| reader.error(message))
+    }
+
+    fn read_tree(reader: &mut impl Source) -> Result<Self, &'static str> {
+        let boundary = reader.read_rectangle()?;
+        let quadtree = QuadTree::read_node(reader, boundary, 0)?;
+        if !reader.at_end() {
+            return Err("Trailing bytes after tree");
+        }
+        Ok(quadtree)
+    }
+
+    // where the node splits if that isn't its center, to be stored with it
+    fn stored_split(&self) -> Option<(f64, f64)> {
+        Some(self.split_point)
+            .filter(|split| !self.is_leaf() && *split != self.boundary.center())
+    }
+
+    fn write_node(&self, out: &mut Vec<u8>) {
+        let mut children = 0;
+        for (bit, subtree) in [
+            (bytes::NE, &self.ne),
+            (bytes::SE, &self.se),
+            (bytes::SW, &self.sw),
+            (bytes::NW, &self.nw),
+        ] {
+            if subtree.is_some() {
+                children |= bit;
+            }
+        }
+
+        bytes::write_node_header(out, children, self.points.len(), self.stored_split());
+        self.points
+            .iter()
+            .for_each(|point| bytes::write_point(out, point));
+        self.subtrees().for_each(|subtree| subtree.write_node(out));
+    }
+
+    /// Appends the nodes changed since the last call to `out` and marks the
+    /// tree clean again. Applying the result with `apply_dirty` brings a tree
+    /// that has seen every earlier patch up to date, the first call on a new
+    /// tree emits all of it. Meant for replicating a large tree that rarely
+    /// changes without sending full snapshots.
+    pub fn serialize_dirty(&mut self, out: &mut Vec<u8>) {
+        bytes::write_rectangle(out, &self.boundary);
+        self.write_dirty(out);
+        self.validate("serialize_dirty");
+    }
+
+    /// Applies a patch written by `serialize_dirty`. The changed nodes are
+    /// marked dirty here as well, so patches can be passed on. Fails if the
+    /// patch doesn't fit the tree; the tree may then be partially updated,
+    /// but stays valid and its counts match the points it holds.
+    pub fn apply_dirty(&mut self, patch: &[u8]) -> Result<(), &'static str> {
+        let mut reader = ByteReader::new(patch);
+        if reader.read_rectangle()? != self.boundary {
+            return Err("Patch is for a different boundary");
+        }
+        // even a failed patch may have changed sub-trees
+        self.generation = next_generation();
+        self.read_dirty(&mut reader, true, 0)?;
+        if !reader.at_end() {
+            return Err("Trailing bytes after patch");
+        }
+        self.validate("apply_dirty");
+        Ok(())
+    }
+
+    fn write_dirty(&mut self, out: &mut Vec<u8>) {
+        if !self.dirty {
+            out.push(bytes::UNCHANGED);
+            return;
+        }
+        out.push(bytes::CHANGED);
+        let mut children = 0;
+        for (bit, subtree) in [
+            (bytes::NE, &self.ne),
+            (bytes::SE, &self.se),
+            (bytes::SW, &self.sw),
+            (bytes::NW, &self.nw),
+        ] {
+            if subtree.is_some() {
+                children |= bit;
+            }
+        }
+        bytes::write_node_header(out, children, self.points.len(), self.stored_split());
+        self.points
+            .iter()
+            .for_each(|point| bytes::write_point(out, point));
+        for subtree in [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw]
+            .into_iter()
+            .flatten()
+        {
+            subtree.write_dirty(out);
+        }
+        self.dirty = false;
+    }
+
+    // `known` tells whether the writer had this node at its last checkpoint,
+    // only those can be left unchanged. Nodes deeper than MAX_DEPTH are
+    // rejected as in `read_node`. Counts and extents are recomputed on the
+    // way back even when reading fails, so a failed patch leaves a partially
+    // updated but consistent tree.
+    fn read_dirty(
+        &mut self,
+        reader: &mut impl Source,
+        known: bool,
+        depth: usize,
+    ) -> Result<(), &'static str> {
+        if depth > QuadTree::<T>::MAX_DEPTH {
+            return Err("Tree is too deep");
+        }
+        match reader.take(1)?[0] {
+            bytes::UNCHANGED if known => return Ok(()),
+            bytes::UNCHANGED => return Err("Patch doesn't match the tree"),
+            bytes::CHANGED => {}
+            _ => return Err("Invalid change marker"),
+        }
+        let (children, count, split) = reader.read_node_header(&self.boundary)?;
+        self.points = reader.read_points(&self.boundary, count)?;
+        self.dirty = true;
+        if split != self.split_point {
+            // sub-trees of a node split elsewhere than before are all new
+            for slot in [&mut self.ne, &mut self.se, &mut self.sw, &mut self.nw] {
+                *slot = None;
+            }
+            self.split_point = split;
+        }
+        let (boundary, policy, strategy) = (self.boundary, self.capacity, self.strategy);
+        let mut read = Ok(());
+        for (slot, bit, quadrant) in [
+            (&mut self.ne, bytes::NE, Quadrant::NE),
+            (&mut self.se, bytes::SE, Quadrant::SE),
+            (&mut self.sw, bytes::SW, Quadrant::SW),
+            (&mut self.nw, bytes::NW, Quadrant::NW),
+        ] {
+            if children & bit == 0 {
+                *slot = None;
+                continue;
+            }
+            // sub-trees created here are new to the writer as well
+            let known = known && slot.is_some();
+            let subtree = slot.get_or_insert_with(|| {
+                let boundary = boundary.quadrant_at(quadrant, split);
+                Box::new(QuadTree::with_policies(boundary, policy, strategy))
+            });
+            read = subtree.read_dirty(reader, known, depth + 1);
+            if read.is_err() {
+                break;
+            }
+        }
+        self.recount();
+        read
+    }
+
+    // nodes can't lie deeper than MAX_DEPTH, deeper input is rejected before
+    // the recursion can overflow the stack
+    fn read_node(
+        reader: &mut impl Source,
+        boundary: Rectangle,
+        depth: usize,
+    ) -> Result<Self, &'static str> {
+        if depth > QuadTree::<T>::MAX_DEPTH {
+            return Err("Tree is too deep");
+        }
+        let (children, count, split) = reader.read_node_header(&boundary)?;
+        let mut quadtree = QuadTree::new(boundary);
+        quadtree.points = reader.read_points(&boundary, count)?;
+        quadtree.split_point = split;
+
+        let mut read_subtree = |bit: u8, boundary: Rectangle| {
+            if children & bit == 0 {
+                Ok(None)
+            } else {
+                let subtree = QuadTree::read_node(reader, boundary, depth + 1)?;
+                Ok(Some(Box::new(subtree)))
+            }
+        };
+        quadtree.ne = read_subtree(bytes::NE, boundary.quadrant_at(Quadrant::NE, split))?;
+        quadtree.se = read_subtree(bytes::SE, boundary.quadrant_at(Quadrant::SE, split))?;
+        quadtree.sw = read_subtree(bytes::SW, boundary.quadrant_at(Quadrant::SW, split))?;
+        quadtree.nw = read_subtree(bytes::NW, boundary.quadrant_at(Quadrant::NW, split))?;
+        quadtree.recount();
+        Ok(quadtree)
+    }
+}
+
+impl<T> fmt::Display for QuadTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_outline(f, "root", 0, usize::MAX)
+    }
+}
+
+impl<T> TreeNode<T> for QuadTree<T> {
+    fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    fn points(&self) -> &[Point2D<T>] {
+        &self.points
+    }
+
+    fn count(&self) -> usize {
+        QuadTree::count(self)
+    }
+
+    fn push_children<'a>(&'a self, stack: &mut Vec<&'a Self>) {
+        stack.extend(self.subtrees());
+    }
+
+    fn split_point(&self) -> (f64, f64) {
+        self.split_point
+    }
+
+    fn child(&self, quadrant: Quadrant) -> Option<&Self> {
+        let slot = match quadrant {
+            Quadrant::NE => &self.ne,
+            Quadrant::SE => &self.se,
+            Quadrant::SW => &self.sw,
+            Quadrant::NW => &self.nw,
+        };
+        slot.as_deref()
+    }
+
+    fn envelope(&self) -> Option<Rectangle> {
+        self.extent.map(geometry::covering_rectangle)
+    }
+}
+
+impl<T> Sealed for QuadTree<T> {}
+
+impl<T> EntryTree<T> for QuadTree<T> {
+    fn find_exact_slot(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        self.find_exact_slot(x, y)
+    }
+
+    fn point_at_mut(&mut self, x: f64, y: f64, (depth, index): (usize, usize)) -> &mut Point2D<T> {
+        let mut node = self;
+        for _ in 0..depth {
+            node.dirty = true;
+            node = node.quadrant_mut(x, y).0.as_deref_mut().expect("slot depth within the tree");
+        }
+        node.dirty = true;
+        &mut node.points[index]
+    }
+
+    fn insert_point(&mut self, point: Point2D<T>) -> Result<(usize, usize), InsertError<T>> {
+        let (x, y) = (point.x, point.y);
+        self.insert(point)?;
+        // points are pushed onto the leaf their coordinates route to, and
+        // splitting it keeps their order
+        let mut node = &*self;
+        let mut depth = 0;
+        while let Some(child) = node.quadrant(x, y) {
+            node = child;
+            depth += 1;
+        }
+        Ok((depth, node.points.len() - 1))
+    }
+}
+
+impl<T> RangeJoin<T> for QuadTree<T> {
+    fn visit_points(&self, f: &mut dyn FnMut(&Point2D<T>)) {
+        self.for_each_point(&mut |point| f(point));
+    }
+}
+
+impl<T> SpatialIndex<T> for QuadTree<T> {
+    fn new(boundary: Rectangle) -> Self {
+        QuadTree::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn query_many(&self, regions: &[Rectangle]) -> Vec<Vec<&Point2D<T>>> {
+        self.query_many(regions)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{Point2D, Rectangle};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_inserts_a_point() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(quadtree.count(), 0);
+
+        let point = Point2D {
+            x: 10.0,
+            y: 10.0,
+            data: 42,
+        };
+        quadtree.insert(point)?;
+        assert_eq!(quadtree.count(), 1);
+
+        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(points.len(), 1);
         assert!(points[0].data == 42);
 
+        let points = quadtree.query(Rectangle::new(9.0, 9.0, 11.0, 11.0));
+        assert_eq!(points.len(), 1);
+        assert!(points[0].data == 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_inserts_many_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+
+        for i in 0..10 {
+            let point = Point2D {
+                x: 10.0 + i as f64,
+                y: 10.0 + i as f64,
+                data: 42,
+            };
+            quadtree.insert(point)?;
+        }
+
+        for i in 0..10 {
+            let point = Point2D {
+                x: 90.0 + i as f64,
+                y: 90.0 + i as f64,
+                data: 42,
+            };
+            quadtree.insert(point)?;
+        }
+        assert_eq!(quadtree.count(), 20);
+
+        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(points.len(), 20);
+        assert!(points[0].data == 42);
+
+        let points = quadtree.query(Rectangle::new(9.0, 9.0, 11.0, 11.0));
+        assert_eq!(points.len(), 10);
+        assert!(points[0].data == 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_inserts_the_same_point_often() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+
+        for _i in 0..10 {
+            let point = Point2D {
+                x: 10.0,
+                y: 10.0,
+                data: 42,
+            };
+            quadtree.insert(point)?;
+        }
+
+        let points = quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(points.len(), 10);
+        assert!(points[0].data == 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_hands_out_owned_points() -> Result<(), Box<dyn std::error::Error>> {
+        // a payload that can't be cloned
+        #[derive(Debug)]
+        struct Buffer(Vec<u8>);
+
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(840);
+        for i in 0..100 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: Buffer(vec![0; i]) })?;
+        }
+        let expected: Vec<(f64, f64)> =
+            quadtree.query(boundary).iter().map(|point| point.position()).collect();
+
+        let points = quadtree.into_points();
+        let found: Vec<(f64, f64)> = points.iter().map(|point| point.position()).collect();
+        assert_eq!(found, expected);
+        let mut sizes: Vec<usize> = points.into_iter().map(|point| point.data.0.len()).collect();
+        sizes.sort();
+        assert_eq!(sizes, (0..100).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_points_within_a_tolerance() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..10 {
+            quadtree.insert(Point2D { x: 10.0 * i as f64, y: i as f64 + 0.3, data: i })?;
+        }
+        quadtree.insert(Point2D { x: 30.05, y: 3.3, data: 10 })?;
+
+        // 0.1 + 0.2 isn't exactly 0.3
+        let y = 0.1 + 0.2 + 2.0;
+        assert_eq!(quadtree.find(20.0, y, 1e-9).map(|p| p.data), Some(2));
+        assert!(quadtree.find(20.0, y + 0.1, 1e-9).is_none());
+        assert!(quadtree.find(20.0, y, -1.0).is_none());
+
+        assert_eq!(quadtree.find(30.01, 3.3, 0.1).map(|p| p.data), Some(3));
+        assert_eq!(quadtree.find(30.04, 3.3, 0.1).map(|p| p.data), Some(10));
+        let all = quadtree.find_all(30.0, 3.3, 0.1);
+        let mut all: Vec<usize> = all.iter().map(|p| p.data).collect();
+        all.sort();
+        assert_eq!(all, vec![3, 10]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_clamps_points_outside_the_boundary() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 10.0, 10.0));
+        assert_eq!(quadtree.insert_clamped(Point2D { x: 2.0, y: 3.0, data: 1 })?, (0.0, 0.0));
+        assert_eq!(quadtree.insert_clamped(Point2D { x: 12.5, y: -1.0, data: 2 })?, (2.5, -1.0));
+        let result = quadtree.insert_clamped(Point2D { x: f64::NAN, y: 1.0, data: 3 });
+        assert_eq!(result.map_err(|error| error.reason), Err(RejectReason::InvalidCoordinates));
+        assert_eq!(quadtree.count(), 2);
+        let clamped = quadtree.query(Rectangle::new(10.0, 0.0, 0.0, 0.0));
+        assert_eq!(clamped.len(), 1);
+        assert_eq!(clamped[0].data, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_clamps_points_onto_the_far_edges() -> Result<(), Box<dyn std::error::Error>> {
+        // halving this boundary doesn't reach its far edges exactly
+        let side = 165.43751721195542;
+        let boundary = Rectangle::new(-852.5608418732809, -852.5608418732809, side, side);
+        let (east, south) = (boundary.x + boundary.width, boundary.y + boundary.height);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(835);
+        for i in 0..8 {
+            let along = rng.gen_range(boundary.x..east);
+            let (x, y) = if i % 2 == 0 { (east + 10.0, along) } else { (along, south + 10.0) };
+            quadtree.insert_clamped(Point2D { x, y, data: i })?;
+        }
+        for i in 8..16 {
+            assert_eq!(quadtree.insert_clamped(Point2D { x: 1e9, y: 1e9, data: i })?.0, 1e9 - east);
+        }
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.query(Rectangle::new(east, boundary.y, 0.0, side)).len(), 12);
+        assert_eq!(quadtree.query(Rectangle::new(boundary.x, south, side, 0.0)).len(), 12);
+        assert_eq!(quadtree.count(), 16);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_sorted_distances() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(834);
+        let mut points = Vec::new();
+        for i in 0..300 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            };
+            points.push(point);
+            quadtree.insert(point)?;
+        }
+        let target = Point2D { x: 40.0, y: 60.0, data: 0 };
+        let mut expected: Vec<(usize, f64)> =
+            points.iter().map(|p| (p.data, p.distance_to(&target))).collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let within = quadtree.query_radius(40.0, 60.0, 15.0);
+        let found: Vec<(usize, f64)> = within.iter().map(|(p, d)| (p.data, *d)).collect();
+        let expected_within: Vec<(usize, f64)> =
+            expected.iter().copied().filter(|(_, d)| *d <= 15.0).collect();
+        assert!(!found.is_empty());
+        assert_eq!(found, expected_within);
+
+        let nearest = quadtree.k_nearest(40.0, 60.0, 10);
+        let found: Vec<(usize, f64)> = nearest.iter().map(|(p, d)| (p.data, *d)).collect();
+        assert_eq!(found, expected[..10]);
+        assert_eq!(quadtree.k_nearest(40.0, 60.0, 1000).len(), 300);
+        assert!(quadtree.k_nearest(40.0, 60.0, 0).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_the_closest_pair() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(quadtree.closest_pair().is_none());
+
+        let mut rng = StdRng::seed_from_u64(793);
+        let mut points = Vec::new();
+        for i in 0..500 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            };
+            points.push(point);
+            quadtree.insert(point)?;
+        }
+
+        let mut expected = f64::INFINITY;
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                expected = expected.min(a.distance_squared_to(b));
+            }
+        }
+
+        let (a, b) = quadtree.closest_pair().unwrap();
+        assert_ne!(a.data, b.data);
+        assert_eq!(a.distance_squared_to(b), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_many_regions_at_once() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+
+        let mut rng = StdRng::seed_from_u64(794);
+        for i in 0..500 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+
+        let regions = vec![
+            Rectangle::new(0.0, 0.0, 100.0, 100.0),
+            Rectangle::new(10.0, 10.0, 20.0, 20.0),
+            Rectangle::new(45.0, 45.0, 10.0, 10.0),
+            Rectangle::new(200.0, 200.0, 10.0, 10.0),
+        ];
+        let results = quadtree.query_many(&regions);
+        assert_eq!(results.len(), regions.len());
+        for (region, result) in regions.iter().zip(results.iter()) {
+            let expected: Vec<usize> = quadtree.query(*region).iter().map(|p| p.data).collect();
+            let actual: Vec<usize> = result.iter().map(|p| p.data).collect();
+            assert_eq!(actual, expected);
+        }
+        assert!(results[3].is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_round_trips_through_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u32>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(796);
+        for i in 0..200 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+
+        let bytes = quadtree.to_bytes();
+        let decoded = QuadTree::<u32>::from_bytes(&bytes)?;
+        assert_eq!(decoded.count(), quadtree.count());
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        let region = Rectangle::new(20.0, 20.0, 30.0, 30.0);
+        let expected: Vec<u32> = quadtree.query(region).iter().map(|p| p.data).collect();
+        let actual: Vec<u32> = decoded.query(region).iter().map(|p| p.data).collect();
+        assert_eq!(actual, expected);
+
+        assert!(QuadTree::<u32>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // nodes nested deeper than a tree can grow are rejected instead of
+        // being read recursively until the stack runs out
+        let nested = |levels: usize| {
+            let mut out = Vec::new();
+            bytes::write_rectangle(&mut out, &Rectangle::new(0.0, 0.0, 100.0, 100.0));
+            for _ in 0..levels {
+                bytes::write_node_header(&mut out, bytes::NE, 0, None);
+            }
+            bytes::write_node_header(&mut out, 0, 0, None);
+            out
+        };
+        assert!(QuadTree::<u32>::from_bytes(&nested(QuadTree::<u32>::MAX_DEPTH)).is_ok());
+        for levels in [QuadTree::<u32>::MAX_DEPTH + 1, 1_000_000] {
+            let deep = QuadTree::<u32>::from_bytes(&nested(levels));
+            assert_eq!(deep.err(), Some("Tree is too deep"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_streams_from_a_reader() -> Result<(), Box<dyn std::error::Error>> {
+        // hands out at most three bytes per read, like a slow stream
+        struct Trickle<'a>(&'a [u8]);
+        impl io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(self.0.len()).min(3);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let mut quadtree = QuadTree::<u32>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(846);
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+        let bytes = quadtree.to_bytes();
+
+        let decoded = QuadTree::<u32>::from_reader(Trickle(&bytes))?;
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        let truncated = QuadTree::<u32>::from_reader(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(truncated.kind(), io::ErrorKind::UnexpectedEof);
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let trailing = QuadTree::<u32>::from_reader(trailing.as_slice()).unwrap_err();
+        assert_eq!(trailing.kind(), io::ErrorKind::InvalidData);
+
+        // a stream of ever deeper nodes fails at the deepest a tree can grow
+        let mut nested = Vec::new();
+        bytes::write_rectangle(&mut nested, quadtree.boundary());
+        for _ in 0..1_000_000 {
+            bytes::write_node_header(&mut nested, bytes::NE, 0, None);
+        }
+        let deep = QuadTree::<u32>::from_reader(nested.as_slice()).unwrap_err();
+        assert_eq!(deep.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(deep.to_string(), "Tree is too deep");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_invariants_under_random_inserts() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(797);
+        for _ in 0..20 {
+            let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+            let mut points = Vec::new();
+            for i in 0..rng.gen_range(0..300) {
+                // snap to a coarse grid now and then to provoke duplicates and edge cases
+                let (x, y) = if rng.gen_bool(0.3) {
+                    (rng.gen_range(0..=4) as f64 * 25.0, rng.gen_range(0..=4) as f64 * 25.0)
+                } else {
+                    (rng.gen_range(0.0..=100.0), rng.gen_range(0.0..=100.0))
+                };
+                let point = Point2D { x, y, data: i };
+                points.push(point);
+                quadtree.insert(point)?;
+            }
+            quadtree.check_invariants()?;
+            assert_eq!(quadtree.count(), points.len());
+
+            for _ in 0..10 {
+                let region = Rectangle::new(
+                    rng.gen_range(-10.0..100.0),
+                    rng.gen_range(-10.0..100.0),
+                    rng.gen_range(0.0..60.0),
+                    rng.gen_range(0.0..60.0),
+                );
+                let mut expected: Vec<usize> = points
+                    .iter()
+                    .filter(|p| region.contains(p.x, p.y))
+                    .map(|p| p.data)
+                    .collect();
+                let mut actual: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+                expected.sort();
+                actual.sort();
+                assert_eq!(actual, expected);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_clears_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..20 {
+            quadtree.insert(Point2D { x: 5.0 * i as f64, y: 5.0 * i as f64, data: i })?;
+        }
+
+        quadtree.clear();
+        assert_eq!(quadtree.count(), 0);
+        assert!(quadtree.query(Rectangle::new(0.0, 0.0, 100.0, 100.0)).is_empty());
+        assert!(quadtree.nw.is_some());
+        quadtree.check_invariants()?;
+
+        quadtree.insert(Point2D { x: 90.0, y: 90.0, data: 1 })?;
+        assert_eq!(quadtree.count(), 1);
+
+        quadtree.clear_and_shrink();
+        assert_eq!(quadtree.count(), 0);
+        assert!(quadtree.is_leaf());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_samples_points_in_a_region() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(799);
+        for i in 0..1000 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+
+        let region = Rectangle::new(10.0, 20.0, 40.0, 30.0);
+        let matching = quadtree.query(region).len();
+
+        let sample = quadtree.sample_in_region(region, &mut rng, 50);
+        assert_eq!(sample.len(), 50);
+        assert!(sample.iter().all(|p| region.contains(p.x, p.y)));
+        let mut ids: Vec<usize> = sample.iter().map(|p| p.data).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 50);
+
+        let sample = quadtree.sample_in_region(region, &mut rng, matching + 10);
+        assert_eq!(sample.len(), matching);
+
+        let sample = quadtree.sample_in_region(Rectangle::new(200.0, 200.0, 1.0, 1.0), &mut rng, 5);
+        assert!(sample.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_exposes_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::<usize>::new(boundary);
+        assert_eq!(*quadtree.boundary(), boundary);
+        assert_eq!(quadtree.capacity(), 4);
+        assert!(quadtree.is_leaf());
+
+        for i in 0..5 {
+            quadtree.insert(Point2D { x: 10.0 * i as f64, y: 10.0, data: i })?;
+        }
+        assert!(!quadtree.is_leaf());
+        assert_eq!(*quadtree.boundary(), boundary);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_by_a_capacity_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::with_capacity_policy(boundary, |depth| match depth {
+            0 => 64,
+            1 => 16,
+            _ => 4,
+        });
+        assert_eq!(quadtree.capacity(), 64);
+        assert_eq!(quadtree.capacity_at(5), 4);
+
+        let mut rng = StdRng::seed_from_u64(839);
+        for i in 0..64 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+        assert!(quadtree.is_leaf());
+        for i in 64..500 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+        assert!(!quadtree.is_leaf());
+        quadtree.check_invariants()?;
+
+        // the policy survives rebuilding
+        quadtree.rebuild();
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.count(), 500);
+        quadtree.clear_and_shrink();
+        assert_eq!(quadtree.capacity(), 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stores_points_on_seams_in_the_east_and_south() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for _ in 0..4 {
+            quadtree.insert(Point2D { x: 10.0, y: 10.0, data: 0 })?;
+        }
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 1 })?;
+        quadtree.insert(Point2D { x: 50.0, y: 10.0, data: 2 })?;
+        quadtree.insert(Point2D { x: 10.0, y: 50.0, data: 3 })?;
+
+        assert_eq!(quadtree.se.as_ref().unwrap().points[0].data, 1);
+        assert_eq!(quadtree.ne.as_ref().unwrap().points[0].data, 2);
+        assert_eq!(quadtree.sw.as_ref().unwrap().points[0].data, 3);
+        assert_eq!(quadtree.nw.as_ref().unwrap().points.len(), 4);
+        assert!(quadtree.points.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_claims_grid_points_once_with_half_open_rectangles() {
+        let tiles = [
+            Rectangle::new(0.0, 0.0, 50.0, 50.0),
+            Rectangle::new(50.0, 0.0, 50.0, 50.0),
+            Rectangle::new(0.0, 50.0, 50.0, 50.0),
+            Rectangle::new(50.0, 50.0, 50.0, 50.0),
+        ];
+        for (x, y) in [(50.0, 50.0), (50.0, 0.0), (0.0, 50.0), (25.0, 25.0)] {
+            let claims = tiles.iter().filter(|tile| tile.contains_half_open(x, y)).count();
+            assert_eq!(claims, 1);
+        }
+    }
+
+    #[test]
+    fn it_upserts_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u32>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..100 {
+            let x = (i % 10) as f64 * 10.0;
+            let y = (i % 7) as f64 * 10.0;
+            quadtree.upsert(x, y, 1, |count, new| *count += new)?;
+        }
+        // i % 10 and i % 7 pair up uniquely for i < 70
+        assert_eq!(quadtree.count(), 70);
+        let total: u32 = quadtree
+            .query(Rectangle::new(0.0, 0.0, 100.0, 100.0))
+            .iter()
+            .map(|p| p.data)
+            .sum();
+        assert_eq!(total, 100);
+
+        let hits = quadtree.query(Rectangle::new(0.0, 0.0, 0.0, 0.0));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].data, 2);
+
+        assert!(quadtree.upsert(200.0, 0.0, 1, |count, new| *count += new).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_handles_degenerate_deep_trees() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for _ in 0..10_000 {
+            quadtree.insert(Point2D { x: 33.3, y: 66.6, data: 7 })?;
+        }
+        assert_eq!(quadtree.count(), 10_000);
+        assert_eq!(quadtree.query(Rectangle::new(30.0, 60.0, 10.0, 10.0)).len(), 10_000);
+        assert!(quadtree.query(Rectangle::new(0.0, 0.0, 10.0, 10.0)).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_computes_the_bounds_of_stored_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(quadtree.bounds().is_none());
+
+        quadtree.insert(Point2D { x: 20.0, y: 30.0, data: 0 })?;
+        assert_eq!(quadtree.bounds(), Some(Rectangle::new(20.0, 30.0, 0.0, 0.0)));
+
+        for (x, y) in [(25.0, 70.0), (60.0, 35.0), (40.0, 40.0), (30.0, 31.0), (55.0, 65.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+        }
+        assert_eq!(quadtree.bounds(), Some(Rectangle::new(20.0, 30.0, 40.0, 40.0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_redistributes_points_on_split() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for (x, y) in [(10.0, 10.0), (60.0, 10.0), (10.0, 60.0), (60.0, 60.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+        }
+        assert!(quadtree.is_leaf());
+
+        quadtree.insert(Point2D { x: 70.0, y: 70.0, data: 0 })?;
+        assert!(quadtree.points.is_empty());
+        assert_eq!(quadtree.depth(), 1);
+        assert_eq!(quadtree.se.as_ref().unwrap().points.len(), 2);
+        quadtree.check_invariants()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_about_as_deep_as_the_enum_tree_for_clustered_data() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::<usize>::new(boundary);
+        let mut enum_quadtree = crate::QuadTree::<usize>::new(boundary);
+
+        let mut rng = StdRng::seed_from_u64(807);
+        for i in 0..2000 {
+            // a few tight clusters
+            let (cx, cy) = [(20.0, 20.0), (70.0, 30.0), (45.0, 80.0)][i % 3];
+            let point = Point2D {
+                x: cx + rng.gen_range(-1.0..1.0),
+                y: cy + rng.gen_range(-1.0..1.0),
+                data: i,
+            };
+            quadtree.insert(point)?;
+            enum_quadtree.insert(point)?;
+        }
+
+        quadtree.check_invariants()?;
+        // both trees move the points of a leaf into its children when it splits
+        assert_eq!(quadtree.depth(), enum_quadtree.depth());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_bins_points_into_a_density_grid() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(808);
+        let mut points = Vec::new();
+        for i in 0..1000 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..=100.0),
+                y: rng.gen_range(0.0..=100.0),
+                data: i,
+            };
+            points.push(point);
+            quadtree.insert(point)?;
+        }
+
+        for (region, cols, rows) in [
+            (Rectangle::new(0.0, 0.0, 100.0, 100.0), 4, 4),
+            (Rectangle::new(0.0, 0.0, 100.0, 100.0), 7, 3),
+            (Rectangle::new(12.5, 30.0, 50.0, 25.0), 5, 2),
+        ] {
+            let grid = quadtree.density_grid(region, cols, rows);
+            assert_eq!(grid.len(), cols * rows);
+
+            let mut expected = vec![0; cols * rows];
+            let cell_width = region.width / cols as f64;
+            let cell_height = region.height / rows as f64;
+            for point in points.iter().filter(|p| region.contains(p.x, p.y)) {
+                let col = (((point.x - region.x) / cell_width) as usize).min(cols - 1);
+                let row = (((point.y - region.y) / cell_height) as usize).min(rows - 1);
+                expected[row * cols + col] += 1;
+            }
+            assert_eq!(grid, expected);
+        }
+
+        assert_eq!(quadtree.density_grid(Rectangle::new(0.0, 0.0, 100.0, 100.0), 1, 1), vec![1000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_with_a_depth_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(809);
+        for i in 0..1000 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+        let region = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+
+        assert_eq!(quadtree.query_at_depth(region, 0).len(), 1);
+
+        let mut previous = 1;
+        // nodes at the deepest level still hold several points
+        for max_depth in 1..=quadtree.depth() + 1 {
+            let points = quadtree.query_at_depth(region, max_depth);
+            assert!(points.len() >= previous);
+            assert!(points.len() <= 4 * 4usize.pow(max_depth as u32));
+            previous = points.len();
+        }
+        assert_eq!(previous, 1000);
+
+        let small = Rectangle::new(10.0, 10.0, 5.0, 5.0);
+        let expected = quadtree.query(small).len();
+        assert_eq!(quadtree.query_at_depth(small, quadtree.depth() + 1).len(), expected);
+        assert!(quadtree.query_at_depth(small, 1).len() <= expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_points() {
+        let mut quadtree = QuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for (x, y) in [
+            (f64::NAN, 10.0),
+            (10.0, f64::NAN),
+            (f64::INFINITY, 10.0),
+            (10.0, f64::NEG_INFINITY),
+        ] {
+            let result = quadtree.insert(Point2D { x, y, data: 0 });
+            assert_eq!(result.map_err(|error| error.reason), Err(RejectReason::InvalidCoordinates));
+        }
+        let result = quadtree.insert(Point2D { x: 110.0, y: 10.0, data: 0 });
+        assert_eq!(result.map_err(|error| error.reason), Err(RejectReason::OutOfBounds));
+        assert_eq!(quadtree.count(), 0);
+    }
+
+    #[test]
+    fn it_hands_back_rejected_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 10.0, 10.0));
+        let point = Point2D { x: 25.0, y: 5.0, data: String::from("far away") };
+        let error = quadtree.insert(point).err().ok_or("inserted a point outside")?;
+        assert_eq!(error.reason, RejectReason::OutOfBounds);
+        assert_eq!(error.to_string(), "Boundary doesn't contain point at (25, 5)");
+
+        // retry in a tree large enough for it
+        let mut larger = QuadTree::new(Rectangle::new(0.0, 0.0, 50.0, 50.0));
+        larger.insert(error.point)?;
+        assert_eq!(larger.query(Rectangle::new(20.0, 0.0, 10.0, 10.0))[0].data, "far away");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_accumulates_through_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<u32>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..100 {
+            let x = (i % 10) as f64 * 10.0;
+            let y = (i % 7) as f64 * 10.0;
+            *quadtree.entry(x, y)?.or_insert(0) += 1;
+        }
+        assert_eq!(quadtree.count(), 70);
+        assert_eq!(*quadtree.entry(0.0, 0.0)?.or_default(), 2);
+
+        let value = quadtree.entry(0.0, 0.0)?.and_modify(|count| *count *= 10).or_insert(0);
+        assert_eq!(*value, 20);
+        let value = quadtree.entry(99.0, 99.0)?.and_modify(|count| *count *= 10).or_insert(5);
+        assert_eq!(*value, 5);
+        assert_eq!(quadtree.count(), 71);
+
+        match quadtree.entry(99.0, 99.0)? {
+            Entry::Occupied(entry) => assert_eq!(*entry.get(), 5),
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+        assert!(matches!(quadtree.entry(1.0, 1.0)?, Entry::Vacant(_)));
+        assert!(quadtree.entry(101.0, 1.0).is_err());
+
+        // vacant entries hand out the new point even when storing it splits a leaf
+        for i in 0..50 {
+            let (x, y) = (i as f64 * 1.5 + 0.5, 99.5 - i as f64 * 1.25);
+            assert_eq!(*quadtree.entry(x, y)?.or_insert(i + 100), i + 100);
+            assert_eq!(quadtree.find(x, y, 0.0).map(|point| point.data), Some(i + 100));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_all_pairs_within_a_radius() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(814);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut points = Vec::new();
+        for i in 0..300 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            };
+            points.push(point);
+            quadtree.insert(point)?;
+        }
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 300 })?;
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 301 })?;
+        points.push(Point2D { x: 50.0, y: 50.0, data: 300 });
+        points.push(Point2D { x: 50.0, y: 50.0, data: 301 });
+
+        let radius = 4.0;
+        let mut expected = Vec::new();
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                if a.distance_squared_to(b) <= radius * radius {
+                    expected.push((a.data, b.data));
+                }
+            }
+        }
+        let mut found = Vec::new();
+        quadtree.for_each_pair_within(radius, |a, b| {
+            found.push((a.data.min(b.data), a.data.max(b.data)));
+        });
+        found.sort();
+        assert_eq!(found, expected);
+
+        let mut calls = 0;
+        quadtree.for_each_pair_within(-1.0, |_, _| calls += 1);
+        assert_eq!(calls, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rebuilds_a_degraded_tree() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(816);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(!quadtree.needs_rebuild());
+        for i in 0..1000 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+        assert!(!quadtree.needs_rebuild());
+
+        // a cleared tree refilled with a few points keeps all of its nodes
+        quadtree.clear();
+        for i in 0..20 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+        assert!(quadtree.needs_rebuild());
+
+        let region = Rectangle::new(20.0, 20.0, 50.0, 50.0);
+        let mut before: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        let depth = quadtree.depth();
+        quadtree.rebuild();
+        let mut after: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+        assert_eq!(quadtree.count(), 20);
+        assert!(quadtree.depth() <= depth);
+        assert!(!quadtree.needs_rebuild());
+        quadtree.check_invariants()?;
+
+        for i in 0..10 {
+            quadtree.insert(Point2D { x: 5.0, y: 5.0, data: i })?;
+        }
+        quadtree.rebuild();
+        assert_eq!(quadtree.count(), 30);
+        quadtree.check_invariants()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stops_queries_at_a_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(817);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..500 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+
+        let region = Rectangle::new(10.0, 10.0, 30.0, 30.0);
+        let all: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        assert!(all.len() > 10);
+
+        let (first, truncated) = quadtree.query_limited(region, 10);
+        assert!(truncated);
+        assert_eq!(first.iter().map(|p| p.data).collect::<Vec<_>>(), all[..10]);
+
+        let (found, truncated) = quadtree.query_limited(region, all.len());
+        assert!(!truncated);
+        assert_eq!(found.len(), all.len());
+
+        let (found, truncated) = quadtree.query_limited(region, 0);
+        assert!(found.is_empty() && truncated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_diffs_two_trees() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(819);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let points: Vec<Point2D<usize>> = (0..200)
+            .map(|i| Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })
+            .collect();
+
+        let mut old = QuadTree::new(boundary);
+        for point in points.iter() {
+            old.insert(*point)?;
+        }
+        // same points in a different order may end up in different nodes
+        let mut new = QuadTree::new(boundary);
+        for point in points.iter().rev() {
+            if point.data == 7 {
+                continue;
+            }
+            if point.data == 42 {
+                new.insert(Point2D { x: 1.0, y: 2.0, data: 42 })?;
+            } else {
+                new.insert(*point)?;
+            }
+        }
+        new.insert(Point2D { x: 3.0, y: 4.0, data: 1000 })?;
+
+        assert!(old.diff(&old).is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(diff.removed.iter().map(|p| p.data).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(diff.added.iter().map(|p| p.data).collect::<Vec<_>>(), vec![1000]);
+        assert_eq!(diff.moved.len(), 1);
+        let (from, to) = diff.moved[0];
+        assert_eq!((from.data, to.x, to.y), (42, 1.0, 2.0));
+
+        let empty = QuadTree::new(boundary);
+        assert_eq!(old.diff(&empty).removed.len(), 200);
+        assert_eq!(empty.diff(&old).added.len(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_shrinks_to_fit() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(821);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..1000 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+        quadtree.clear();
+        quadtree.shrink_to_fit();
+        assert!(quadtree.is_leaf());
+        assert_eq!(quadtree.depth(), 0);
+
+        for i in 0..1000 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            })?;
+        }
+        let region = Rectangle::new(0.0, 0.0, 10.0, 10.0);
+        let before = quadtree.query(region).len();
+        let depth = quadtree.depth();
+        quadtree.shrink_to_fit();
+        assert_eq!(quadtree.query(region).len(), before);
+        assert_eq!(quadtree.depth(), depth);
+        quadtree.check_invariants()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prints_an_outline() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for (x, y) in [(10.0, 10.0), (20.0, 10.0), (30.0, 10.0), (40.0, 10.0), (60.0, 60.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+        }
+
+        let expected = "\
+root (0, 0, 100x100): 0 points, 5 in total
+  se (50, 50, 50x50): 1 points, 1 in total
+  nw (0, 0, 50x50): 4 points, 4 in total
+";
+        assert_eq!(quadtree.to_string(), expected);
+        assert_eq!(
+            quadtree.pretty_print(0),
+            "root (0, 0, 100x100): 0 points, 5 in total\n  ...\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_caps_the_depth_for_nearly_identical_points() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::<usize>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..10_000 {
+            quadtree.insert(Point2D { x: 10.0, y: 10.0, data: i })?;
+        }
+        // distinct points closer together than the smallest cell
+        for i in 0..100 {
+            let offset = i as f64 * 1e-12;
+            quadtree.insert(Point2D { x: 20.0 + offset, y: 20.0, data: i })?;
+        }
+        assert_eq!(quadtree.count(), 10_100);
+        assert!(quadtree.depth() <= QuadTree::<usize>::MAX_DEPTH);
+        assert_eq!(quadtree.query(Rectangle::new(9.0, 9.0, 2.0, 2.0)).len(), 10_000);
+        quadtree.check_invariants()?;
+
+        quadtree.rebuild();
+        assert!(quadtree.depth() <= QuadTree::<usize>::MAX_DEPTH);
+        quadtree.check_invariants()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_filters_queries_by_payload() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(826);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for _ in 0..500 {
+            quadtree.insert(Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: rng.gen_range(0..=5),
+            })?;
+        }
+
+        let region = Rectangle::new(20.0, 20.0, 40.0, 40.0);
+        let expected: Vec<_> = quadtree.query(region).into_iter().filter(|p| p.data > 4).collect();
+        let found = quadtree.query_filter(region, |rating| *rating > 4);
+        assert!(!found.is_empty());
+        assert_eq!(found.len(), expected.len());
+        assert!(found.iter().zip(expected).all(|(a, b)| std::ptr::eq(*a, b)));
+        assert!(quadtree.query_filter(region, |_| false).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_maps_every_point_to_its_nearest_neighbor() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(827);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 0 })?;
+        assert!(quadtree.nearest_neighbor_map().is_empty());
+
+        let mut points = vec![Point2D { x: 50.0, y: 50.0, data: 0 }];
+        for i in 1..400 {
+            let point = Point2D {
+                x: rng.gen_range(0.0..100.0),
+                y: rng.gen_range(0.0..100.0),
+                data: i,
+            };
+            points.push(point);
+            quadtree.insert(point)?;
+        }
+        // a duplicate is its own nearest neighbor at distance zero
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 400 })?;
+        points.push(Point2D { x: 50.0, y: 50.0, data: 400 });
+
+        let map = quadtree.nearest_neighbor_map();
+        assert_eq!(map.len(), points.len());
+        for (point, nearest) in map {
+            assert_ne!(point.data, nearest.data);
+            let expected = points
+                .iter()
+                .filter(|other| other.data != point.data)
+                .map(|other| other.distance_squared_to(point))
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(point.distance_squared_to(nearest), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_indexes_borrowed_items() -> Result<(), Box<dyn std::error::Error>> {
+        // neither Debug nor Clone
+        struct Entity {
+            id: u32,
+            position: (f64, f64),
+        }
+
+        let entities: Vec<Entity> = (0..50)
+            .map(|id| Entity { id, position: (id as f64 * 2.0, id as f64) })
+            .collect();
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::from_refs(boundary, &entities, |entity| entity.position)?;
+        assert_eq!(quadtree.count(), 50);
+
+        let late = Entity { id: 99, position: (5.0, 5.0) };
+        // the rejected point would borrow `late`, so only the reason is passed on
+        quadtree.insert(Point2D { x: 5.0, y: 5.0, data: &late }).map_err(RejectReason::from)?;
+        let mut ids: Vec<u32> = quadtree
+            .query(Rectangle::new(0.0, 0.0, 10.0, 10.0))
+            .iter()
+            .map(|p| p.data.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5, 99]);
+
+        let outside = Entity { id: 100, position: (200.0, 0.0) };
+        let result = QuadTree::from_refs(boundary, [&outside], |entity| entity.position);
+        assert!(matches!(result, Err(RejectReason::OutOfBounds)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_seam_points_once_for_half_open_tiles() -> Result<(), Box<dyn std::error::Error>> {
+        // points on an 11x11 lattice, so many lie exactly on the tile seams
+        // and the last row and column on the boundary's far edges
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for x in 0..=10 {
+            for y in 0..=10 {
+                quadtree.insert(Point2D { x: x as f64 * 10.0, y: y as f64 * 10.0, data: (x, y) })?;
+            }
+        }
+
+        let tiles: Vec<Rectangle> = (0..16)
+            .map(|i| Rectangle::new((i % 4) as f64 * 25.0, (i / 4) as f64 * 25.0, 25.0, 25.0))
+            .collect();
+        let mut found: Vec<(i32, i32)> = tiles
+            .iter()
+            .flat_map(|tile| quadtree.query_with_edges(tile, QueryEdges::HalfOpen))
+            .map(|point| point.data)
+            .collect();
+        found.sort();
+        found.dedup();
+        assert_eq!(found.len(), 121);
+        let total: usize = tiles
+            .iter()
+            .map(|tile| quadtree.query_with_edges(tile, QueryEdges::HalfOpen).len())
+            .sum();
+        assert_eq!(total, 121);
+        let corner = quadtree.query_with_edges(tiles[15], QueryEdges::HalfOpen);
+        assert!(corner.iter().any(|point| point.x == 100.0 && point.y == 100.0));
+        let inner = quadtree.query_with_edges(tiles[0], QueryEdges::HalfOpen);
+        assert!(inner.iter().all(|point| point.x < 25.0 && point.y < 25.0));
+
+        let inclusive: usize = tiles.iter().map(|tile| quadtree.query(tile).len()).sum();
+        assert!(inclusive > 121);
+        assert_eq!(
+            quadtree.query_with_edges(tiles[5], QueryEdges::Inclusive).len(),
+            quadtree.query(tiles[5]).len()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_trees() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(852);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut left = QuadTree::new(boundary);
+        let mut right = QuadTree::new(boundary);
+        // the left tree only fills the western half, so whole sub-trees of
+        // the right one land on empty leaves
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(0.0..50.0), rng.gen_range(0.0..100.0));
+            left.insert(Point2D { x, y, data: i })?;
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            right.insert(Point2D { x, y, data: 500 + i })?;
+        }
+        let mut small = QuadTree::new(Rectangle::new(10.0, 10.0, 30.0, 30.0));
+        for i in 0..50 {
+            let (x, y) = (rng.gen_range(10.0..40.0), rng.gen_range(10.0..40.0));
+            small.insert(Point2D { x, y, data: 1000 + i })?;
+        }
+
+        left.merge(right)?;
+        left.merge(small)?;
+        left.check_invariants()?;
+        assert_eq!(left.count(), 1050);
+        let mut ids: Vec<usize> = left.query(boundary).iter().map(|p| p.data).collect();
+        ids.sort();
+        assert_eq!(ids, (0..1050).collect::<Vec<_>>());
+
+        let mut outside = QuadTree::new(Rectangle::new(50.0, 50.0, 100.0, 100.0));
+        outside.insert(Point2D { x: 60.0, y: 60.0, data: 2000 })?;
+        outside.insert(Point2D { x: 120.0, y: 60.0, data: 2001 })?;
+        assert_eq!(left.merge(outside), Err(RejectReason::OutOfBounds));
+        assert_eq!(left.count(), 1050);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_maps_points_during_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..20 {
+            quadtree.insert(Point2D { x: 5.0 * i as f64, y: 50.0, data: (i, "payload") })?;
+        }
+
+        let region = Rectangle::new(0.0, 40.0, 32.0, 20.0);
+        let mut ids = quadtree.query_map(region, |point| point.data.0);
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5, 6]);
+        let xs = quadtree.query_map(region, |point| point.x);
+        let expected: Vec<f64> = quadtree.query(region).iter().map(|p| p.x).collect();
+        assert_eq!(xs, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_approximates_counts_within_the_bound() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(856);
+        for i in 0..5000 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+
+        for _ in 0..20 {
+            let region = Rectangle::new(
+                rng.gen_range(-10.0..90.0),
+                rng.gen_range(-10.0..90.0),
+                rng.gen_range(0.0..60.0),
+                rng.gen_range(0.0..60.0),
+            );
+            let exact = quadtree.query(region).len() as f64;
+            for max_error in [0.0, 10.0, 100.0] {
+                let (estimate, bound) = quadtree.approx_count_in_region(region, max_error);
+                assert!(bound <= max_error);
+                assert!((estimate - exact).abs() <= bound + 1e-9, "{} vs {}", estimate, exact);
+            }
+        }
+        assert_eq!(quadtree.approx_count_in_region(boundary, 0.0), (5000.0, 0.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_cached_counts_up_to_date() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(857);
+        let mut insert = |quadtree: &mut QuadTree<u32>, n: u32| {
+            (0..n).try_for_each(|i| {
+                let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+                quadtree.insert(Point2D { x, y, data: i })
+            })
+        };
+
+        insert(&mut quadtree, 1000)?;
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.count(), 1000);
+        quadtree.clear();
+        assert_eq!(quadtree.count(), 0);
+        insert(&mut quadtree, 100)?;
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.count(), 100);
+        quadtree.rebuild();
+        quadtree.check_invariants()?;
+
+        let copy = QuadTree::<u32>::from_bytes(&quadtree.to_bytes())?;
+        copy.check_invariants()?;
+        assert_eq!(copy.count(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_replicates_changed_nodes() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut replica = QuadTree::<i32>::new(boundary);
+        let mut rng = StdRng::seed_from_u64(863);
+        for i in 0..2000 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+        let mut patch = Vec::new();
+        quadtree.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+        assert_eq!(replica.to_bytes(), quadtree.to_bytes());
+        let full = patch.len();
+
+        // nothing changed, only the boundary and the root's marker are sent
+        patch.clear();
+        quadtree.serialize_dirty(&mut patch);
+        assert_eq!(patch.len(), 33);
+        quadtree.check_invariants()?;
+
+        // a few changes in one corner
+        for i in 0..20 {
+            let (x, y) = (rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0));
+            quadtree.insert(Point2D { x, y, data: 2000 + i })?;
+        }
+        quadtree.upsert(90.0, 90.0, 1, |data, new| *data += new)?;
+        patch.clear();
+        quadtree.serialize_dirty(&mut patch);
+        assert!(patch.len() < full / 4);
+        replica.apply_dirty(&patch)?;
+        assert_eq!(replica.to_bytes(), quadtree.to_bytes());
+        replica.check_invariants()?;
+
+        quadtree.clear();
+        quadtree.insert(Point2D { x: 50.0, y: 50.0, data: 0 })?;
+        quadtree.shrink_to_fit();
+        patch.clear();
+        quadtree.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+        assert_eq!(replica.to_bytes(), quadtree.to_bytes());
+        assert_eq!(replica.count(), 1);
+
+        // a patch only fits the state it was written against
+        for i in 0..10 {
+            quadtree.insert(Point2D { x: 10.0 * i as f64, y: 10.0, data: i })?;
+        }
+        patch.clear();
+        quadtree.serialize_dirty(&mut patch);
+        quadtree.insert(Point2D { x: 10.0, y: 10.0, data: 1 })?;
+        let mut next = Vec::new();
+        quadtree.serialize_dirty(&mut next);
+        assert!(QuadTree::<i32>::new(boundary).apply_dirty(&next).is_err());
+        let mut other = QuadTree::<i32>::new(Rectangle::new(0.0, 0.0, 10.0, 10.0));
+        assert!(other.apply_dirty(&patch).is_err());
+
+        // patches cut short or nested too deep fail, but leave the counts and
+        // extents of the nodes read so far right
+        let mut source = QuadTree::<i32>::from_bytes(&quadtree.to_bytes())?;
+        patch.clear();
+        source.serialize_dirty(&mut patch);
+        for len in [patch.len() / 3, patch.len() / 2, patch.len() - 1] {
+            let mut partial = QuadTree::<i32>::new(boundary);
+            assert!(partial.apply_dirty(&patch[..len]).is_err());
+            partial.check_invariants()?;
+        }
+        let mut nested = Vec::new();
+        bytes::write_rectangle(&mut nested, &boundary);
+        for _ in 0..1_000_000 {
+            nested.push(bytes::CHANGED);
+            bytes::write_node_header(&mut nested, bytes::NE, 0, None);
+        }
+        let mut partial = QuadTree::<i32>::new(boundary);
+        assert_eq!(partial.apply_dirty(&nested), Err("Tree is too deep"));
+        partial.check_invariants()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_the_first_point_in_a_region() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(867);
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(!quadtree.contains_any(Rectangle::new(0.0, 0.0, 100.0, 100.0)));
+        for i in 0..500 {
+            quadtree.insert(Point2D::new(rng.gen_range(0.0..50.0), rng.gen_range(0.0..50.0), i))?;
+        }
+
+        for _ in 0..100 {
+            let region = Rectangle::new(
+                rng.gen_range(0.0..100.0),
+                rng.gen_range(0.0..100.0),
+                rng.gen_range(0.0..10.0),
+                rng.gen_range(0.0..10.0),
+            );
+            let all = quadtree.query(region);
+            let first = quadtree.first_in_region(region);
+            assert_eq!(first.map(|p| p.data), all.first().map(|p| p.data));
+            assert_eq!(quadtree.contains_any(region), !all.is_empty());
+        }
+        assert!(!quadtree.contains_any(Rectangle::new(60.0, 60.0, 40.0, 40.0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_changes_payloads_in_a_region() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut replica = QuadTree::<i32>::new(boundary);
+        let mut rng = StdRng::seed_from_u64(868);
+        for i in 0..1000 {
+            quadtree.insert(Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))?;
+        }
+        let mut patch = Vec::new();
+        quadtree.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+
+        let region = Rectangle::new(20.0, 20.0, 15.0, 15.0);
+        let expected: Vec<(f64, f64, i32)> =
+            quadtree.query(region).iter().map(|p| (p.x, p.y, -p.data)).collect();
+        for mut point in quadtree.query_mut(region) {
+            assert!(region.contains(point.x, point.y));
+            *point.data_mut() *= -1;
+        }
+        let changed: Vec<(f64, f64, i32)> =
+            quadtree.query(region).iter().map(|p| (p.x, p.y, p.data)).collect();
+        assert_eq!(changed, expected);
+        assert_eq!(quadtree.query(boundary).iter().filter(|p| p.data < 0).count(), expected.len());
+        quadtree.check_invariants()?;
+
+        // the nodes holding changed points are marked dirty
+        patch.clear();
+        quadtree.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+        assert_eq!(replica.to_bytes(), quadtree.to_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_converts_from_points_and_iterators() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(871);
+        let points: Vec<Point2D<usize>> = (0..500)
+            .map(|i| Point2D::new(rng.gen_range(20.0..60.0), rng.gen_range(10.0..90.0), i))
+            .collect();
+
+        let quadtree = QuadTree::try_from((boundary, points.clone()))?;
+        assert_eq!(quadtree.count(), points.len());
+        quadtree.check_invariants()?;
+        let outside = vec![Point2D::new(150.0, 0.0, 0)];
+        assert_eq!(QuadTree::try_from((boundary, outside)).err(), Some(RejectReason::OutOfBounds));
+
+        let collected: QuadTree<usize> = points.iter().copied().collect();
+        let bounds = collected.bounds().ok_or("empty")?;
+        assert!(collected.boundary().contains_rectangle(&bounds));
+        assert!(collected.boundary().width < 4.0 * bounds.width);
+        assert_eq!(collected.count(), points.len());
+        collected.check_invariants()?;
+        let empty: QuadTree<usize> = std::iter::empty().collect();
+        assert_eq!(empty.count(), 0);
+        let around_zero: QuadTree<usize> =
+            [Point2D::new(-1.0, -3.0, 0), Point2D::new(1.0, 0.5, 1)].into_iter().collect();
+        assert_eq!(*around_zero.boundary(), Rectangle::new(-2.0, -4.0, 4.0, 8.0));
+
+        let mut extended = QuadTree::new(boundary);
+        extended.extend(points.iter().copied().filter(|p| p.data % 2 == 0));
+        extended.extend(points.iter().copied().filter(|p| p.data % 2 == 1));
+        let region = Rectangle::new(30.0, 30.0, 20.0, 20.0);
+        let mut found: Vec<usize> = extended.query(region).iter().map(|p| p.data).collect();
+        let mut expected: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_splits_at_the_median_of_skewed_points() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(872);
+        // most points crowd into the north-west corner
+        let points: Vec<Point2D<i32>> = (0..3000)
+            .map(|i| {
+                let (x, y): (f64, f64) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+                Point2D::new(100.0 * x.powi(6), 100.0 * y.powi(6), i)
+            })
+            .collect();
+        let mut midpoint = QuadTree::new(boundary);
+        let mut median = QuadTree::with_split_strategy(boundary, SplitStrategy::Median);
+        assert_eq!(median.split_strategy(), SplitStrategy::Median);
+        for point in points.iter() {
+            midpoint.insert(*point)?;
+            median.insert(*point)?;
+        }
+        median.check_invariants()?;
+        assert!(median.depth() < midpoint.depth());
+
+        let sorted = |found: Vec<&Point2D<i32>>| {
+            let mut found: Vec<i32> = found.iter().map(|p| p.data).collect();
+            found.sort();
+            found
+        };
+        for region in [
+            Rectangle::new(0.0, 0.0, 0.5, 0.5),
+            Rectangle::new(0.01, 0.2, 3.0, 40.0),
+            Rectangle::new(50.0, 50.0, 50.0, 50.0),
+            boundary,
+        ] {
+            assert_eq!(sorted(median.query(region)), sorted(midpoint.query(region)));
+        }
+        let nearest = |tree: &QuadTree<i32>| {
+            tree.nearest_iter(0.1, 0.1).take(10).map(|(p, _)| p.data).collect::<Vec<_>>()
+        };
+        assert_eq!(nearest(&median), nearest(&midpoint));
+
+        // nodes keep where they split through bytes and patches
+        let copy = QuadTree::<i32>::from_bytes(&median.to_bytes())?;
+        copy.check_invariants()?;
+        assert_eq!(copy.to_bytes(), median.to_bytes());
+        let mut replica = QuadTree::<i32>::new(boundary);
+        let mut patch = Vec::new();
+        median.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+        for point in points.iter().take(500) {
+            median.insert(Point2D::new(point.y, point.x, point.data))?;
+        }
+        patch.clear();
+        median.serialize_dirty(&mut patch);
+        replica.apply_dirty(&patch)?;
+        replica.check_invariants()?;
+        assert_eq!(replica.to_bytes(), median.to_bytes());
+
+        let mut merged = QuadTree::with_split_strategy(boundary, SplitStrategy::Median);
+        let mut other = QuadTree::with_split_strategy(boundary, SplitStrategy::Median);
+        for (i, point) in points.iter().enumerate() {
+            let tree = if i % 3 == 0 { &mut merged } else { &mut other };
+            tree.insert(*point)?;
+        }
+        merged.merge(other)?;
+        merged.check_invariants()?;
+        assert_eq!(merged.count(), points.len());
+        assert_eq!(sorted(merged.query(boundary)), sorted(midpoint.query(boundary)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_into_a_reused_buffer() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(875);
+        for i in 0..1000 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+
+        let mut buffer: Vec<&Point2D<i32>> = Vec::with_capacity(64);
+        for _ in 0..50 {
+            let (x, y) = (rng.gen_range(0.0..95.0), rng.gen_range(0.0..95.0));
+            let region = Rectangle::new(x, y, 5.0, 5.0);
+            buffer.clear();
+            quadtree.query_into(region, &mut buffer);
+            let expected: Vec<i32> = quadtree.query(region).iter().map(|p| p.data).collect();
+            assert_eq!(buffer.iter().map(|p| p.data).collect::<Vec<_>>(), expected);
+            assert_eq!(buffer.capacity(), 64);
+        }
+
+        // results add up across calls
+        let west = Rectangle::new(0.0, 0.0, 40.0, 100.0);
+        let east = Rectangle::new(60.0, 0.0, 40.0, 100.0);
+        let mut both: Vec<&Point2D<i32>> = Vec::new();
+        quadtree.query_into(west, &mut both);
+        quadtree.query_into(east, &mut both);
+        assert_eq!(both.len(), quadtree.query(west).len() + quadtree.query(east).len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_tells_an_observer_about_changes() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+
+        impl TreeObserver for Log {
+            fn on_insert(&self, x: f64, _y: f64) {
+                self.0.lock().unwrap().push(format!("insert {}", x));
+            }
+
+            fn on_remove(&self, x: f64, _y: f64) {
+                self.0.lock().unwrap().push(format!("remove {}", x));
+            }
+
+            fn on_split(&self, boundary: &Rectangle) {
+                self.0.lock().unwrap().push(format!("split {}", boundary.width));
+            }
+
+            fn on_merge(&self, boundary: &Rectangle) {
+                self.0.lock().unwrap().push(format!("merge {}", boundary.width));
+            }
+        }
+        let take = |log: &Log| mem::take(&mut *log.0.lock().unwrap());
+
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let log = Arc::new(Log::default());
+        quadtree.set_observer(log.clone());
+        for (x, y) in [(10.0, 10.0), (60.0, 10.0), (10.0, 60.0), (60.0, 60.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+        }
+        // all five points end up in the north-west quadrant, which splits too
+        quadtree.clear();
+        for x in 1..6 {
+            quadtree.insert(Point2D { x: x as f64, y: 1.0, data: 0 })?;
+        }
+        let expected = [
+            "insert 10", "insert 60", "insert 10", "insert 60", "remove 10", "remove 60",
+            "remove 10", "remove 60", "insert 1", "insert 2", "insert 3", "insert 4", "insert 5",
+            "split 100", "split 50", "split 25", "split 12.5", "split 6.25",
+        ];
+        assert_eq!(take(&log), expected);
+
+        quadtree.clear();
+        take(&log);
+        quadtree.shrink_to_fit();
+        assert_eq!(take(&log), ["merge 6.25", "merge 12.5", "merge 25", "merge 50", "merge 100"]);
+        quadtree.check_invariants()?;
+
+        let mut other = QuadTree::new(boundary);
+        other.insert(Point2D { x: 90.0, y: 90.0, data: 0 })?;
+        other.set_observer(Arc::new(Log::default()));
+        quadtree.merge(other)?;
+        quadtree.upsert(90.0, 90.0, 1, |data, new| *data += new)?;
+        quadtree.entry(5.0, 5.0)?.or_insert(0);
+        assert_eq!(take(&log), ["insert 90", "insert 5"]);
+
+        assert!(quadtree.remove_observer().is_some());
+        quadtree.insert(Point2D { x: 1.0, y: 1.0, data: 0 })?;
+        assert!(take(&log).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_translates_and_scales_in_place() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(880);
+        let mut points: Vec<(f64, f64)> = (0..1000)
+            .map(|_| (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0)))
+            .collect();
+        // points on and right before the edges between nodes, where rounding
+        // may carry them into the neighbouring node
+        for i in 1..16 {
+            let edge = i as f64 * 6.25;
+            let before = f64::from_bits(edge.to_bits() - 1);
+            points.extend([(edge, edge), (before, edge), (edge, before), (before, before)]);
+        }
+        for (i, (x, y)) in points.iter().enumerate() {
+            quadtree.insert(Point2D { x: *x, y: *y, data: i })?;
+        }
+
+        let check = |quadtree: &mut QuadTree<usize>, points: &[(f64, f64)]| {
+            quadtree.check_invariants()?;
+            // upsert finds points along the path insert takes, so each point
+            // must be where insert would put it
+            for (x, y) in points.iter() {
+                quadtree.upsert(*x, *y, 0, |_, _| {})?;
+            }
+            assert_eq!(quadtree.count(), points.len());
+            let region = Rectangle::new(points[0].0 - 5.0, points[0].1 - 5.0, 10.0, 10.0);
+            let mut found: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+            found.sort();
+            let expected: Vec<usize> =
+                (0..points.len()).filter(|&i| region.contains(points[i].0, points[i].1)).collect();
+            assert_eq!(found, expected);
+            Ok::<_, Box<dyn std::error::Error>>(())
+        };
+
+        let (dx, dy) = (0.1, -1000.0 / 3.0);
+        quadtree.translate(dx, dy);
+        points.iter_mut().for_each(|(x, y)| (*x, *y) = (*x + dx, *y + dy));
+        assert_eq!(*quadtree.boundary(), Rectangle::new(0.1, dy, 100.0, 100.0));
+        check(&mut quadtree, &points)?;
+
+        quadtree.scale(3.0, 0.7);
+        points.iter_mut().for_each(|(x, y)| (*x, *y) = (*x * 3.0, *y * 0.7));
+        check(&mut quadtree, &points)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_one_point_per_key() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(884);
+        // entities sampled along their outline, ids in the payload
+        for id in 0..50 {
+            let (x, y) = (rng.gen_range(0.0..90.0), rng.gen_range(0.0..90.0));
+            for step in 0..10 {
+                let step = step as f64;
+                tree.insert(Point2D::new(x + step, y + step / 2.0, (id, step)))?;
+            }
+        }
+
+        let region = Rectangle::new(20.0, 20.0, 40.0, 40.0);
+        let unique = tree.query_unique_by(region, |&(id, _)| id);
+        let mut ids: Vec<i32> = unique.iter().map(|point| point.data.0).collect();
+        ids.sort();
+        let mut expected: Vec<i32> = tree.query(region).iter().map(|point| point.data.0).collect();
+        expected.sort();
+        expected.dedup();
+        assert_eq!(ids, expected);
+        assert!(tree.query(region).len() > ids.len());
+        assert!(unique.iter().all(|point| region.contains(point.x, point.y)));
+
+        // distinct keys keep every point
+        let all = tree.query_unique_by(region, |&(id, step)| (id, step as i32));
+        assert_eq!(all.len(), tree.query(region).len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prunes_queries_by_the_extent_of_points() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(886);
+        // clustered in the north-west corner of the north-west quadrant
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0));
+            quadtree.insert(Point2D::new(x, y, i))?;
+        }
+        quadtree.check_invariants()?;
+
+        // overlaps the boundary of the node holding the cluster, but none of
+        // its points
+        let region = Rectangle::new(20.0, 20.0, 20.0, 20.0);
+        let (found, stats) = quadtree.query_with_stats(region);
+        assert!(found.is_empty());
+        assert_eq!((stats.nodes_visited, stats.points_tested), (1, 0));
+        let bounds = quadtree.bounds().ok_or("empty")?;
+        assert!(bounds.x + bounds.width < 10.0 && bounds.y + bounds.height < 10.0);
+
+        // the extents follow every change of the points
+        quadtree.translate(30.0, 30.0);
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.query(region).len(), quadtree.query_with_stats(region).0.len());
+        assert!(!quadtree.query(region).is_empty());
+        let mut other = QuadTree::new(boundary);
+        other.insert(Point2D::new(90.0, 90.0, 500))?;
+        quadtree.merge(other)?;
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.query(Rectangle::new(80.0, 80.0, 20.0, 20.0)).len(), 1);
+        quadtree.rebuild();
+        quadtree.check_invariants()?;
+        let copy = QuadTree::<i32>::from_bytes(&quadtree.to_bytes())?;
+        copy.check_invariants()?;
+        assert_eq!(copy.bounds(), quadtree.bounds());
+
+        quadtree.clear();
+        quadtree.check_invariants()?;
+        assert_eq!(quadtree.bounds(), None);
+        // the sub-trees kept by `clear` aren't even looked at
+        let (_, stats) = quadtree.query_with_stats(boundary);
+        assert_eq!(stats.nodes_visited, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_follows_insert_hints_while_they_are_valid() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut hinted = QuadTree::new(boundary);
+        let mut plain = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(888);
+
+        // a trace heading east with some jitter, like the fixes of a GPS
+        // track
+        let (mut x, mut y) = (10.0f64, 50.0f64);
+        let mut hint = None;
+        let mut followed = 0;
+        for i in 0..5000 {
+            x = (x + rng.gen_range(0.0..0.03)).clamp(0.0, 100.0);
+            y = (y + rng.gen_range(-0.03..0.03)).clamp(0.0, 100.0);
+            let valid = |hint: &Option<InsertHint>, tree: &QuadTree<i32>| {
+                hint.as_ref().is_some_and(|hint: &InsertHint| {
+                    hint.generation == tree.generation && hint.cell.owns(x, y)
+                })
+            };
+            followed += valid(&hint, &hinted) as usize;
+            hint = Some(hinted.insert_with_hint(Point2D::new(x, y, i), hint)?);
+            plain.insert(Point2D::new(x, y, i))?;
+        }
+        assert!(followed > 3000, "followed {} hints", followed);
+        hinted.check_invariants()?;
+        assert_eq!(hinted.to_bytes(), plain.to_bytes());
+
+        // hints of another tree, after a rebuild or for points elsewhere are
+        // ignored
+        let foreign = plain.insert_with_hint(Point2D::new(x, y, -1), None)?;
+        let hint = hinted.insert_with_hint(Point2D::new(x, y, -1), Some(foreign))?;
+        hinted.rebuild();
+        let hint = hinted.insert_with_hint(Point2D::new(1.0, 99.0, -2), Some(hint))?;
+        hinted.insert_with_hint(Point2D::new(99.0, 1.0, -3), Some(hint))?;
+        let error = hinted.insert_with_hint(Point2D::new(101.0, 1.0, -4), None).err();
+        assert_eq!(error.map(|error| error.reason), Some(RejectReason::OutOfBounds));
+        hinted.check_invariants()?;
+        assert_eq!(hinted.count(), 5003);
+        for (x, y) in [(1.0, 99.0), (99.0, 1.0)] {
+            assert_eq!(hinted.query(Rectangle::new(x, y, 0.0, 0.0)).len(), 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_insert_hints_gone_stale() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut hinted = QuadTree::new(boundary);
+        let mut plain = QuadTree::new(boundary);
+        let hint = hinted.insert_with_hint(Point2D::new(10.0, 10.0, 0), None)?;
+        plain.insert(Point2D::new(10.0, 10.0, 0))?;
+        assert!(hint.path.is_empty());
+
+        // splits the root the hint leads to, it still owns the next point
+        let mut rng = StdRng::seed_from_u64(888);
+        for i in 1..50 {
+            let (x, y) = (rng.gen_range(0.0..20.0), rng.gen_range(0.0..20.0));
+            hinted.insert(Point2D::new(x, y, i))?;
+            plain.insert(Point2D::new(x, y, i))?;
+        }
+        assert!(!hinted.is_leaf() && hint.cell.owns(11.0, 11.0));
+        assert_ne!(hint.generation, hinted.generation);
+
+        let hint = hinted.insert_with_hint(Point2D::new(11.0, 11.0, 50), Some(hint))?;
+        plain.insert(Point2D::new(11.0, 11.0, 50))?;
+        assert!(!hint.path.is_empty());
+        assert_eq!(hint.generation, hinted.generation);
+        hinted.check_invariants()?;
+        assert_eq!(hinted.to_bytes(), plain.to_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_summarizes_the_points_in_a_region() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(889);
+        for i in 0..2000 {
+            tree.insert(Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))?;
+        }
+
+        let region = Rectangle::new(20.0, 30.0, 25.0, 40.0);
+        let found = tree.query(region);
+        let count = found.len() as f64;
+        let (x, y) = tree.centroid_in_region(region).ok_or("no points")?;
+        let expected_x = found.iter().map(|point| point.x).sum::<f64>() / count;
+        let expected_y = found.iter().map(|point| point.y).sum::<f64>() / count;
+        assert!((x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9);
+
+        let circle = tree.bounding_circle_in_region(region).ok_or("no points")?;
+        assert!(found.iter().all(|point| circle.contains(point.x, point.y)));
+        // a smaller circle would leave out the points farthest from the center
+        let farthest = found
+            .iter()
+            .map(|point| (point.x - circle.x).hypot(point.y - circle.y))
+            .fold(0.0, f64::max);
+        assert!(farthest > circle.radius * (1.0 - 1e-9));
+        assert!(circle.radius <= region.width.hypot(region.height) / 2.0);
+
+        let empty = Rectangle::new(-10.0, -10.0, 5.0, 5.0);
+        assert!(tree.centroid_in_region(empty).is_none());
+        assert!(tree.bounding_circle_in_region(empty).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_queries_the_difference_of_two_regions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(891);
+        for i in 0..3000 {
+            tree.insert(Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))?;
+        }
+        // points on the old viewport's edge
+        for i in 0..20 {
+            tree.insert(Point2D::new(50.0, 20.0 + i as f64, 3000 + i))?;
+        }
+
+        // a viewport panning right and down
+        let before = Rectangle::new(10.0, 10.0, 40.0, 30.0);
+        let after = Rectangle::new(25.0, 15.0, 40.0, 30.0);
+        let mut appeared: Vec<i32> =
+            tree.query_difference(after, before).iter().map(|point| point.data).collect();
+        appeared.sort();
+        let old: Vec<i32> = tree.query(before).iter().map(|point| point.data).collect();
+        let mut expected: Vec<i32> = tree
+            .query(after)
+            .iter()
+            .map(|point| point.data)
+            .filter(|id| !old.contains(id))
+            .collect();
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(appeared, expected);
+
+        assert!(tree.query_difference(before, before).is_empty());
+        let disjoint = Rectangle::new(80.0, 80.0, 10.0, 10.0);
+        assert_eq!(tree.query_difference(before, disjoint).len(), old.len());
+        assert!(tree.query_difference(before, *tree.boundary()).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_path_to_a_broken_node() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut rng = StdRng::seed_from_u64(892);
+        for i in 0..200 {
+            tree.insert(Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))?;
+        }
+        let path = tree.locate(10.0, 10.0).ok_or("no node")?;
+        assert!(path.depth() > 1);
+
+        let mut node = &mut tree;
+        for &quadrant in path.quadrants() {
+            node = node.slot_mut(quadrant).as_mut().ok_or("missing sub-tree")?;
+        }
+        node.count += 1;
+        let violation = tree.check_invariants().err().ok_or("no violation")?;
+        assert!(violation.ends_with(&format!(" at {}", path)), "{}", violation);
+        assert!(violation.contains("counts"));
+
+        #[cfg(all(feature = "debug-validate", debug_assertions))]
+        {
+            let insert = std::panic::AssertUnwindSafe(|| tree.insert(Point2D::new(90.0, 90.0, 0)));
+            let panic = std::panic::catch_unwind(insert).err().ok_or("no panic")?;
+            let message = panic.downcast_ref::<String>().ok_or("no message")?;
+            assert!(message.starts_with("insert left the tree invalid: "), "{}", message);
+            assert!(message.ends_with(&format!(" at {}", path)), "{}", message);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_intersects_two_trees() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let (mut left, mut right) = (QuadTree::new(boundary), QuadTree::new(boundary));
+        let mut rng = StdRng::seed_from_u64(895);
+        // places both datasets contain, slightly apart, and places only one does
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(1.0..99.0), rng.gen_range(1.0..99.0));
+            left.insert(Point2D::new(x, y, i))?;
+            let (dx, dy) = (rng.gen_range(-0.01..0.01), rng.gen_range(-0.01..0.01));
+            right.insert(Point2D::new(x + dx, y + dy, format!("poi {}", i)))?;
+        }
+        for i in 500..1000 {
+            left.insert(Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i))?;
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            right.insert(Point2D::new(x, y, format!("poi {}", i)))?;
+        }
+
+        let epsilon = 0.05;
+        let mut found: Vec<(i32, String)> = left
+            .intersection(&right, epsilon)
+            .into_iter()
+            .map(|(a, b)| (a.data, b.data.clone()))
+            .collect();
+        found.sort();
+        let (points, others) = (left.query(boundary), right.query(boundary));
+        let mut expected = Vec::new();
+        for a in points.iter() {
+            for b in others.iter().filter(|b| a.distance_to(b) <= epsilon) {
+                expected.push((a.data, b.data.clone()));
+            }
+        }
+        expected.sort();
+        assert!(expected.len() >= 500);
+        assert_eq!(found, expected);
+
+        assert!(left.intersection(&QuadTree::<()>::new(boundary), 1.0).is_empty());
+        assert!(left.intersection(&right, -1.0).is_empty());
+        assert!(left.intersection(&right, f64::NAN).is_empty());
+
         Ok(())
     }
 }
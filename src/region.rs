@@ -0,0 +1,215 @@
+// a quadrant of the raster: either every cell has the same value or it is
+// split into [ne, se, sw, nw] children. Children lying completely outside a
+// grid that isn't a power-of-two square are `None`.
+#[derive(Debug, Clone, PartialEq)]
+enum Node<V> {
+    Uniform(V),
+    Split(Box<[Option<Node<V>>; 4]>),
+}
+
+/// A region quadtree over raster data (bitmaps, terrain, ...). Quadrants
+/// whose cells all hold the same value collapse into a single leaf, so large
+/// uniform areas take constant space. Cells are addressed by column `x` and
+/// row `y`, with `y` growing to the south like for the point trees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionQuadTree<V> {
+    width: usize,
+    height: usize,
+    // side of the power-of-two square covered by the root
+    size: usize,
+    root: Option<Node<V>>,
+}
+
+impl<V: Clone + PartialEq> RegionQuadTree<V> {
+    /// Builds the tree from `width * height` values in row-major order.
+    pub fn from_grid(width: usize, height: usize, values: &[V]) -> Result<Self, &'static str> {
+        if width.checked_mul(height) != Some(values.len()) {
+            return Err("Grid must hold exactly width * height values");
+        }
+        let size = width.max(height).max(1).next_power_of_two();
+        let grid = Grid {
+            width,
+            height,
+            values,
+        };
+        Ok(RegionQuadTree {
+            width,
+            height,
+            size,
+            root: grid.build(0, 0, size),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of uniform leaves, a measure of how well the raster compressed.
+    pub fn leaf_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&Node<V>> = self.root.iter().collect();
+        while let Some(node) = stack.pop() {
+            match node {
+                Node::Uniform(_) => count += 1,
+                Node::Split(children) => stack.extend(children.iter().flatten()),
+            }
+        }
+        count
+    }
+
+    /// The value of the cell at column `x` and row `y`, `None` outside the
+    /// grid.
+    pub fn value_at(&self, x: usize, y: usize) -> Option<&V> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let (mut node_x, mut node_y, mut size) = (0, 0, self.size);
+        let mut node = self.root.as_ref()?;
+        loop {
+            match node {
+                Node::Uniform(value) => return Some(value),
+                Node::Split(children) => {
+                    size /= 2;
+                    let east = x >= node_x + size;
+                    let south = y >= node_y + size;
+                    let index = match (east, south) {
+                        (true, false) => 0,
+                        (true, true) => 1,
+                        (false, true) => 2,
+                        (false, false) => 3,
+                    };
+                    node_x += if east { size } else { 0 };
+                    node_y += if south { size } else { 0 };
+                    node = children[index].as_ref()?;
+                }
+            }
+        }
+    }
+
+    /// Expands the tree back into `width * height` values in row-major order.
+    pub fn to_grid(&self) -> Vec<V> {
+        let mut cells = vec![None; self.width * self.height];
+        let mut stack: Vec<(&Node<V>, usize, usize, usize)> =
+            self.root.iter().map(|root| (root, 0, 0, self.size)).collect();
+        while let Some((node, x, y, size)) = stack.pop() {
+            match node {
+                Node::Uniform(value) => {
+                    for row in y..(y + size).min(self.height) {
+                        let start = row * self.width;
+                        for cell in &mut cells[start + x..start + (x + size).min(self.width)] {
+                            *cell = Some(value.clone());
+                        }
+                    }
+                }
+                Node::Split(children) => {
+                    let half = size / 2;
+                    let origins = [(x + half, y), (x + half, y + half), (x, y + half), (x, y)];
+                    for (child, (x, y)) in children.iter().zip(origins) {
+                        if let Some(child) = child {
+                            stack.push((child, x, y, half));
+                        }
+                    }
+                }
+            }
+        }
+        cells
+            .into_iter()
+            .map(|cell| cell.expect("every cell inside the grid is covered by a leaf"))
+            .collect()
+    }
+}
+
+struct Grid<'a, V> {
+    width: usize,
+    height: usize,
+    values: &'a [V],
+}
+
+impl<V: Clone + PartialEq> Grid<'_, V> {
+    // the node for the `size` x `size` square at (x, y), merging children
+    // that are uniform with the same value
+    fn build(&self, x: usize, y: usize, size: usize) -> Option<Node<V>> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        if size == 1 {
+            return Some(Node::Uniform(self.values[y * self.width + x].clone()));
+        }
+
+        let half = size / 2;
+        let children = [
+            self.build(x + half, y, half),
+            self.build(x + half, y + half, half),
+            self.build(x, y + half, half),
+            self.build(x, y, half),
+        ];
+        // the nw child always exists, the others may lie outside the grid
+        if let Some(Node::Uniform(first)) = &children[3] {
+            let uniform = children.iter().flatten().all(|child| match child {
+                Node::Uniform(value) => value == first,
+                Node::Split(_) => false,
+            });
+            if uniform {
+                return Some(Node::Uniform(first.clone()));
+            }
+        }
+        Some(Node::Split(Box::new(children)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_merges_uniform_quadrants() -> Result<(), Box<dyn std::error::Error>> {
+        // 6 x 5 raster with a 2 x 2 block of ones
+        let mut values = vec![0u8; 30];
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            values[y * 6 + x] = 1;
+        }
+        let tree = RegionQuadTree::from_grid(6, 5, &values)?;
+        assert_eq!((tree.width(), tree.height()), (6, 5));
+        assert!(tree.leaf_count() < values.len() / 2);
+        assert_eq!(tree.value_at(2, 3), Some(&1));
+        assert_eq!(tree.value_at(4, 3), Some(&0));
+        assert_eq!(tree.value_at(6, 0), None);
+        assert_eq!(tree.value_at(0, 5), None);
+        assert_eq!(tree.to_grid(), values);
+
+        let uniform = RegionQuadTree::from_grid(3, 7, &[5; 21])?;
+        assert_eq!(uniform.leaf_count(), 1);
+        assert_eq!(uniform.to_grid(), vec![5; 21]);
+
+        assert!(RegionQuadTree::from_grid(3, 3, &[0; 8]).is_err());
+        let empty = RegionQuadTree::<u8>::from_grid(0, 4, &[])?;
+        assert_eq!(empty.leaf_count(), 0);
+        assert!(empty.to_grid().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reconstructs_random_rasters() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(842);
+        for _ in 0..20 {
+            let (width, height) = (rng.gen_range(1..40), rng.gen_range(1..40));
+            let values: Vec<u8> = (0..width * height).map(|_| rng.gen_range(0..3)).collect();
+            let tree = RegionQuadTree::from_grid(width, height, &values)?;
+            assert_eq!(tree.to_grid(), values);
+            for y in 0..height {
+                for x in 0..width {
+                    assert_eq!(tree.value_at(x, y), Some(&values[y * width + x]));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
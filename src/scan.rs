@@ -0,0 +1,113 @@
+//! Leaf scans over `f32` copies of the coordinates, enabled with the
+//! `f32-scan` feature. Comparisons run on fixed-size chunks without branches,
+//! so the compiler turns them into SIMD instructions testing 8 points at once.
+//! The `f32` test is conservative, callers confirm candidates with `f64`.
+
+use crate::geometry::Rectangle;
+
+const LANES: usize = 8;
+
+/// A region rounded outwards to `f32`, so no point inside the original
+/// region can fall outside of it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct F32Region {
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+impl F32Region {
+    pub(crate) fn enclosing(region: &Rectangle) -> Self {
+        F32Region {
+            min_x: round_down(region.x),
+            min_y: round_down(region.y),
+            max_x: round_up(region.x + region.width),
+            max_y: round_up(region.y + region.height),
+        }
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        // `&` instead of `&&` keeps the test branch free
+        (x >= self.min_x) & (x <= self.max_x) & (y >= self.min_y) & (y <= self.max_y)
+    }
+}
+
+fn round_down(value: f64) -> f32 {
+    let rounded = value as f32;
+    if f64::from(rounded) > value {
+        rounded.next_down()
+    } else {
+        rounded
+    }
+}
+
+fn round_up(value: f64) -> f32 {
+    let rounded = value as f32;
+    if f64::from(rounded) < value {
+        rounded.next_up()
+    } else {
+        rounded
+    }
+}
+
+/// Calls `f` with the index of every coordinate pair inside `region`, in
+/// increasing order.
+pub(crate) fn candidates(xs: &[f32], ys: &[f32], region: &F32Region, mut f: impl FnMut(usize)) {
+    debug_assert_eq!(xs.len(), ys.len());
+    let chunks = xs.chunks_exact(LANES).zip(ys.chunks_exact(LANES));
+    for (chunk, (cx, cy)) in chunks.enumerate() {
+        let mut mask = 0u8;
+        for lane in 0..LANES {
+            mask |= (region.contains(cx[lane], cy[lane]) as u8) << lane;
+        }
+        while mask != 0 {
+            f(chunk * LANES + mask.trailing_zeros() as usize);
+            mask &= mask - 1;
+        }
+    }
+
+    let done = xs.len() - xs.len() % LANES;
+    for index in done..xs.len() {
+        if region.contains(xs[index], ys[index]) {
+            f(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_never_misses_points_inside_the_region() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(815);
+        let points: Vec<(f64, f64)> = (0..1001)
+            .map(|_| (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))
+            .collect();
+        let xs: Vec<f32> = points.iter().map(|p| p.0 as f32).collect();
+        let ys: Vec<f32> = points.iter().map(|p| p.1 as f32).collect();
+
+        for _ in 0..50 {
+            // regions starting and ending exactly on stored coordinates
+            let (x, y) = points[rng.gen_range(0..points.len())];
+            let (x2, y2) = points[rng.gen_range(0..points.len())];
+            let region = Rectangle::from_corners(x, y, x2, y2)?;
+
+            let mut found = Vec::new();
+            candidates(&xs, &ys, &F32Region::enclosing(&region), |i| {
+                if region.contains(points[i].0, points[i].1) {
+                    found.push(i);
+                }
+            });
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&i| region.contains(points[i].0, points[i].1))
+                .collect();
+            assert_eq!(found, expected);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::cmp::Reverse;
+use std::marker::PhantomData;
+
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+/// A payload tagged with its position in insertion order, starting at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sequenced<T> {
+    pub sequence: u64,
+    pub data: T,
+}
+
+/// Wraps any index (`QuadTree`, `SparseQuadTree`, ...) and stamps every
+/// inserted point with a monotonically increasing sequence number, for
+/// "newest points in a region" queries or deterministic tie-breaking.
+#[derive(Debug)]
+pub struct SequencedIndex<T, I: SpatialIndex<Sequenced<T>>> {
+    index: I,
+    next: u64,
+    data: PhantomData<T>,
+}
+
+impl<T, I: SpatialIndex<Sequenced<T>>> SequencedIndex<T, I> {
+    pub fn new(boundary: Rectangle) -> Self {
+        SequencedIndex {
+            index: I::new(boundary),
+            next: 0,
+            data: PhantomData,
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        self.index.boundary()
+    }
+
+    pub fn count(&self) -> usize {
+        self.index.count()
+    }
+
+    /// The wrapped index.
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+
+    /// Inserts the point and returns its sequence number. Rejected points
+    /// don't use up a number.
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<u64, InsertError<T>> {
+        let sequence = self.next;
+        self.index
+            .insert(point.map_data(|data| Sequenced { sequence, data }))
+            .map_err(|error| error.map_data(|sequenced| sequenced.data))?;
+        self.next += 1;
+        Ok(sequence)
+    }
+
+    /// Points inside `region`, oldest first.
+    pub fn query(&self, region: impl Into<Rectangle>) -> Vec<&Point2D<Sequenced<T>>> {
+        let mut result = self.index.query(region.into());
+        result.sort_unstable_by_key(|point| point.data.sequence);
+        result
+    }
+
+    /// The `n` most recently inserted points inside `region`, newest first.
+    pub fn newest_in(
+        &self,
+        region: impl Into<Rectangle>,
+        n: usize,
+    ) -> Vec<&Point2D<Sequenced<T>>> {
+        let mut result = self.index.query(region.into());
+        result.sort_unstable_by_key(|point| Reverse(point.data.sequence));
+        result.truncate(n);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{QuadTree, RejectReason, SparseQuadTree};
+
+    use super::*;
+
+    fn newest<I: SpatialIndex<Sequenced<usize>>>() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut index = SequencedIndex::<usize, I>::new(boundary);
+        let mut rng = StdRng::seed_from_u64(845);
+        let mut points = Vec::new();
+        for i in 0..300 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            assert_eq!(index.insert(point)?, i as u64);
+            points.push(point);
+        }
+        let error = index.insert(Point2D::new(200.0, 0.0, 300)).err().ok_or("inserted")?;
+        assert_eq!((error.reason, error.point.data), (RejectReason::OutOfBounds, 300));
+        assert_eq!(index.insert(Point2D::new(0.0, 0.0, 300))?, 300);
+
+        let region = Rectangle::new(10.0, 10.0, 40.0, 40.0);
+        let expected: Vec<usize> = points
+            .iter()
+            .rev()
+            .filter(|point| region.contains(point.x, point.y))
+            .take(5)
+            .map(|point| point.data)
+            .collect();
+        let newest: Vec<usize> =
+            index.newest_in(region, 5).iter().map(|point| point.data.data).collect();
+        assert_eq!(newest, expected);
+
+        let all = index.query(region);
+        assert!(all.windows(2).all(|pair| pair[0].data.sequence < pair[1].data.sequence));
+        assert!(all.iter().all(|point| point.data.sequence == point.data.data as u64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_the_newest_points_in_a_region() -> Result<(), Box<dyn std::error::Error>> {
+        newest::<QuadTree<Sequenced<usize>>>()?;
+        newest::<SparseQuadTree<Sequenced<usize>>>()
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+/// Lets a single writer change a tree while any number of readers, e.g. on
+/// other threads, query the version it last published. The writer works on
+/// its own copy and `publish` swaps a clone of it in atomically, readers
+/// never lock and keep the snapshot they loaded as long as they hold it.
+///
+/// Works with any tree that is `Clone`. Publishing clones the whole tree,
+/// except for `PersistentQuadTree` whose clones share all nodes.
+#[derive(Debug)]
+pub struct SnapshotQuadTree<I> {
+    tree: I,
+    published: Arc<ArcSwap<I>>,
+}
+
+/// A handle to the snapshots a `SnapshotQuadTree` publishes, cheap to clone
+/// and send to reader threads.
+#[derive(Debug)]
+pub struct SnapshotReader<I> {
+    published: Arc<ArcSwap<I>>,
+}
+
+impl<I: Clone> SnapshotQuadTree<I> {
+    /// Wraps `tree` and publishes it as the first snapshot.
+    pub fn new(tree: I) -> Self {
+        SnapshotQuadTree {
+            published: Arc::new(ArcSwap::from_pointee(tree.clone())),
+            tree,
+        }
+    }
+
+    /// The writer's copy, including changes not published yet.
+    pub fn tree(&self) -> &I {
+        &self.tree
+    }
+
+    /// Changes go to the writer's copy, readers see them after `publish`.
+    pub fn tree_mut(&mut self) -> &mut I {
+        &mut self.tree
+    }
+
+    /// Makes the current state of the writer's copy the snapshot readers
+    /// load from now on. Readers still holding an older snapshot keep it.
+    pub fn publish(&mut self) {
+        self.published.store(Arc::new(self.tree.clone()));
+    }
+
+    /// The last published snapshot.
+    pub fn snapshot(&self) -> Arc<I> {
+        self.published.load_full()
+    }
+
+    pub fn reader(&self) -> SnapshotReader<I> {
+        SnapshotReader {
+            published: Arc::clone(&self.published),
+        }
+    }
+}
+
+impl<I> SnapshotReader<I> {
+    /// The last published snapshot, without locking. Load a fresh one e.g.
+    /// every frame, a loaded snapshot never changes.
+    pub fn load(&self) -> Arc<I> {
+        self.published.load_full()
+    }
+}
+
+impl<I> Clone for SnapshotReader<I> {
+    fn clone(&self) -> Self {
+        SnapshotReader {
+            published: Arc::clone(&self.published),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{PersistentQuadTree, Point2D, Rectangle, SparseQuadTree};
+
+    use super::*;
+
+    #[test]
+    fn it_publishes_snapshots_to_readers() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut snapshots = SnapshotQuadTree::new(SparseQuadTree::new(boundary));
+        let reader = snapshots.reader();
+        let mut rng = StdRng::seed_from_u64(882);
+
+        snapshots.tree_mut().insert(Point2D::new(10.0, 10.0, 0usize))?;
+        assert_eq!(snapshots.tree().count(), 1);
+        assert_eq!(reader.load().count(), 0);
+        let before = reader.load();
+        snapshots.publish();
+        assert_eq!((reader.load().count(), snapshots.snapshot().count()), (1, 1));
+        assert_eq!(before.count(), 0);
+
+        // readers only ever see whole batches while the writer keeps going
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+            for _ in 0..4 {
+                let (reader, done) = (reader.clone(), &done);
+                scope.spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        let snapshot = reader.load();
+                        let count = snapshot.count();
+                        assert_eq!(count % 10, 1);
+                        assert!(count >= last);
+                        assert_eq!(snapshot.query(boundary).len(), count);
+                        last = count;
+                    }
+                });
+            }
+            for batch in 0..100 {
+                for _ in 0..10 {
+                    let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+                    snapshots.tree_mut().insert(Point2D::new(x, y, batch))?;
+                }
+                snapshots.publish();
+            }
+            done.store(true, Ordering::Relaxed);
+            Ok(())
+        })?;
+        assert_eq!(reader.load().count(), 1001);
+
+        // persistent trees publish without copying their nodes
+        let mut snapshots = SnapshotQuadTree::new(PersistentQuadTree::new(boundary));
+        let tree = snapshots.tree().insert(Point2D::new(50.0, 50.0, 1))?;
+        *snapshots.tree_mut() = tree;
+        snapshots.publish();
+        assert_eq!(snapshots.reader().load().count(), 1);
+
+        Ok(())
+    }
+}
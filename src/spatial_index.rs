@@ -0,0 +1,68 @@
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+
+/// Operations every spatial index in this crate supports, so code can be
+/// written once and run against any of the implementations.
+pub trait SpatialIndex<T> {
+    fn new(boundary: Rectangle) -> Self
+    where
+        Self: Sized;
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>>;
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>>;
+
+    fn query_many(&self, regions: &[Rectangle]) -> Vec<Vec<&Point2D<T>>> {
+        regions.iter().map(|region| self.query(*region)).collect()
+    }
+
+    fn count(&self) -> usize;
+
+    fn boundary(&self) -> &Rectangle;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        KdTree, LinearQuadTree, PersistentQuadTree, Point2D, QuadTree, Rectangle, SparseQuadTree,
+        VecIndex,
+    };
+
+    use super::*;
+
+    fn fill_and_query<I: SpatialIndex<u8>>() -> Result<(), Box<dyn std::error::Error>> {
+        let mut index = I::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..20 {
+            index.insert(Point2D {
+                x: 5.0 * i as f64,
+                y: 5.0 * i as f64,
+                data: i,
+            })?;
+        }
+        assert!(index.insert(Point2D { x: 200.0, y: 0.0, data: 0 }).is_err());
+        assert_eq!(index.count(), 20);
+        assert_eq!(*index.boundary(), Rectangle::new(0.0, 0.0, 100.0, 100.0));
+
+        let region = Rectangle::new(0.0, 0.0, 22.0, 22.0);
+        let mut found: Vec<u8> = index.query(region).iter().map(|p| p.data).collect();
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2, 3, 4]);
+
+        let results = index.query_many(&[region, Rectangle::new(200.0, 200.0, 1.0, 1.0)]);
+        assert_eq!(results[0].len(), 5);
+        assert!(results[1].is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_works_for_every_implementation() -> Result<(), Box<dyn std::error::Error>> {
+        fill_and_query::<QuadTree<u8>>()?;
+        fill_and_query::<SparseQuadTree<u8>>()?;
+        fill_and_query::<LinearQuadTree<u8>>()?;
+        fill_and_query::<VecIndex<u8>>()?;
+        fill_and_query::<PersistentQuadTree<u8>>()?;
+        fill_and_query::<KdTree<u8>>()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::ops::Range;
+
+use crate::aggregate::{Aggregate, AggregateQuadTree};
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+
+/// A payload stamped with the time it was recorded, in whatever unit the
+/// caller uses (e.g. milliseconds since the epoch).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timed<T> {
+    pub timestamp: u64,
+    pub data: T,
+}
+
+// oldest and newest timestamp below a node, used to prune by time
+#[derive(Debug, Clone, Copy, Default)]
+struct TimeSpan(Option<(u64, u64)>);
+
+impl TimeSpan {
+    fn overlaps(&self, range: &Range<u64>) -> bool {
+        self.0.is_some_and(|(min, max)| min < range.end && range.start <= max)
+    }
+}
+
+impl<T> Aggregate<Timed<T>> for TimeSpan {
+    fn add(&mut self, point: &Point2D<Timed<T>>) {
+        let timestamp = point.data.timestamp;
+        Aggregate::<Timed<T>>::merge(self, &TimeSpan(Some((timestamp, timestamp))));
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 = match (self.0, other.0) {
+            (Some((min, max)), Some((other_min, other_max))) => {
+                Some((min.min(other_min), max.max(other_max)))
+            }
+            (span, None) | (None, span) => span,
+        };
+    }
+}
+
+/// A quadtree of timestamped points. Every node knows the time span of the
+/// points below it, so time-bounded queries and evicting old points skip
+/// whole sub-trees, e.g. to keep a sliding window of sensor positions.
+#[derive(Debug)]
+pub struct TemporalQuadTree<T> {
+    tree: AggregateQuadTree<Timed<T>, TimeSpan>,
+}
+
+impl<T> TemporalQuadTree<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        TemporalQuadTree {
+            tree: AggregateQuadTree::new(boundary),
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        self.tree.boundary()
+    }
+
+    pub fn count(&self) -> usize {
+        self.tree.count()
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>, timestamp: u64) -> Result<(), InsertError<T>> {
+        self.tree
+            .insert(point.map_data(|data| Timed { timestamp, data }))
+            .map_err(|error| error.map_data(|timed| timed.data))
+    }
+
+    /// Removes every point recorded before `timestamp` and returns how many
+    /// were removed.
+    pub fn evict_older_than(&mut self, timestamp: u64) -> usize {
+        self.tree.retain_where(
+            &|span: &TimeSpan| span.0.is_some_and(|(_, max)| max < timestamp),
+            &|span: &TimeSpan| span.0.is_none_or(|(min, _)| min >= timestamp),
+            &|point| point.data.timestamp >= timestamp,
+        )
+    }
+
+    /// Points inside `region` recorded within `time`.
+    pub fn query_in_time(
+        &self,
+        region: impl Into<Rectangle>,
+        time: Range<u64>,
+    ) -> Vec<&Point2D<Timed<T>>> {
+        self.tree.query_where(
+            &region.into(),
+            |span| span.overlaps(&time),
+            |point| time.contains(&point.data.timestamp),
+        )
+    }
+
+    /// The oldest and newest timestamp stored, `None` for an empty tree.
+    pub fn time_span(&self) -> Option<(u64, u64)> {
+        self.tree.total().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_keeps_a_sliding_window() -> Result<(), Box<dyn std::error::Error>> {
+        let mut rng = StdRng::seed_from_u64(829);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = TemporalQuadTree::new(boundary);
+        let mut points = Vec::new();
+        for timestamp in 0..2000 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), ());
+            points.push((point, timestamp));
+            quadtree.insert(point, timestamp)?;
+        }
+        assert!(quadtree.insert(Point2D::new(-1.0, 0.0, ()), 0).is_err());
+        assert_eq!(quadtree.time_span(), Some((0, 1999)));
+
+        let region = Rectangle::new(10.0, 10.0, 50.0, 50.0);
+        for time in [0..100, 500..1500, 1990..5000, 3000..4000] {
+            let expected = points
+                .iter()
+                .filter(|(p, t)| region.contains(p.x, p.y) && time.contains(t))
+                .count();
+            assert_eq!(quadtree.query_in_time(region, time).len(), expected);
+        }
+
+        assert_eq!(quadtree.evict_older_than(1400), 1400);
+        assert_eq!(quadtree.count(), 600);
+        assert_eq!(quadtree.time_span(), Some((1400, 1999)));
+        assert!(quadtree.query_in_time(boundary, 0..1400).is_empty());
+        assert_eq!(quadtree.query_in_time(boundary, 0..2000).len(), 600);
+        assert_eq!(quadtree.evict_older_than(1400), 0);
+
+        assert_eq!(quadtree.evict_older_than(u64::MAX), 600);
+        assert_eq!(quadtree.time_span(), None);
+
+        Ok(())
+    }
+}
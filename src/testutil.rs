@@ -0,0 +1,198 @@
+//! Data generators for tests and benchmarks, enabled with the `testutil`
+//! feature.
+
+use rand::Rng;
+
+use crate::error::RejectReason;
+use crate::geometry::{Point2D, Rectangle};
+use crate::spatial_index::SpatialIndex;
+
+/// `n` points spread uniformly over `boundary`, each carrying its index.
+pub fn uniform_points(rng: &mut impl Rng, boundary: &Rectangle, n: usize) -> Vec<Point2D<usize>> {
+    (0..n)
+        .map(|i| Point2D {
+            x: rng.gen_range(boundary.x..=boundary.x + boundary.width),
+            y: rng.gen_range(boundary.y..=boundary.y + boundary.height),
+            data: i,
+        })
+        .collect()
+}
+
+/// `n` points around `clusters` random centers, each point at most `spread`
+/// (as a fraction of the boundary size) away from its center per axis.
+pub fn clustered_points(
+    rng: &mut impl Rng,
+    boundary: &Rectangle,
+    n: usize,
+    clusters: usize,
+    spread: f64,
+) -> Vec<Point2D<usize>> {
+    let centers = uniform_points(rng, boundary, clusters.max(1));
+    (0..n)
+        .map(|i| {
+            let center = &centers[i % centers.len()];
+            let dx = rng.gen_range(-spread..=spread) * boundary.width;
+            let dy = rng.gen_range(-spread..=spread) * boundary.height;
+            Point2D {
+                x: (center.x + dx).clamp(boundary.x, boundary.x + boundary.width),
+                y: (center.y + dy).clamp(boundary.y, boundary.y + boundary.height),
+                data: i,
+            }
+        })
+        .collect()
+}
+
+/// `n` query rectangles inside `boundary`, each covering `selectivity` (0..=1)
+/// of its area with the boundary's aspect ratio.
+pub fn query_regions(
+    rng: &mut impl Rng,
+    boundary: &Rectangle,
+    selectivity: f64,
+    n: usize,
+) -> Vec<Rectangle> {
+    let scale = selectivity.clamp(0.0, 1.0).sqrt();
+    let width = boundary.width * scale;
+    let height = boundary.height * scale;
+    // the last position a region fits at may round to before the boundary
+    let last_x = (boundary.x + boundary.width - width).max(boundary.x);
+    let last_y = (boundary.y + boundary.height - height).max(boundary.y);
+    (0..n)
+        .map(|_| {
+            Rectangle::new(
+                rng.gen_range(boundary.x..=last_x),
+                rng.gen_range(boundary.y..=last_y),
+                width,
+                height,
+            )
+        })
+        .collect()
+}
+
+/// One step of a random workload, see `random_operations`.
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Insert(Point2D<usize>),
+    Query(Rectangle),
+    Count,
+}
+
+/// The answer of an index to an `Operation`, comparable between
+/// implementations. Query results are payloads sorted ascending, since the
+/// implementations visit points in different orders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Inserted(Result<(), RejectReason>),
+    Found(Vec<usize>),
+    Counted(usize),
+}
+
+/// `n` random operations against `boundary`. Besides uniform points the
+/// inserts repeat earlier positions, hit the seams between quadrants and the
+/// boundary's edges, or lie outside the boundary, which is where the
+/// implementations' splitting rules differ. Queries may reach past the
+/// boundary.
+pub fn random_operations(rng: &mut impl Rng, boundary: &Rectangle, n: usize) -> Vec<Operation> {
+    let mut operations = Vec::with_capacity(n);
+    let mut inserted: Vec<(f64, f64)> = Vec::new();
+    for i in 0..n {
+        let operation = match rng.gen_range(0..100) {
+            0..=59 => {
+                let (x, y) = random_position(rng, boundary, &inserted);
+                inserted.push((x, y));
+                Operation::Insert(Point2D { x, y, data: i })
+            }
+            60..=94 => {
+                let selectivity = [0.0, 0.001, 0.01, 0.1, 1.0][rng.gen_range(0..5)];
+                let region = query_regions(rng, &boundary.scaled(1.2), selectivity, 1)[0];
+                Operation::Query(region)
+            }
+            _ => Operation::Count,
+        };
+        operations.push(operation);
+    }
+    operations
+}
+
+fn random_position(
+    rng: &mut impl Rng,
+    boundary: &Rectangle,
+    inserted: &[(f64, f64)],
+) -> (f64, f64) {
+    let (right, bottom) = (boundary.x + boundary.width, boundary.y + boundary.height);
+    let center = (boundary.x + boundary.width / 2.0, boundary.y + boundary.height / 2.0);
+    let uniform = uniform_points(rng, boundary, 1)[0].position();
+    match rng.gen_range(0..20) {
+        0..=2 if !inserted.is_empty() => inserted[rng.gen_range(0..inserted.len())],
+        3 => (center.0, uniform.1),
+        4 => (uniform.0, center.1),
+        5 => (right, uniform.1),
+        6 => (uniform.0, bottom),
+        7 => (right + 1.0, uniform.1),
+        _ => uniform,
+    }
+}
+
+/// Applies `operations` to `index` and records every answer.
+pub fn run_operations<I: SpatialIndex<usize>>(
+    index: &mut I,
+    operations: &[Operation],
+) -> Vec<Outcome> {
+    operations
+        .iter()
+        .map(|operation| match operation {
+            Operation::Insert(point) => {
+                Outcome::Inserted(index.insert(*point).map_err(|error| error.reason))
+            }
+            Operation::Query(region) => {
+                let mut found: Vec<usize> =
+                    index.query(*region).iter().map(|point| point.data).collect();
+                found.sort_unstable();
+                Outcome::Found(found)
+            }
+            Operation::Count => Outcome::Counted(index.count()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn it_generates_data_inside_the_boundary() {
+        let mut rng = StdRng::seed_from_u64(812);
+        let boundary = Rectangle::new(10.0, 20.0, 100.0, 50.0);
+
+        let points = uniform_points(&mut rng, &boundary, 100);
+        assert_eq!(points.len(), 100);
+        assert!(points.iter().all(|p| boundary.contains(p.x, p.y)));
+
+        let points = clustered_points(&mut rng, &boundary, 100, 3, 0.05);
+        assert_eq!(points.len(), 100);
+        assert!(points.iter().all(|p| boundary.contains(p.x, p.y)));
+
+        let regions = query_regions(&mut rng, &boundary, 0.01, 10);
+        for region in regions {
+            assert!(boundary.contains_rectangle(&region));
+            let area = region.width * region.height;
+            assert!((area - 0.01 * boundary.width * boundary.height).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn it_records_the_outcome_of_operations() {
+        let mut rng = StdRng::seed_from_u64(844);
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let operations = random_operations(&mut rng, &boundary, 200);
+        assert_eq!(operations.len(), 200);
+
+        let mut index = crate::VecIndex::new(boundary);
+        let outcomes = run_operations(&mut index, &operations);
+        assert_eq!(outcomes.len(), 200);
+        assert!(outcomes.contains(&Outcome::Inserted(Err(RejectReason::OutOfBounds))));
+        let inserted = outcomes.iter().filter(|outcome| **outcome == Outcome::Inserted(Ok(())));
+        assert_eq!(inserted.count(), index.count());
+    }
+}
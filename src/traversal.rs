@@ -0,0 +1,1271 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+use crate::geometry::{Point2D, Quadrant, QueryEdges, Rectangle};
+use crate::metric::{Euclidean, Metric};
+
+// node access shared by the pointer-based trees, so the region traversal is
+// written once for both of them
+pub(crate) trait TreeNode<T> {
+    fn boundary(&self) -> &Rectangle;
+    fn points(&self) -> &[Point2D<T>];
+    // points stored in the node and all nodes below it
+    fn count(&self) -> usize;
+    // pushes the existing children in ne, se, sw, nw order
+    fn push_children<'a>(&'a self, stack: &mut Vec<&'a Self>);
+    // where the node's children meet, only meaningful for nodes with children
+    fn split_point(&self) -> (f64, f64);
+    fn child(&self, quadrant: Quadrant) -> Option<&Self>;
+    // a rectangle around every point below the node, `None` if there are
+    // none. Nodes not tracking their points fall back to the boundary.
+    fn envelope(&self) -> Option<Rectangle> {
+        Some(*self.boundary())
+    }
+}
+
+// how a node's boundary relates to the shape a query is looking for
+pub(crate) enum Overlap {
+    Disjoint,
+    Partial,
+    // every point below the node matches without checking it
+    Contained,
+}
+
+// the engine behind the queries of both trees: walks the nodes depth-first
+// in ne, se, sw, nw order, skipping the ones `overlap` finds disjoint and
+// reporting the points `keep` accepts. Sub-trees `overlap` finds contained
+// are reported without further checks. Stops as soon as `f` breaks.
+// `overlap` is given the envelope of each node, which only needs to hold
+// the node's points, not its boundary.
+pub(crate) fn visit<'a, T, N>(
+    root: &'a N,
+    mut overlap: impl FnMut(&Rectangle) -> Overlap,
+    mut keep: impl FnMut(&Point2D<T>) -> bool,
+    mut f: impl FnMut(&'a Point2D<T>) -> ControlFlow<()>,
+) -> ControlFlow<()>
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let mut stack = vec![(root, false)];
+    let mut children = Vec::new();
+    while let Some((node, contained)) = stack.pop() {
+        let contained = contained
+            || match node.envelope().map(|envelope| overlap(&envelope)) {
+                None | Some(Overlap::Disjoint) => continue,
+                Some(Overlap::Partial) => false,
+                Some(Overlap::Contained) => true,
+            };
+        for point in node.points() {
+            if contained || keep(point) {
+                f(point)?;
+            }
+        }
+        // reversed, so children are visited in ne, se, sw, nw order
+        node.push_children(&mut children);
+        stack.extend(children.drain(..).rev().map(|child| (child, contained)));
+    }
+    ControlFlow::Continue(())
+}
+
+// calls `f` for every point inside `region`
+pub(crate) fn for_each_in_region<'a, T, N>(
+    root: &'a N,
+    region: &Rectangle,
+    edges: QueryEdges,
+    f: impl FnMut(&'a Point2D<T>) -> ControlFlow<()>,
+) -> ControlFlow<()>
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let overlap = |boundary: &Rectangle| {
+        if !region.intersects(boundary) {
+            Overlap::Disjoint
+        } else if edges == QueryEdges::Inclusive && region.contains_rectangle(boundary) {
+            Overlap::Contained
+        } else {
+            Overlap::Partial
+        }
+    };
+    // nothing lies beyond the tree's far edges to claim the points on them, so
+    // half-open regions reaching those edges own them, as the last Grid cells do
+    let boundary = root.boundary();
+    let closed_right = region.x + region.width >= boundary.x + boundary.width;
+    let closed_bottom = region.y + region.height >= boundary.y + boundary.height;
+    let contains = |point: &Point2D<T>| match edges {
+        QueryEdges::Inclusive => region.contains(point.x, point.y),
+        QueryEdges::HalfOpen => {
+            region.contains(point.x, point.y)
+                && (closed_right || point.x < region.x + region.width)
+                && (closed_bottom || point.y < region.y + region.height)
+        }
+    };
+    visit(root, overlap, contains, f)
+}
+
+// points inside `include` but not inside `exclude`, skipping nodes whose
+// points all lie inside `exclude`
+pub(crate) fn query_difference<'a, T, N>(
+    root: &'a N,
+    include: &Rectangle,
+    exclude: &Rectangle,
+) -> Vec<&'a Point2D<T>>
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let overlap = |boundary: &Rectangle| {
+        if !include.intersects(boundary) || exclude.contains_rectangle(boundary) {
+            Overlap::Disjoint
+        } else if include.contains_rectangle(boundary) && !exclude.intersects(boundary) {
+            Overlap::Contained
+        } else {
+            Overlap::Partial
+        }
+    };
+    let keep = |point: &Point2D<T>| {
+        include.contains(point.x, point.y) && !exclude.contains(point.x, point.y)
+    };
+    let mut result = Vec::new();
+    let _ = visit(root, overlap, keep, |point| {
+        result.push(point);
+        ControlFlow::Continue(())
+    });
+    result
+}
+
+// calls `f` for every stored point
+pub(crate) fn for_each_point<'a, T, N>(root: &'a N, mut f: impl FnMut(&'a Point2D<T>))
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let _ = visit(root, |_| Overlap::Contained, |_| true, |point| {
+        f(point);
+        ControlFlow::Continue(())
+    });
+}
+
+// calls `f` for every point below `root` whose squared distance to (x, y)
+// is at most `max_d`
+fn for_each_near<'a, T, N>(
+    root: &'a N,
+    x: f64,
+    y: f64,
+    max_d: f64,
+    mut f: impl FnMut(&'a Point2D<T>),
+)
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let overlap = |boundary: &Rectangle| {
+        if boundary.distance_squared(x, y) > max_d {
+            Overlap::Disjoint
+        } else if boundary.max_distance_squared(x, y) <= max_d {
+            Overlap::Contained
+        } else {
+            Overlap::Partial
+        }
+    };
+    let keep = |point: &Point2D<T>| point.distance_squared_to_position(x, y) <= max_d;
+    let _ = visit(root, overlap, keep, |point| {
+        f(point);
+        ControlFlow::Continue(())
+    });
+}
+
+// every pair of a point below `a` and a point below `b` at most `epsilon`
+// apart, descending both trees together. A pair of nodes covers the points
+// of `a`'s node against all of `b`'s sub-tree, the points of `b`'s node
+// against the children of `a` and hands the pairs of their children on, so
+// each pair of points is found once. Nodes farther apart are skipped.
+pub(crate) fn intersection<'a, T, U, N, M>(
+    a: &'a N,
+    b: &'a M,
+    epsilon: f64,
+) -> Vec<(&'a Point2D<T>, &'a Point2D<U>)>
+where
+    T: 'a,
+    U: 'a,
+    N: TreeNode<T>,
+    M: TreeNode<U>,
+{
+    let mut result = Vec::new();
+    // negative or NaN tolerances match nothing
+    if epsilon.is_nan() || epsilon < 0.0 {
+        return result;
+    }
+    let max_d = epsilon * epsilon;
+    let mut stack = vec![(a, b)];
+    let (mut children_a, mut children_b) = (Vec::new(), Vec::new());
+    while let Some((a, b)) = stack.pop() {
+        let (Some(envelope_a), Some(envelope_b)) = (a.envelope(), b.envelope()) else {
+            continue;
+        };
+        if envelope_a.distance_squared_to_rectangle(&envelope_b) > max_d {
+            continue;
+        }
+        for point in a.points() {
+            for_each_near(b, point.x, point.y, max_d, |other| result.push((point, other)));
+        }
+        a.push_children(&mut children_a);
+        for other in b.points() {
+            for child in children_a.iter() {
+                for_each_near(*child, other.x, other.y, max_d, |point| result.push((point, other)));
+            }
+        }
+        b.push_children(&mut children_b);
+        for child in children_a.drain(..) {
+            stack.extend(children_b.iter().map(|other| (child, *other)));
+        }
+        children_b.clear();
+    }
+    result
+}
+
+// points at most `radius` away from (x, y) under `metric` with their
+// distance, nearest first
+pub(crate) fn within_radius<'a, T, N, M>(
+    root: &'a N,
+    x: f64,
+    y: f64,
+    radius: f64,
+    metric: &M,
+) -> Vec<(&'a Point2D<T>, f64)>
+where
+    T: 'a,
+    N: TreeNode<T>,
+    M: Metric + ?Sized,
+{
+    let overlap = |boundary: &Rectangle| {
+        if radius < 0.0 || metric.min_distance(boundary, x, y) > radius {
+            Overlap::Disjoint
+        } else if metric.max_distance(boundary, x, y) <= radius {
+            Overlap::Contained
+        } else {
+            Overlap::Partial
+        }
+    };
+    let keep = |point: &Point2D<T>| metric.distance(x, y, point.x, point.y) <= radius;
+    let mut result = Vec::new();
+    let _ = visit(root, overlap, keep, |point| {
+        result.push((point, metric.distance(x, y, point.x, point.y)));
+        ControlFlow::Continue(())
+    });
+    result.sort_by(|a, b| a.1.total_cmp(&b.1));
+    result
+}
+
+// whether no point lies closer than `clearance` to (x, y)
+fn is_clear<T, N: TreeNode<T>>(root: &N, x: f64, y: f64, clearance: f64) -> bool {
+    let overlap = |boundary: &Rectangle| {
+        if Euclidean.min_distance(boundary, x, y) >= clearance {
+            Overlap::Disjoint
+        } else {
+            Overlap::Partial
+        }
+    };
+    let close = |point: &Point2D<T>| Euclidean.distance(x, y, point.x, point.y) < clearance;
+    visit(root, overlap, close, |_| ControlFlow::Break(())).is_continue()
+}
+
+// a random location no point is closer to than `clearance`, `None` if none
+// was found within a fixed number of attempts. Empty nodes and the missing
+// children of nodes are tried first, picked by area, as little of their
+// area lies near points. Then the whole boundary is tried, which also finds
+// the space between points of occupied leaves.
+pub(crate) fn sample_empty_location<T, N: TreeNode<T>>(
+    root: &N,
+    rng: &mut impl Rng,
+    clearance: f64,
+) -> Option<(f64, f64)> {
+    const ATTEMPTS: usize = 64;
+    fn random_in(rng: &mut impl Rng, region: &Rectangle) -> (f64, f64) {
+        let x = rng.gen_range(region.x..=region.x + region.width);
+        let y = rng.gen_range(region.y..=region.y + region.height);
+        (x, y)
+    }
+
+    let mut empty = Vec::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let boundary = node.boundary();
+        if node.count() == 0 {
+            empty.push(*boundary);
+            continue;
+        }
+        let before = stack.len();
+        node.push_children(&mut stack);
+        if stack.len() > before {
+            empty.extend(
+                Quadrant::ALL
+                    .into_iter()
+                    .filter(|quadrant| node.child(*quadrant).is_none())
+                    .map(|quadrant| boundary.quadrant_at(quadrant, node.split_point())),
+            );
+        }
+    }
+    if let Ok(by_area) = WeightedIndex::new(empty.iter().map(Rectangle::area)) {
+        for _ in 0..ATTEMPTS {
+            let region = &empty[by_area.sample(rng)];
+            let (x, y) = random_in(rng, region);
+            if is_clear(root, x, y, clearance) {
+                return Some((x, y));
+            }
+        }
+    }
+    (0..ATTEMPTS)
+        .map(|_| random_in(rng, root.boundary()))
+        .find(|(x, y)| is_clear(root, *x, *y, clearance))
+}
+
+// the `k` points nearest to (x, y) under `metric` with their distance,
+// nearest first. Ties keep the traversal order.
+pub(crate) fn k_nearest<'a, T, N, M>(
+    root: &'a N,
+    x: f64,
+    y: f64,
+    k: usize,
+    metric: &M,
+) -> Vec<(&'a Point2D<T>, f64)>
+where
+    T: 'a,
+    N: TreeNode<T>,
+    M: Metric + ?Sized,
+{
+    // best holds up to k (point, distance) pairs sorted by distance, nodes
+    // farther than the current k-th point are skipped. Children are visited
+    // nearest first, so the bound shrinks quickly.
+    let mut best: Vec<(&'a Point2D<T>, f64)> = Vec::with_capacity(k.min(64) + 1);
+    let mut stack = if k > 0 { vec![root] } else { Vec::new() };
+    while let Some(node) = stack.pop() {
+        if best.len() == k && metric.min_distance(node.boundary(), x, y) > best[k - 1].1 {
+            continue;
+        }
+        for point in node.points() {
+            let d = metric.distance(x, y, point.x, point.y);
+            if best.len() < k || d < best[k - 1].1 {
+                let index = best.partition_point(|(_, best_d)| *best_d <= d);
+                best.insert(index, (point, d));
+                best.truncate(k);
+            }
+        }
+        let first = stack.len();
+        node.push_children(&mut stack);
+        // farthest first on the stack, so the nearest child is popped next.
+        // Reversed before the stable sort, so ties keep ne, se, sw, nw order.
+        stack[first..].reverse();
+        stack[first..].sort_by(|a, b| {
+            let a = metric.min_distance(a.boundary(), x, y);
+            let b = metric.min_distance(b.boundary(), x, y);
+            b.total_cmp(&a)
+        });
+    }
+    best
+}
+
+// a node or point waiting in the queue of `nearest`
+enum Pending<'a, T, N> {
+    Node(&'a N),
+    Point(&'a Point2D<T>),
+}
+
+// queue entries ordered so the max-heap pops the smallest distance first,
+// ties in the order they were queued
+struct Queued<'a, T, N> {
+    distance: f64,
+    order: usize,
+    pending: Pending<'a, T, N>,
+}
+
+impl<T, N> PartialEq for Queued<'_, T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, N> Eq for Queued<'_, T, N> {}
+
+impl<T, N> PartialOrd for Queued<'_, T, N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, N> Ord for Queued<'_, T, N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+// every point with its distance to (x, y) under `metric`, nearest first.
+// A best-first search: nodes and points share one queue keyed by their
+// (minimum) distance, so a point is yielded once nothing queued can be
+// nearer, and only the nodes needed so far are expanded.
+pub(crate) fn nearest<'a, T, N, M>(
+    root: &'a N,
+    x: f64,
+    y: f64,
+    metric: &'a M,
+) -> impl Iterator<Item = (&'a Point2D<T>, f64)>
+where
+    T: 'a,
+    N: TreeNode<T>,
+    M: Metric + ?Sized,
+{
+    let mut queue = BinaryHeap::from([Queued {
+        distance: metric.min_distance(root.boundary(), x, y),
+        order: 0,
+        pending: Pending::Node(root),
+    }]);
+    let mut order = 1;
+    let mut children = Vec::new();
+    std::iter::from_fn(move || loop {
+        let queued = queue.pop()?;
+        let node = match queued.pending {
+            Pending::Point(point) => return Some((point, queued.distance)),
+            Pending::Node(node) => node,
+        };
+        let points = node.points().iter().map(|point| {
+            (metric.distance(x, y, point.x, point.y), Pending::Point(point))
+        });
+        node.push_children(&mut children);
+        let children = children.drain(..).map(|child| {
+            (metric.min_distance(child.boundary(), x, y), Pending::Node(child))
+        });
+        for (distance, pending) in points.chain(children) {
+            queue.push(Queued { distance, order, pending });
+            order += 1;
+        }
+    })
+}
+
+// nodes up to `max_depth` report their points inside `region`, each node at
+// `max_depth` reports only the first point of its sub-tree inside it
+pub(crate) fn query_at_depth<'a, T, N>(
+    root: &'a N,
+    region: &Rectangle,
+    max_depth: usize,
+) -> Vec<&'a Point2D<T>>
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let mut result = Vec::new();
+    let mut stack = vec![(root, 0)];
+    let mut children = Vec::new();
+    while let Some((node, depth)) = stack.pop() {
+        if !region.intersects(node.boundary()) {
+            continue;
+        }
+        if depth == max_depth {
+            let _ = for_each_in_region(node, region, QueryEdges::Inclusive, |point| {
+                result.push(point);
+                ControlFlow::Break(())
+            });
+            continue;
+        }
+        for point in node.points() {
+            if region.contains(point.x, point.y) {
+                result.push(point);
+            }
+        }
+        node.push_children(&mut children);
+        stack.extend(children.drain(..).rev().map(|child| (child, depth + 1)));
+    }
+    result
+}
+
+// estimated number of points inside `region` and a bound on the error of the
+// estimate. Nodes inside the region count exactly. Partially overlapping
+// nodes are estimated from the share of their area inside the region while
+// their worst case error still fits into `max_error`, otherwise their points
+// are checked and their children visited.
+pub(crate) fn approx_count<T, N>(root: &N, region: &Rectangle, max_error: f64) -> (f64, f64)
+where
+    N: TreeNode<T>,
+{
+    let mut estimate = 0.0;
+    let mut error = 0.0;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let boundary = node.boundary();
+        if !region.intersects(boundary) {
+            continue;
+        }
+        if region.contains_rectangle(boundary) {
+            estimate += node.count() as f64;
+            continue;
+        }
+        if boundary.area() > 0.0 {
+            let count = node.count() as f64;
+            let share = boundary.clamped_to(region).area() / boundary.area();
+            // anywhere between none and all of the points may be inside
+            let worst = count * share.max(1.0 - share);
+            if error + worst <= max_error {
+                estimate += count * share;
+                error += worst;
+                continue;
+            }
+        }
+        estimate += node.points().iter().filter(|p| region.contains(p.x, p.y)).count() as f64;
+        node.push_children(&mut stack);
+    }
+    (estimate, error)
+}
+
+// centers of the non-empty `cell` x `cell` squares tiling the root boundary
+// from its top left corner, with the number of points in each, row by row.
+// Cells are half-open except the last ones along each axis, which also own
+// the boundary's far edges. Nodes inside one cell are counted as a whole.
+pub(crate) fn snap_to_grid<T, N>(root: &N, cell: f64) -> Vec<(Point2D<()>, usize)>
+where
+    N: TreeNode<T>,
+{
+    assert!(cell > 0.0 && cell.is_finite(), "invalid cell size {}", cell);
+    let origin = root.boundary();
+    let last = |extent: f64| ((extent / cell).ceil() as u64).max(1) - 1;
+    let (last_col, last_row) = (last(origin.width), last(origin.height));
+    let index = |value: f64, start: f64, last: u64| {
+        (((value - start) / cell).floor().max(0.0) as u64).min(last)
+    };
+    // (row, col), so the map is ordered row by row
+    let cell_of = |x: f64, y: f64| (index(y, origin.y, last_row), index(x, origin.x, last_col));
+
+    let mut counts = std::collections::BTreeMap::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let boundary = node.boundary();
+        let first = cell_of(boundary.x, boundary.y);
+        if first == cell_of(boundary.x + boundary.width, boundary.y + boundary.height) {
+            if node.count() > 0 {
+                *counts.entry(first).or_insert(0) += node.count();
+            }
+            continue;
+        }
+        for point in node.points() {
+            *counts.entry(cell_of(point.x, point.y)).or_insert(0) += 1;
+        }
+        node.push_children(&mut stack);
+    }
+    counts
+        .into_iter()
+        .map(|((row, col), count)| {
+            let x = origin.x + (col as f64 + 0.5) * cell;
+            let y = origin.y + (row as f64 + 0.5) * cell;
+            (Point2D::new(x, y, ()), count)
+        })
+        .collect()
+}
+
+/// A node as seen by `nodes()` and `leaves()`: its boundary, the points
+/// stored directly in it and its depth, the root being at depth 0.
+#[derive(Debug)]
+pub struct NodeView<'a, T> {
+    boundary: &'a Rectangle,
+    points: &'a [Point2D<T>],
+    depth: usize,
+    leaf: bool,
+}
+
+impl<T> Clone for NodeView<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeView<'_, T> {}
+
+impl<'a, T> NodeView<'a, T> {
+    pub fn boundary(&self) -> &'a Rectangle {
+        self.boundary
+    }
+
+    /// Points stored in this node, not the ones below it.
+    pub fn points(&self) -> &'a [Point2D<T>] {
+        self.points
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Whether the node has no children.
+    pub fn is_leaf(&self) -> bool {
+        self.leaf
+    }
+}
+
+// every node depth-first in ne, se, sw, nw order, parents before children
+pub(crate) fn nodes<'a, T, N>(root: &'a N) -> impl Iterator<Item = NodeView<'a, T>>
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let mut stack = vec![(root, 0)];
+    let mut children = Vec::new();
+    std::iter::from_fn(move || {
+        let (node, depth) = stack.pop()?;
+        node.push_children(&mut children);
+        let leaf = children.is_empty();
+        // reversed, so children are visited in ne, se, sw, nw order
+        stack.extend(children.drain(..).rev().map(|child| (child, depth + 1)));
+        Some(NodeView {
+            boundary: node.boundary(),
+            points: node.points(),
+            depth,
+            leaf,
+        })
+    })
+}
+
+/// The quadrants leading from the root to a node, e.g. as a spatial hashing
+/// key. Created by `locate` and resolved with `node_at`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NodePath {
+    quadrants: Vec<Quadrant>,
+}
+
+impl NodePath {
+    pub fn new(quadrants: Vec<Quadrant>) -> Self {
+        NodePath { quadrants }
+    }
+
+    pub fn quadrants(&self) -> &[Quadrant] {
+        &self.quadrants
+    }
+
+    /// Depth of the node the path leads to, the root being at depth 0.
+    pub fn depth(&self) -> usize {
+        self.quadrants.len()
+    }
+}
+
+/// Writes the path as e.g. "root/NE/SW".
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "root")?;
+        self.quadrants.iter().try_for_each(|quadrant| write!(f, "/{:?}", quadrant))
+    }
+}
+
+// the path to the deepest existing node whose boundary holds (x, y), which
+// is where insert puts a point there unless it splits the node first
+pub(crate) fn locate<T, N: TreeNode<T>>(root: &N, x: f64, y: f64) -> Option<NodePath> {
+    if !root.boundary().contains(x, y) {
+        return None;
+    }
+    let mut quadrants = Vec::new();
+    let mut node = root;
+    loop {
+        let quadrant = Quadrant::of(node.split_point(), x, y);
+        match node.child(quadrant) {
+            Some(child) => {
+                quadrants.push(quadrant);
+                node = child;
+            }
+            None => return Some(NodePath { quadrants }),
+        }
+    }
+}
+
+pub(crate) fn node_at<'a, T, N: TreeNode<T>>(
+    root: &'a N,
+    path: &NodePath,
+) -> Option<NodeView<'a, T>> {
+    let mut node = root;
+    for quadrant in path.quadrants() {
+        node = node.child(*quadrant)?;
+    }
+    Some(NodeView {
+        boundary: node.boundary(),
+        points: node.points(),
+        depth: path.depth(),
+        leaf: Quadrant::ALL.iter().all(|quadrant| node.child(*quadrant).is_none()),
+    })
+}
+
+// the tree as a Graphviz digraph. Node ids are built from the path to the
+// node, e.g. `n_se_nw`, so a node keeps its id while the tree changes around
+// it and graphs of two versions of a tree can be diffed.
+pub(crate) fn to_dot<T, N: TreeNode<T>>(root: &N) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::from("digraph quadtree {\n    node [shape=box];\n");
+    let mut stack = vec![(root, String::from("n"))];
+    while let Some((node, id)) = stack.pop() {
+        let boundary = node.boundary();
+        // writing to a String can't fail
+        let _ = writeln!(
+            out,
+            "    {} [label=\"({}, {}, {}x{})\\n{} points, {} in total\"];",
+            id,
+            boundary.x,
+            boundary.y,
+            boundary.width,
+            boundary.height,
+            node.points().len(),
+            node.count(),
+        );
+        let children: Vec<_> = ["ne", "se", "sw", "nw"]
+            .into_iter()
+            .zip(Quadrant::ALL)
+            .filter_map(|(label, quadrant)| Some((label, node.child(quadrant)?)))
+            .collect();
+        for (label, _) in children.iter() {
+            let _ = writeln!(out, "    {} -> {}_{} [label={}];", id, id, label, label);
+        }
+        // reversed, so children are written in ne, se, sw, nw order
+        for (label, child) in children.into_iter().rev() {
+            stack.push((child, format!("{}_{}", id, label)));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// How much work a query did, see `query_with_stats`. Many visited nodes or
+/// tested points per match hint at a degraded tree shape.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    /// Nodes checked against the region. Sub-trees without points are
+    /// skipped unchecked where the tree knows about them.
+    pub nodes_visited: usize,
+    /// Points checked against the region, points of nodes fully inside it
+    /// match without a check.
+    pub points_tested: usize,
+    pub matches: usize,
+}
+
+// the points inside `region` and what finding them took
+pub(crate) fn query_with_stats<'a, T, N>(
+    root: &'a N,
+    region: &Rectangle,
+) -> (Vec<&'a Point2D<T>>, QueryStats)
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let (mut nodes_visited, mut points_tested) = (0, 0);
+    let overlap = |boundary: &Rectangle| {
+        nodes_visited += 1;
+        if !region.intersects(boundary) {
+            Overlap::Disjoint
+        } else if region.contains_rectangle(boundary) {
+            Overlap::Contained
+        } else {
+            Overlap::Partial
+        }
+    };
+    let keep = |point: &Point2D<T>| {
+        points_tested += 1;
+        region.contains(point.x, point.y)
+    };
+    let mut result = Vec::new();
+    let _ = visit(root, overlap, keep, |point| {
+        result.push(point);
+        ControlFlow::Continue(())
+    });
+    let stats = QueryStats {
+        nodes_visited,
+        points_tested,
+        matches: result.len(),
+    };
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        nodes_visited = stats.nodes_visited,
+        points_tested = stats.points_tested,
+        matches = stats.matches,
+        "query"
+    );
+    (result, stats)
+}
+
+/// Limits how much work a budgeted query does before it returns, either as
+/// a number of visited nodes, a deadline or both. A budget can be reused for
+/// several queries, visits keep adding up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraversalBudget {
+    max_nodes: Option<usize>,
+    deadline: Option<Instant>,
+    visited: usize,
+}
+
+impl TraversalBudget {
+    /// Stops after visiting `max_nodes` nodes.
+    pub fn nodes(max_nodes: usize) -> Self {
+        TraversalBudget {
+            max_nodes: Some(max_nodes),
+            ..Default::default()
+        }
+    }
+
+    /// Stops once `deadline` has passed.
+    pub fn until(deadline: Instant) -> Self {
+        TraversalBudget {
+            deadline: Some(deadline),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        TraversalBudget {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Nodes visited so far.
+    pub fn visited(&self) -> usize {
+        self.visited
+    }
+
+    fn exhausted(&self) -> bool {
+        self.max_nodes.is_some_and(|max| self.visited >= max)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Where a budgeted query stopped, pass it to `resume_query` to continue.
+/// Borrows the tree, so it can't change in between.
+#[derive(Debug)]
+pub struct QueryResume<'a, N> {
+    region: Rectangle,
+    stack: Vec<&'a N>,
+}
+
+impl<'a, N> QueryResume<'a, N> {
+    pub(crate) fn new(root: &'a N, region: Rectangle) -> Self {
+        QueryResume {
+            region,
+            stack: vec![root],
+        }
+    }
+}
+
+// like `for_each_in_region`, but every visited node counts against `budget`
+// and the remaining nodes are handed back when it runs out. Nodes are
+// processed as a whole, so results are never split within a node.
+pub(crate) fn query_with_budget<'a, T, N>(
+    resume: QueryResume<'a, N>,
+    budget: &mut TraversalBudget,
+) -> (Vec<&'a Point2D<T>>, Option<QueryResume<'a, N>>)
+where
+    T: 'a,
+    N: TreeNode<T>,
+{
+    let QueryResume { region, mut stack } = resume;
+    let mut result = Vec::new();
+    while let Some(node) = stack.pop() {
+        if budget.exhausted() {
+            stack.push(node);
+            return (result, Some(QueryResume { region, stack }));
+        }
+        budget.visited += 1;
+        if !node.envelope().is_some_and(|envelope| region.intersects(&envelope)) {
+            continue;
+        }
+        for point in node.points() {
+            if region.contains(point.x, point.y) {
+                result.push(point);
+            }
+        }
+        let first = stack.len();
+        node.push_children(&mut stack);
+        stack[first..].reverse();
+    }
+    (result, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::metric::{Euclidean, Manhattan};
+    use crate::{QuadTree, SparseQuadTree};
+
+    use super::*;
+
+    #[test]
+    fn it_visits_both_trees_in_the_same_order() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(843);
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+            sparse.insert(Point2D { x, y, data: i })?;
+        }
+
+        let regions = [Rectangle::new(20.0, 30.0, 40.0, 25.0), Rectangle::new(0.0, 0.0, 5.0, 5.0)];
+        // regions can be passed by reference
+        for region in &regions {
+            let mut expected: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+            let mut found: Vec<usize> = sparse.query(region).iter().map(|p| p.data).collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+
+        let region = regions[0];
+        let ids = |points: Vec<(&Point2D<usize>, f64)>| -> Vec<usize> {
+            points.iter().map(|(p, _)| p.data).collect()
+        };
+        let near = quadtree.query_radius(40.0, 40.0, 15.0);
+        assert_eq!(ids(sparse.query_radius(40.0, 40.0, 15.0)), ids(near));
+        assert_eq!(ids(sparse.k_nearest(40.0, 40.0, 10)), ids(quadtree.k_nearest(40.0, 40.0, 10)));
+        assert_eq!(quadtree.bounds(), sparse.bounds());
+
+        let mut first = Vec::new();
+        let flow = for_each_in_region(&sparse, &region, QueryEdges::Inclusive, |point| {
+            first.push(point.data);
+            if first.len() == 3 {
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        });
+        assert!(flow.is_break());
+        let (limited, more) = sparse.query_limited(region, 3);
+        assert!(more);
+        assert_eq!(limited.iter().map(|p| p.data).collect::<Vec<_>>(), first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_resumes_budgeted_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(848);
+        for i in 0..1000 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+        }
+        let region = Rectangle::new(10.0, 10.0, 60.0, 60.0);
+        let expected: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+
+        let mut budget = TraversalBudget::nodes(10);
+        let (found, mut resume) = quadtree.query_with_budget(region, &mut budget);
+        assert_eq!(budget.visited(), 10);
+        let mut found: Vec<usize> = found.iter().map(|p| p.data).collect();
+        let mut rounds = 1;
+        while let Some(token) = resume {
+            let mut budget = TraversalBudget::nodes(10);
+            let (more, next) = quadtree.resume_query(token, &mut budget);
+            found.extend(more.iter().map(|p| p.data));
+            resume = next;
+            rounds += 1;
+        }
+        assert!(rounds > 2);
+        assert_eq!(found, expected);
+
+        // a deadline in the past stops before the first node
+        let mut budget = TraversalBudget::until(Instant::now());
+        let (found, resume) = quadtree.query_with_budget(region, &mut budget);
+        assert!(found.is_empty() && resume.is_some());
+        let mut unlimited = TraversalBudget::default();
+        let (found, resume) = quadtree.resume_query(resume.unwrap(), &mut unlimited);
+        assert!(resume.is_none());
+        assert_eq!(found.len(), expected.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_iterates_over_nodes_and_leaves() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(859);
+        for i in 0..500 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: i })?;
+            sparse.insert(Point2D { x, y, data: i })?;
+        }
+
+        let root = quadtree.nodes().next().ok_or("no root")?;
+        assert_eq!(root.boundary(), &boundary);
+        assert_eq!(root.depth(), 0);
+        assert!(!root.is_leaf());
+        let stored: usize = quadtree.nodes().map(|node| node.points().len()).sum();
+        assert_eq!(stored, 500);
+        let deepest = quadtree.nodes().map(|node| node.depth()).max();
+        assert_eq!(deepest, Some(quadtree.depth()));
+
+        for node in quadtree.leaves() {
+            assert!(node.is_leaf());
+            assert!(node.points().iter().all(|p| node.boundary().contains(p.x, p.y)));
+        }
+        let sparse_points: usize = sparse.nodes().map(|node| node.points().len()).sum();
+        assert_eq!(sparse_points, 500);
+        assert!(sparse.leaves().all(|node| node.is_leaf()));
+        assert_eq!(sparse.nodes().map(|node| node.depth()).max(), Some(sparse.depth()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_locates_the_node_of_a_position() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(865);
+        let mut points = Vec::new();
+        for i in 0..500 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            quadtree.insert(point)?;
+            sparse.insert(point)?;
+            points.push(point);
+        }
+
+        for point in points.iter() {
+            let path = sparse.locate(point.x, point.y).ok_or("not located")?;
+            let node = sparse.node_at(&path).ok_or("no node at path")?;
+            assert!(node.is_leaf());
+            assert_eq!(node.depth(), path.depth());
+            assert!(node.points().iter().any(|p| p.data == point.data));
+
+            let path = quadtree.locate(point.x, point.y).ok_or("not located")?;
+            let node = quadtree.node_at(&path).ok_or("no node at path")?;
+            assert!(node.is_leaf());
+            assert!(node.points().iter().any(|p| p.data == point.data));
+        }
+
+        let root = quadtree.node_at(&NodePath::default()).ok_or("no root")?;
+        assert_eq!(root.boundary(), &boundary);
+        assert!(quadtree.locate(150.0, 0.0).is_none());
+        let too_deep = NodePath::new(vec![Quadrant::NE; 40]);
+        assert!(quadtree.node_at(&too_deep).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_yields_points_by_increasing_distance() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(870);
+        let mut points = Vec::new();
+        for i in 0..1000 {
+            let point = Point2D::new(rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0), i);
+            quadtree.insert(point)?;
+            sparse.insert(point)?;
+            points.push(point);
+        }
+
+        let (x, y) = (30.0, 70.0);
+        let mut expected: Vec<f64> = points
+            .iter()
+            .map(|p| Euclidean.distance(x, y, p.x, p.y))
+            .collect();
+        expected.sort_by(f64::total_cmp);
+        let all: Vec<f64> = quadtree.nearest_iter(x, y).map(|(_, d)| d).collect();
+        assert_eq!(all, expected);
+        let all: Vec<f64> = sparse.nearest_iter(x, y).map(|(_, d)| d).collect();
+        assert_eq!(all, expected);
+
+        // stop at the first point matching a predicate
+        let found = quadtree.nearest_iter(x, y).find(|(p, _)| p.data % 7 == 0);
+        let nearest = points
+            .iter()
+            .filter(|p| p.data % 7 == 0)
+            .map(|p| Euclidean.distance(x, y, p.x, p.y))
+            .min_by(f64::total_cmp);
+        assert_eq!(found.map(|(_, d)| d), nearest);
+
+        let k_nearest: Vec<f64> = quadtree.k_nearest(x, y, 10).iter().map(|(_, d)| *d).collect();
+        let manhattan: Vec<f64> =
+            quadtree.nearest_iter_by(x, y, &Manhattan).take(10).map(|(_, d)| d).collect();
+        let expected: Vec<f64> =
+            quadtree.k_nearest_by(x, y, 10, &Manhattan).iter().map(|(_, d)| *d).collect();
+        assert_eq!(manhattan, expected);
+        assert_eq!(k_nearest, all[..10]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_renders_both_trees_as_dot_graphs() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        for (x, y) in [(10.0, 10.0), (20.0, 10.0), (30.0, 10.0), (40.0, 10.0), (60.0, 60.0)] {
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+            sparse.insert(Point2D { x, y, data: 0 })?;
+        }
+
+        let expected = "\
+digraph quadtree {
+    node [shape=box];
+    n [label=\"(0, 0, 100x100)\\n0 points, 5 in total\"];
+    n -> n_se [label=se];
+    n -> n_nw [label=nw];
+    n_se [label=\"(50, 50, 50x50)\\n1 points, 1 in total\"];
+    n_nw [label=\"(0, 0, 50x50)\\n4 points, 4 in total\"];
+}
+";
+        assert_eq!(sparse.to_dot(), expected);
+        let dot = quadtree.to_dot();
+        assert_eq!(dot.matches(" -> ").count(), 4);
+        assert!(dot.contains("    n -> n_sw [label=sw];\n"));
+
+        // ids stay put when the tree changes elsewhere
+        let mut rng = StdRng::seed_from_u64(873);
+        for _ in 0..100 {
+            let (x, y) = (rng.gen_range(0.0..50.0), rng.gen_range(0.0..50.0));
+            sparse.insert(Point2D { x, y, data: 1 })?;
+        }
+        let dot = sparse.to_dot();
+        assert!(dot.contains("    n_se [label=\"(50, 50, 50x50)\\n1 points, 1 in total\"];\n"));
+        assert_eq!(dot.matches(" [label=\"").count(), sparse.nodes().count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_snaps_points_to_grid_cells() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(-50.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(876);
+        let mut points = vec![(50.0, 100.0), (-50.0, 0.0), (0.0, 30.0)];
+        points.extend((0..2000).map(|_| (rng.gen_range(-50.0..50.0), rng.gen_range(0.0..100.0))));
+        for (i, (x, y)) in points.iter().copied().enumerate() {
+            quadtree.insert(Point2D { x, y, data: i })?;
+            sparse.insert(Point2D { x, y, data: i })?;
+        }
+
+        // 30 doesn't divide 100, the last cells reach past the boundary
+        for cell in [30.0f64, 12.5, 100.0, 1000.0] {
+            let last = (100.0 / cell).ceil() as u64 - 1;
+            let index = |offset: f64| ((offset / cell).floor() as u64).min(last);
+            let mut expected = std::collections::BTreeMap::new();
+            for (x, y) in points.iter() {
+                *expected.entry((index(*y), index(x + 50.0))).or_insert(0) += 1;
+            }
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|((row, col), count)| {
+                    ((-50.0 + (col as f64 + 0.5) * cell, (row as f64 + 0.5) * cell), count)
+                })
+                .collect();
+            for snapped in [quadtree.snap_to_grid(cell), sparse.snap_to_grid(cell)] {
+                let snapped = snapped.iter().map(|(p, count)| ((p.x, p.y), *count));
+                assert_eq!(snapped.collect::<Vec<_>>(), expected);
+            }
+        }
+        let [(center, count)] = sparse.snap_to_grid(1000.0)[..] else {
+            return Err("not a single cell".into());
+        };
+        assert_eq!((center.x, center.y, count), (450.0, 500.0, 2003));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_samples_locations_clear_of_points() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        let mut rng = StdRng::seed_from_u64(878);
+        assert!(sparse.sample_empty_location(&mut rng, 1.0).is_some());
+        let mut points = Vec::new();
+        for i in 0..3000 {
+            // dense on the west half, a few points on the east half
+            let west = if i % 10 == 0 { 50.0 } else { 0.0 };
+            let (x, y) = (west + rng.gen_range(0.0..50.0), rng.gen_range(0.0..100.0));
+            points.push((x, y));
+            quadtree.insert(Point2D { x, y, data: i })?;
+            sparse.insert(Point2D { x, y, data: i })?;
+        }
+
+        let clearance = 2.0;
+        for _ in 0..50 {
+            for location in [
+                quadtree.sample_empty_location(&mut rng, clearance),
+                sparse.sample_empty_location(&mut rng, clearance),
+            ] {
+                let (x, y) = location.ok_or("no empty location")?;
+                assert!(boundary.contains(x, y));
+                let nearest = points
+                    .iter()
+                    .map(|(px, py)| ((px - x).powi(2) + (py - y).powi(2)).sqrt())
+                    .fold(f64::INFINITY, f64::min);
+                assert!(nearest >= clearance);
+            }
+        }
+        assert_eq!(quadtree.sample_empty_location(&mut rng, 200.0), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_counts_the_work_a_query_does() -> Result<(), Box<dyn std::error::Error>> {
+        let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+        let mut rng = StdRng::seed_from_u64(881);
+        let mut quadtree = QuadTree::new(boundary);
+        let mut sparse = SparseQuadTree::new(boundary);
+        for _ in 0..1000 {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            quadtree.insert(Point2D { x, y, data: 0 })?;
+            sparse.insert(Point2D { x, y, data: 0 })?;
+        }
+
+        let region = Rectangle::new(20.0, 30.0, 25.0, 10.0);
+        for (found, stats, expected, nodes) in [
+            {
+                let (found, stats) = quadtree.query_with_stats(region);
+                (found.len(), stats, quadtree.query(region).len(), quadtree.nodes().count())
+            },
+            {
+                let (found, stats) = sparse.query_with_stats(region);
+                (found.len(), stats, sparse.query(region).len(), sparse.nodes().count())
+            },
+        ] {
+            assert_eq!(found, expected);
+            assert_eq!(stats.matches, expected);
+            // points of nodes whose envelope lies inside the region aren't tested
+            assert!(stats.points_tested > 0 && stats.points_tested < 1000);
+            assert!(stats.nodes_visited > 1 && stats.nodes_visited < nodes);
+        }
+
+        // nodes inside the region match as a whole
+        let (found, stats) = sparse.query_with_stats(boundary);
+        assert_eq!(found.len(), 1000);
+        assert_eq!(
+            stats,
+            QueryStats {
+                nodes_visited: 1,
+                points_tested: 0,
+                matches: 1000
+            }
+        );
+
+        // the same number of points piled into a tiny cluster end up in one
+        // deep leaf, which has to be searched through for a few matches
+        let (_, spread) = sparse.query_with_stats(Rectangle::new(50.0, 50.0, 1.0, 1.0));
+        let mut clustered = SparseQuadTree::new(boundary);
+        for _ in 0..1000 {
+            let (x, y) = (50.0 + rng.gen_range(0.0..1e-9), 50.0 + rng.gen_range(0.0..1e-9));
+            clustered.insert(Point2D { x, y, data: 0 })?;
+        }
+        let (_, degraded) = clustered.query_with_stats(Rectangle::new(50.0, 50.0, 1e-10, 1e-10));
+        assert!(degraded.points_tested > 10 * spread.points_tested);
+        assert!(degraded.points_tested > 10 * degraded.matches);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! Tests shared by the enum `QuadTree` and the `SparseQuadTree`. Both split a
+//! leaf once it holds more than four points and move its points into the new
+//! children, so only leaves store points, and both let leaves of identical
+//! points grow instead. The sparse tree only creates the children receiving
+//! points, the enum tree always all four.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{NodeView, Point2D, QuadTree, Rectangle, SparseQuadTree, SpatialIndex};
+
+// the structure both trees expose, so each test is written once
+trait SubdividingTree: SpatialIndex<usize> {
+    fn node_views(&self) -> Vec<NodeView<'_, usize>>;
+    fn depth(&self) -> usize;
+    fn check_invariants(&self) -> Result<(), String>;
+}
+
+impl SubdividingTree for QuadTree<usize> {
+    fn node_views(&self) -> Vec<NodeView<'_, usize>> {
+        self.nodes().collect()
+    }
+
+    fn depth(&self) -> usize {
+        QuadTree::depth(self)
+    }
+
+    fn check_invariants(&self) -> Result<(), String> {
+        QuadTree::check_invariants(self)
+    }
+}
+
+impl SubdividingTree for SparseQuadTree<usize> {
+    fn node_views(&self) -> Vec<NodeView<'_, usize>> {
+        self.nodes().collect()
+    }
+
+    fn depth(&self) -> usize {
+        SparseQuadTree::depth(self)
+    }
+
+    fn check_invariants(&self) -> Result<(), String> {
+        SparseQuadTree::check_invariants(self)
+    }
+}
+
+const CAPACITY: usize = 4;
+
+fn subdivides_on_overflow<Q: SubdividingTree>() -> Result<(), Box<dyn std::error::Error>> {
+    let mut tree = Q::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+    let corners = [(10.0, 10.0), (60.0, 10.0), (10.0, 60.0), (60.0, 60.0)];
+    for (i, (x, y)) in corners.into_iter().enumerate() {
+        tree.insert(Point2D { x, y, data: i })?;
+    }
+    assert_eq!(tree.node_views().len(), 1);
+    assert_eq!(tree.depth(), 0);
+
+    tree.insert(Point2D { x: 70.0, y: 70.0, data: 4 })?;
+    tree.check_invariants()?;
+    assert_eq!(tree.depth(), 1);
+    assert_eq!(tree.count(), 5);
+    let nodes = tree.node_views();
+    let root = nodes[0];
+    assert!(!root.is_leaf());
+    assert!(root.points().is_empty());
+    let stored: usize = nodes.iter().map(|node| node.points().len()).sum();
+    assert_eq!(stored, 5);
+    for node in nodes.iter() {
+        assert!(node.points().iter().all(|p| node.boundary().contains(p.x, p.y)));
+    }
+    Ok(())
+}
+
+fn keeps_nodes_within_capacity<Q: SubdividingTree>() -> Result<(), Box<dyn std::error::Error>> {
+    let mut tree = Q::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+    let mut rng = StdRng::seed_from_u64(877);
+    for i in 0..2000 {
+        let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+        tree.insert(Point2D { x, y, data: i })?;
+    }
+    tree.check_invariants()?;
+    for node in tree.node_views() {
+        assert!(node.points().len() <= CAPACITY);
+        if !node.is_leaf() {
+            assert!(node.points().is_empty());
+        }
+    }
+    Ok(())
+}
+
+// piles of identical points can't be separated, they must neither subdivide
+// forever nor get lost. Neither tree subdivides such leaves at all.
+fn grows_leaves_of_identical_points<Q: SubdividingTree>() -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut tree = Q::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+    for i in 0..100 {
+        tree.insert(Point2D { x: 25.0, y: 25.0, data: i })?;
+    }
+    tree.check_invariants()?;
+    assert_eq!(tree.depth(), 0);
+    assert_eq!(tree.query(Rectangle::new(25.0, 25.0, 0.0, 0.0)).len(), 100);
+    Ok(())
+}
+
+// children of a boundary that doesn't halve exactly must still reach its
+// far edges, which points on those edges are routed to
+fn keeps_far_edge_points_in_their_nodes<Q: SubdividingTree>(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let side = 165.43751721195542;
+    let boundary = Rectangle::new(-852.5608418732809, -852.5608418732809, side, side);
+    let (east, south) = (boundary.x + boundary.width, boundary.y + boundary.height);
+    let mut tree = Q::new(boundary);
+    let mut rng = StdRng::seed_from_u64(811);
+    for i in 0..40 {
+        let (x, y) = match i % 4 {
+            0 => (east, south),
+            1 => (east, rng.gen_range(boundary.y..south)),
+            2 => (rng.gen_range(boundary.x..east), south),
+            _ => (rng.gen_range(boundary.x..east), rng.gen_range(boundary.y..south)),
+        };
+        tree.insert(Point2D { x, y, data: i })?;
+    }
+    tree.check_invariants()?;
+    assert_eq!(tree.query(Rectangle::new(east, south, 0.0, 0.0)).len(), 10);
+    assert_eq!(tree.query(boundary).len(), 40);
+    Ok(())
+}
+
+#[test]
+fn it_subdivides_both_trees_on_overflow() -> Result<(), Box<dyn std::error::Error>> {
+    subdivides_on_overflow::<QuadTree<usize>>()?;
+    subdivides_on_overflow::<SparseQuadTree<usize>>()
+}
+
+#[test]
+fn it_keeps_nodes_of_both_trees_within_capacity() -> Result<(), Box<dyn std::error::Error>> {
+    keeps_nodes_within_capacity::<QuadTree<usize>>()?;
+    keeps_nodes_within_capacity::<SparseQuadTree<usize>>()
+}
+
+#[test]
+fn it_grows_leaves_of_identical_points_in_both_trees() -> Result<(), Box<dyn std::error::Error>> {
+    grows_leaves_of_identical_points::<QuadTree<usize>>()?;
+    grows_leaves_of_identical_points::<SparseQuadTree<usize>>()
+}
+
+#[test]
+fn it_shapes_both_trees_alike_for_identical_data() -> Result<(), Box<dyn std::error::Error>> {
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    let mut quadtree = QuadTree::new(boundary);
+    let mut sparse = SparseQuadTree::new(boundary);
+    let mut rng = StdRng::seed_from_u64(877);
+    for i in 0..3000 {
+        // half uniform, half in a tight cluster
+        let (x, y) = if i % 2 == 0 {
+            (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0))
+        } else {
+            (rng.gen_range(30.0..31.0), rng.gen_range(70.0..71.0))
+        };
+        quadtree.insert(Point2D { x, y, data: i })?;
+        sparse.insert(Point2D { x, y, data: i })?;
+    }
+
+    // moving points down costs the sparse tree at most one level
+    assert!(sparse.depth() <= quadtree.depth() + 1);
+    assert!(quadtree.depth() <= sparse.depth() + 1);
+    for region in [
+        Rectangle::new(30.0, 70.0, 0.5, 0.5),
+        Rectangle::new(0.0, 0.0, 50.0, 50.0),
+        boundary,
+    ] {
+        let mut found: Vec<usize> = quadtree.query(region).iter().map(|p| p.data).collect();
+        let mut expected: Vec<usize> = sparse.query(region).iter().map(|p| p.data).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn it_keeps_far_edge_points_in_both_trees() -> Result<(), Box<dyn std::error::Error>> {
+    keeps_far_edge_points_in_their_nodes::<QuadTree<usize>>()?;
+    keeps_far_edge_points_in_their_nodes::<SparseQuadTree<usize>>()
+}
@@ -0,0 +1,71 @@
+use crate::error::InsertError;
+use crate::geometry::{Point2D, Rectangle};
+use crate::join::RangeJoin;
+use crate::spatial_index::SpatialIndex;
+
+/// A plain list of points scanned completely by every query. The baseline
+/// the trees are checked and benchmarked against.
+#[derive(Debug)]
+pub struct VecIndex<T> {
+    boundary: Rectangle,
+    points: Vec<Point2D<T>>,
+}
+
+impl<T> VecIndex<T> {
+    pub fn new(boundary: Rectangle) -> Self {
+        VecIndex {
+            boundary,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &Rectangle {
+        &self.boundary
+    }
+
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        let point = InsertError::check(&self.boundary, point)?;
+        self.points.push(point);
+        Ok(())
+    }
+
+    pub fn query(&self, boundary: impl Into<Rectangle>) -> Vec<&Point2D<T>> {
+        let boundary = boundary.into();
+        self.points
+            .iter()
+            .filter(|point| boundary.contains(point.x, point.y))
+            .collect()
+    }
+}
+
+impl<T> SpatialIndex<T> for VecIndex<T> {
+    fn new(boundary: Rectangle) -> Self {
+        VecIndex::new(boundary)
+    }
+
+    fn insert(&mut self, point: Point2D<T>) -> Result<(), InsertError<T>> {
+        self.insert(point)
+    }
+
+    fn query(&self, boundary: Rectangle) -> Vec<&Point2D<T>> {
+        self.query(boundary)
+    }
+
+    fn count(&self) -> usize {
+        self.count()
+    }
+
+    fn boundary(&self) -> &Rectangle {
+        self.boundary()
+    }
+}
+
+impl<T> RangeJoin<T> for VecIndex<T> {
+    fn visit_points(&self, f: &mut dyn FnMut(&Point2D<T>)) {
+        self.points.iter().for_each(f);
+    }
+}
@@ -0,0 +1,66 @@
+//! JavaScript bindings, enabled with the `wasm` feature. Points carry a
+//! numeric id and query results come back as one flat `Float64Array` of
+//! `x, y, id` triples, so no objects are allocated per point.
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::{Point2D, Rectangle};
+use crate::QuadTree;
+
+#[wasm_bindgen(js_name = QuadTree)]
+#[derive(Debug)]
+pub struct WasmQuadTree {
+    tree: QuadTree<u32>,
+}
+
+#[wasm_bindgen(js_class = QuadTree)]
+impl WasmQuadTree {
+    #[wasm_bindgen(constructor)]
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Result<WasmQuadTree, JsError> {
+        let boundary = Rectangle::try_new(x, y, width, height).map_err(JsError::new)?;
+        Ok(WasmQuadTree {
+            tree: QuadTree::new(boundary),
+        })
+    }
+
+    pub fn insert(&mut self, x: f64, y: f64, id: u32) -> Result<(), JsError> {
+        self.tree.insert(Point2D { x, y, data: id })?;
+        Ok(())
+    }
+
+    /// Flat `[x0, y0, id0, x1, y1, id1, ...]` of the points inside the region.
+    pub fn query(&self, x: f64, y: f64, width: f64, height: f64) -> Vec<f64> {
+        self.tree
+            .query(Rectangle::new(x, y, width, height))
+            .into_iter()
+            .flat_map(|point| [point.x, point.y, f64::from(point.data)])
+            .collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn count(&self) -> usize {
+        self.tree.count()
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // error paths create JS values and only work on wasm targets
+    #[test]
+    fn it_returns_flat_coordinates() {
+        let mut tree = WasmQuadTree::new(0.0, 0.0, 100.0, 100.0).ok().unwrap();
+        tree.insert(10.0, 20.0, 7).ok().unwrap();
+        tree.insert(80.0, 80.0, 8).ok().unwrap();
+        assert_eq!(tree.count(), 2);
+        assert_eq!(tree.query(0.0, 0.0, 50.0, 50.0), vec![10.0, 20.0, 7.0]);
+
+        tree.clear();
+        assert!(tree.query(0.0, 0.0, 100.0, 100.0).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use wkt::Wkt;
+
+use crate::geometry::{Point2D, Rectangle};
+use crate::{quadtree, quadtree_option};
+
+impl Rectangle {
+    /// Parses a WKT `POLYGON` literal. Queries only support rectangles, so
+    /// the polygon must be a closed, axis-aligned ring of 4 corners without
+    /// holes. Degenerate rings, too short or enclosing no area, are rejected.
+    pub fn from_wkt(text: &str) -> Result<Self, &'static str> {
+        let Wkt::Polygon(polygon) = Wkt::<f64>::from_str(text)? else {
+            return Err("Only POLYGON regions are supported");
+        };
+        let [ring] = polygon.rings() else {
+            return Err("Polygon must have exactly one ring");
+        };
+        let coords = ring.coords();
+        if coords.len() < 4 {
+            return Err("Polygon ring must have at least 4 positions");
+        }
+        let (first, last) = (&coords[0], &coords[coords.len() - 1]);
+        if first.x != last.x || first.y != last.y {
+            return Err("Polygon ring must be closed");
+        }
+        // shoelace formula, twice the signed area
+        let area: f64 = coords.windows(2).map(|e| e[0].x * e[1].y - e[1].x * e[0].y).sum();
+        if area == 0.0 {
+            return Err("Polygon ring must enclose an area");
+        }
+        if coords.len() != 5 {
+            return Err("Polygon must have 4 corners");
+        }
+        // every edge must move along exactly one axis
+        for edge in coords.windows(2) {
+            if (edge[0].x == edge[1].x) == (edge[0].y == edge[1].y) {
+                return Err("Polygon must be an axis-aligned rectangle");
+            }
+        }
+        Rectangle::from_corners(coords[0].x, coords[0].y, coords[2].x, coords[2].y)
+    }
+}
+
+impl<T> quadtree::QuadTree<T> {
+    /// `query` with the region given as a WKT `POLYGON`, see
+    /// `Rectangle::from_wkt`.
+    pub fn query_wkt(&self, text: &str) -> Result<Vec<&Point2D<T>>, &'static str> {
+        Ok(self.query(Rectangle::from_wkt(text)?))
+    }
+}
+
+impl<T> quadtree_option::QuadTree<T> {
+    /// `query` with the region given as a WKT `POLYGON`, see
+    /// `Rectangle::from_wkt`.
+    pub fn query_wkt(&self, text: &str) -> Result<Vec<&Point2D<T>>, &'static str> {
+        Ok(self.query(Rectangle::from_wkt(text)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{QuadTree, SparseQuadTree};
+
+    use super::*;
+
+    #[test]
+    fn it_parses_rectangular_polygons() -> Result<(), Box<dyn std::error::Error>> {
+        let rectangle = Rectangle::from_wkt("POLYGON((10 20, 40 20, 40 60, 10 60, 10 20))")?;
+        assert_eq!(rectangle, Rectangle::new(10.0, 20.0, 30.0, 40.0));
+        // winding and starting corner don't matter
+        let rectangle = Rectangle::from_wkt("polygon ((40 60, 40 20, 10 20, 10 60, 40 60))")?;
+        assert_eq!(rectangle, Rectangle::new(10.0, 20.0, 30.0, 40.0));
+
+        assert!(Rectangle::from_wkt("POINT(1 2)").is_err());
+        assert!(Rectangle::from_wkt("POLYGON((0 0, 10 0, 5 5, 0 10, 0 0))").is_err());
+        assert!(Rectangle::from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10))").is_err());
+        assert!(Rectangle::from_wkt(
+            "POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (1 1, 2 1, 2 2, 1 2, 1 1))"
+        )
+        .is_err());
+        assert!(Rectangle::from_wkt("POLYGON((0 0, 10 0").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_degenerate_rings() {
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((0 0, 10 0, 0 0))"),
+            Err("Polygon ring must have at least 4 positions")
+        );
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10, 0 1))"),
+            Err("Polygon ring must be closed")
+        );
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((0 0, 10 0, 10 10, 0 10))"),
+            Err("Polygon ring must be closed")
+        );
+        // back and forth along one line, every edge axis-aligned
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((0 0, 10 0, 10 10, 10 0, 0 0))"),
+            Err("Polygon ring must enclose an area")
+        );
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((0 0, 10 0, 20 0, 0 0))"),
+            Err("Polygon ring must enclose an area")
+        );
+        assert_eq!(
+            Rectangle::from_wkt("POLYGON((5 5, 5 5, 5 5, 5 5, 5 5))"),
+            Err("Polygon ring must enclose an area")
+        );
+        let sparse = SparseQuadTree::<u8>::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        assert!(sparse.query_wkt("POLYGON((0 0, 10 0, 10 10, 10 0, 0 0))").is_err());
+    }
+
+    #[test]
+    fn it_queries_with_wkt() -> Result<(), Box<dyn std::error::Error>> {
+        let mut quadtree = QuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        let mut sparse = SparseQuadTree::new(Rectangle::new(0.0, 0.0, 100.0, 100.0));
+        for i in 0..10 {
+            let point = Point2D {
+                x: 10.0 * i as f64,
+                y: 10.0 * i as f64,
+                data: i,
+            };
+            quadtree.insert(point)?;
+            sparse.insert(point)?;
+        }
+
+        let wkt = "POLYGON((15 15, 45 15, 45 45, 15 45, 15 15))";
+        let mut found: Vec<i32> = quadtree.query_wkt(wkt)?.iter().map(|p| p.data).collect();
+        found.sort();
+        assert_eq!(found, vec![2, 3, 4]);
+        assert_eq!(sparse.query_wkt(wkt)?.len(), 3);
+        assert!(quadtree.query_wkt("LINESTRING(0 0, 1 1)").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! Runs the same random operations against every index and checks that they
+//! all answer like the brute-force `VecIndex`.
+
+use quadtree::testutil::{random_operations, run_operations, Operation, Outcome};
+use quadtree::{
+    KdTree, LinearQuadTree, PersistentQuadTree, Point2D, QuadTree, Rectangle, SparseQuadTree,
+    SpatialIndex, VecIndex,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn assert_equivalent<I: SpatialIndex<usize>>(
+    name: &str,
+    boundary: Rectangle,
+    operations: &[Operation],
+    expected: &[Outcome],
+) {
+    let mut index = I::new(boundary);
+    let outcomes = run_operations(&mut index, operations);
+    for (i, (outcome, expected)) in outcomes.iter().zip(expected).enumerate() {
+        assert_eq!(outcome, expected, "{} diverges at {:?}", name, operations[i]);
+    }
+}
+
+fn check_all(boundary: Rectangle, operations: &[Operation]) {
+    let expected = run_operations(&mut VecIndex::new(boundary), operations);
+    assert_equivalent::<QuadTree<usize>>("QuadTree", boundary, operations, &expected);
+    assert_equivalent::<SparseQuadTree<usize>>("SparseQuadTree", boundary, operations, &expected);
+    assert_equivalent::<LinearQuadTree<usize>>("LinearQuadTree", boundary, operations, &expected);
+    assert_equivalent::<PersistentQuadTree<usize>>(
+        "PersistentQuadTree",
+        boundary,
+        operations,
+        &expected,
+    );
+    assert_equivalent::<KdTree<usize>>("KdTree", boundary, operations, &expected);
+}
+
+#[test]
+fn it_answers_random_operations_like_the_model() {
+    let boundary = Rectangle::new(0.0, 0.0, 100.0, 100.0);
+    for seed in 0..20 {
+        let mut rng = StdRng::seed_from_u64(844 + seed);
+        check_all(boundary, &random_operations(&mut rng, &boundary, 1000));
+    }
+}
+
+#[test]
+fn it_agrees_on_unusual_boundaries() {
+    let mut rng = StdRng::seed_from_u64(844);
+    for boundary in [
+        Rectangle::new(-50.0, -50.0, 100.0, 100.0),
+        Rectangle::new(0.0, 0.0, 1e-6, 1e-6),
+        Rectangle::new(1e9, 1e9, 1e3, 10.0),
+        Rectangle::new(0.0, 0.0, 100.0, 0.0),
+    ] {
+        check_all(boundary, &random_operations(&mut rng, &boundary, 500));
+    }
+}
+
+// boundaries whose sizes don't halve exactly, so the computed edges of
+// nodes may round off from where points on them are routed
+#[test]
+fn it_agrees_on_oddly_sized_boundaries() {
+    let mut rng = StdRng::seed_from_u64(844);
+    for _ in 0..40 {
+        let boundary = Rectangle::new(
+            rng.gen_range(-1e3..1e3),
+            rng.gen_range(-1e3..1e3),
+            rng.gen_range(1e-3..1e3),
+            rng.gen_range(1e-3..1e3),
+        );
+        let (east, south) = (boundary.x + boundary.width, boundary.y + boundary.height);
+        let mut operations = random_operations(&mut rng, &boundary, 300);
+        for i in 0..20 {
+            let (x, y) = match i % 3 {
+                0 => (east, south),
+                1 => (east, rng.gen_range(boundary.y..south)),
+                _ => (rng.gen_range(boundary.x..east), south),
+            };
+            operations.push(Operation::Insert(Point2D { x, y, data: 300 + i }));
+        }
+        operations.push(Operation::Query(Rectangle::new(east, south, 0.0, 0.0)));
+        operations.push(Operation::Query(Rectangle::new(east, boundary.y, 0.0, boundary.height)));
+        operations.push(Operation::Query(Rectangle::new(boundary.x, south, boundary.width, 0.0)));
+        operations.push(Operation::Count);
+        check_all(boundary, &operations);
+    }
+}